@@ -0,0 +1,319 @@
+use std::io::{ BufRead, Error, Write };
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use crate::cli::{ BulkFormat, BulkOptions };
+use crate::message::{ rcode_name, Question };
+use crate::name::{ relative, Name };
+use crate::json::json_string;
+use crate::rdata::RecordType;
+use crate::transport::{ self, Client, RetryPolicy };
+
+// one line of input: a name and optionally a type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub line: usize,
+    pub name: String,
+    pub qtype: Result<RecordType, String>, // a bad type is reported in the output, not fatal
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub name: String,
+    pub qtype: String,
+    pub status: String, // the rcode, or ERROR when no answer came back
+    pub answers: Vec<String>,
+    pub elapsed: Duration,
+    pub server: Option<SocketAddr>,
+    pub error: Option<String>,
+}
+
+// `name [type]` per line; blank lines and # comments are skipped
+pub fn parse_job(line: usize, text: &str, default: RecordType) -> Option<Job> {
+    let text = text.split('#').next().unwrap_or("");
+    let mut words = text.split_whitespace();
+    let name = words.next()?.to_string();
+    let qtype = match words.next() {
+        Some(word) => word.parse::<RecordType>(),
+        None => Ok(default),
+    };
+    Some(Job { line, name, qtype })
+}
+
+// spaces queries out evenly; one is shared by all the workers
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    // zero means no limit
+    pub fn new(qps: u32) -> RateLimiter {
+        let interval = if qps == 0 { Duration::ZERO } else { Duration::from_secs(1) / qps };
+        RateLimiter { interval, next: Mutex::new(Instant::now()) }
+    }
+
+    // blocks until the caller's slot comes up
+    pub fn wait(&self) {
+        if self.interval.is_zero() {
+            return;
+        }
+        let slot = match self.next.lock() {
+            Ok(mut next) => {
+                let slot = (*next).max(Instant::now());
+                *next = slot + self.interval;
+                slot
+            }
+            Err(_) => return,
+        };
+        thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+// resolves every job from input with up to options.in_flight queries outstanding, writing
+// one line per name as they finish; returns how many names were resolved and how many failed
+pub fn run(
+    options: &BulkOptions,
+    servers: &[SocketAddr],
+    input: impl BufRead + Send + 'static,
+    output: &mut dyn Write
+) -> Result<(usize, usize), Error> {
+    let workers = options.in_flight.max(1);
+    // the bounded queue keeps a huge input file from being read into memory up front
+    let (job_tx, job_rx) = mpsc::sync_channel::<Job>(workers);
+    let (result_tx, result_rx) = mpsc::channel::<Outcome>();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let limiter = Arc::new(RateLimiter::new(options.qps));
+
+    let mut policy = RetryPolicy::new(options.timeout, options.retries);
+    policy.backoff = 1;
+    for _ in 0..workers {
+        let job_rx = job_rx.clone();
+        let result_tx = result_tx.clone();
+        let limiter = limiter.clone();
+        let servers = servers.to_vec();
+        let tcp = options.tcp;
+        thread::spawn(move || {
+            let mut client = Client::new(policy);
+            client.force_tcp = tcp;
+            loop {
+                let job = match job_rx.lock().map(|rx| rx.recv()) {
+                    Ok(Ok(job)) => job,
+                    _ => return,
+                };
+                let outcome = resolve(&job, &mut client, &servers, &limiter);
+                if result_tx.send(outcome).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    drop(result_tx);
+
+    let default = options.qtype;
+    let reader = thread::spawn(move || -> Result<(), Error> {
+        for (i, line) in input.lines().enumerate() {
+            if let Some(job) = parse_job(i + 1, &line?, default) {
+                if job_tx.send(job).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    });
+
+    if options.format == BulkFormat::Csv {
+        writeln!(output, "name,type,status,answers,elapsed_ms,server,error")?;
+    }
+    let (mut total, mut failed) = (0, 0);
+    for outcome in result_rx {
+        total += 1;
+        if outcome.error.is_some() {
+            failed += 1;
+        }
+        let line = match options.format {
+            BulkFormat::Csv => format_csv(&outcome),
+            BulkFormat::JsonLines => format_json(&outcome),
+        };
+        writeln!(output, "{}", line)?;
+    }
+    output.flush()?;
+    match reader.join() {
+        Ok(result) => result?,
+        Err(_) => return Err(Error::other("input reader panicked")),
+    }
+    Ok((total, failed))
+}
+
+fn resolve(job: &Job, client: &mut Client, servers: &[SocketAddr], limiter: &RateLimiter) -> Outcome {
+    let mut outcome = Outcome {
+        name: job.name.clone(),
+        qtype: job.qtype.as_ref().map(|t| t.to_string()).unwrap_or_default(),
+        status: "ERROR".to_string(),
+        answers: vec![],
+        elapsed: Duration::ZERO,
+        server: None,
+        error: None,
+    };
+    let qtype = match &job.qtype {
+        Ok(qtype) => *qtype,
+        Err(e) => {
+            outcome.error = Some(format!("line {}: {}", job.line, e));
+            return outcome;
+        }
+    };
+    // names in an inventory are taken as fully qualified, there is no search list here
+    let qname = match Name::parse(relative(&job.name)) {
+        Ok(name) => name.to_string(),
+        Err(e) => {
+            outcome.error = Some(format!("line {}: {}", job.line, e));
+            return outcome;
+        }
+    };
+
+    limiter.wait();
+    let started = Instant::now();
+    let question = Question { qname, qtype, qclass: 1 };
+    let result = transport::build_query(question, true, None).and_then(|query| {
+        client.exchange(&query, servers, &mut vec![])
+    });
+    outcome.elapsed = started.elapsed();
+    match result {
+        Ok((response, server)) => {
+            outcome.status = rcode_name(response.rcode());
+            outcome.answers = response.answers
+                .iter()
+                .filter(|r| r.rtype == qtype)
+                .map(|r| r.rdata.to_string())
+                .collect();
+            outcome.server = Some(server);
+        }
+        Err(e) => outcome.error = Some(e.to_string()),
+    }
+    outcome
+}
+
+// answers are joined with spaces inside one field, quoted as RFC 4180 wants
+pub fn format_csv(outcome: &Outcome) -> String {
+    let fields = [
+        outcome.name.clone(),
+        outcome.qtype.clone(),
+        outcome.status.clone(),
+        outcome.answers.join(" "),
+        outcome.elapsed.as_millis().to_string(),
+        outcome.server.map(|s| s.to_string()).unwrap_or_default(),
+        outcome.error.clone().unwrap_or_default(),
+    ];
+    let quoted: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    quoted.join(",")
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn format_json(outcome: &Outcome) -> String {
+    let answers: Vec<String> = outcome.answers.iter().map(|a| json_string(a)).collect();
+    let optional = |value: Option<String>| value.map(|v| json_string(&v)).unwrap_or("null".to_string());
+    format!(
+        "{{\"name\":{},\"type\":{},\"status\":{},\"answers\":[{}],\"elapsed_ms\":{},\"server\":{},\"error\":{}}}",
+        json_string(&outcome.name),
+        json_string(&outcome.qtype),
+        json_string(&outcome.status),
+        answers.join(","),
+        outcome.elapsed.as_millis(),
+        optional(outcome.server.map(|s| s.to_string())),
+        optional(outcome.error.clone())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::zone::{ Authority, Zone };
+
+    fn outcome() -> Outcome {
+        Outcome {
+            name: "txt.example.com".to_string(),
+            qtype: "TXT".to_string(),
+            status: "NOERROR".to_string(),
+            answers: vec!["\"a,b\"".to_string(), "\"c\"".to_string()],
+            elapsed: Duration::from_millis(12),
+            server: Some("192.0.2.53:53".parse().unwrap()),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_jobs() {
+        assert_eq!(parse_job(3, "www.example.com", RecordType::A).unwrap().qtype, Ok(RecordType::A));
+        assert_eq!(parse_job(3, "  mail MX # relay", RecordType::A).unwrap().qtype, Ok(RecordType::MX));
+        assert!(parse_job(3, "www BOGUS", RecordType::A).unwrap().qtype.is_err());
+        assert!(parse_job(3, "# comment", RecordType::A).is_none());
+        assert!(parse_job(3, "   ", RecordType::A).is_none());
+    }
+
+    #[test]
+    fn test_output_formats() {
+        assert_eq!(
+            format_csv(&outcome()),
+            "txt.example.com,TXT,NOERROR,\"\"\"a,b\"\" \"\"c\"\"\",12,192.0.2.53:53,"
+        );
+        assert_eq!(
+            format_json(&outcome()),
+            "{\"name\":\"txt.example.com\",\"type\":\"TXT\",\"status\":\"NOERROR\",\
+\"answers\":[\"\\\"a,b\\\"\",\"\\\"c\\\"\"],\"elapsed_ms\":12,\"server\":\"192.0.2.53:53\",\"error\":null}"
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new(100);
+        let started = Instant::now();
+        for _ in 0..11 {
+            limiter.wait();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_resolves_a_batch() {
+        let zone = Zone::parse(include_str!("../tests/zones/example.com.zone"), "").unwrap();
+        let server = Server::bind("127.0.0.1:0".parse().unwrap(), Arc::new(Authority { zones: vec![zone] })).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let options = BulkOptions {
+            input: None,
+            qtype: RecordType::A,
+            servers: vec![],
+            port: 53,
+            in_flight: 4,
+            qps: 0,
+            format: BulkFormat::JsonLines,
+            timeout: Duration::from_secs(1),
+            retries: 0,
+            tcp: false,
+        };
+        let input = "www.example.com\nexample.com MX\n# skipped\nnope.example.com\nwww.example.com AAAA\nbad..name\n";
+        let mut output = vec![];
+        let (total, failed) = run(&options, &[addr], input.as_bytes(), &mut output).unwrap();
+        assert_eq!((total, failed), (5, 1));
+
+        let mut lines: Vec<String> = String::from_utf8(output).unwrap().lines().map(String::from).collect();
+        lines.sort();
+        assert!(lines[0].contains("\"bad..name\"") && lines[0].contains("\"status\":\"ERROR\""));
+        assert!(lines[1].contains("\"answers\":[\"10 mail.example.com.\"]"));
+        assert!(lines[2].contains("\"NXDOMAIN\""));
+        assert!(lines[3].contains("\"answers\":[\"192.0.2.80\"]"));
+        assert!(lines[4].contains("\"answers\":[\"2001:db8::80\"]"));
+    }
+}
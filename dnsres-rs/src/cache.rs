@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ Cursor, Error };
+use std::path::Path;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::encoding::{ from_hex, to_hex };
+use crate::message::{ malformed, Header, Message, Question, ResourceRecord };
+use crate::rdata::{ RData, RecordType };
+
+const MAX_CNAME_CHAIN: usize = 8;
+const NXDOMAIN: u16 = 3;
+// how often expired entries nobody asked for again are swept out, in seconds
+const SWEEP_INTERVAL: u64 = 60;
+
+type Key = (String, RecordType, u16); // lowercased name, type, class
+
+// in-process answer cache honouring record TTLs, with RFC 2308 negative caching
+#[derive(Default)]
+pub struct Cache {
+    entries: HashMap<Key, Entry>,
+    next_sweep: u64, // unix seconds
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    rcode: u16, // NOERROR for data and NODATA, NXDOMAIN for names that don't exist
+    records: Vec<ResourceRecord>, // the RRset, or the SOA proving a negative answer
+    negative: bool,
+    stored_at: u64, // unix seconds
+    ttl: u32,
+}
+
+impl Entry {
+    fn remaining(&self, now: u64) -> Option<u32> {
+        let age = now.saturating_sub(self.stored_at);
+        if age >= self.ttl as u64 {
+            return None;
+        }
+        Some(self.ttl - (age as u32))
+    }
+
+    // records with TTLs counted down to what is left of their lifetime
+    fn aged_records(&self, now: u64) -> Vec<ResourceRecord> {
+        let remaining = self.remaining(now).unwrap_or(0);
+        self.records
+            .iter()
+            .map(|r| ResourceRecord { ttl: r.ttl.min(remaining), ..r.clone() })
+            .collect()
+    }
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    pub fn insert(&mut self, question: &Question, response: &Message) {
+        self.insert_at(question, response, unix_now());
+    }
+
+    pub fn answer(&mut self, question: &Question) -> Option<Message> {
+        self.answer_at(question, unix_now())
+    }
+
+    // stores the RRsets on the CNAME chain from the question, or a negative entry for where it ends.
+    // Anything else in the answer section is left out, since the server had no say over those names.
+    pub fn insert_at(&mut self, question: &Question, response: &Message, now: u64) {
+        if now >= self.next_sweep {
+            self.entries.retain(|_, entry| entry.remaining(now).is_some());
+            self.next_sweep = now + SWEEP_INTERVAL;
+        }
+        let rcode = response.header.rcode() as u16;
+        if rcode != 0 && rcode != NXDOMAIN {
+            return; // SERVFAIL and friends are never cached
+        }
+        if response.header.is_truncated() {
+            return;
+        }
+
+        // group answers into RRsets, an RRset lives as long as its shortest TTL
+        let chain = cname_chain(&question.qname, &response.answers);
+        let mut rrsets: HashMap<Key, Vec<ResourceRecord>> = HashMap::new();
+        for record in response.answers.iter() {
+            let on_chain = chain.iter().any(|name| name.eq_ignore_ascii_case(&record.name));
+            let wanted = record.rtype == question.qtype || record.rtype == RecordType::CNAME;
+            if !on_chain || !wanted || record.class != question.qclass {
+                continue;
+            }
+            let key = (record.name.to_ascii_lowercase(), record.rtype, record.class);
+            rrsets.entry(key).or_default().push(record.clone());
+        }
+        for (key, records) in rrsets {
+            let ttl = records.iter().map(|r| r.ttl).min().unwrap_or(0);
+            if ttl == 0 {
+                continue;
+            }
+            self.entries.insert(key, Entry { rcode: 0, records, negative: false, stored_at: now, ttl });
+        }
+
+        // NXDOMAIN, or NODATA when nothing at the end of any CNAME chain answers the type
+        let final_name = chain.last().cloned().unwrap_or_default();
+        let answered = response.answers
+            .iter()
+            .any(|r| r.rtype == question.qtype && r.name.eq_ignore_ascii_case(&final_name));
+        if rcode == 0 && (answered || question.qtype == RecordType::ANY) {
+            return;
+        }
+        let soa = response.authorities.iter().find(|r| r.rtype == RecordType::SOA);
+        let soa = match soa {
+            Some(soa) => soa.clone(),
+            None => {
+                return; // without an SOA there is no negative TTL (RFC 2308 5)
+            }
+        };
+        let ttl = match soa.rdata {
+            RData::SOA { minimum, .. } => soa.ttl.min(minimum),
+            _ => 0,
+        };
+        if ttl == 0 {
+            return;
+        }
+        let key = (final_name.to_ascii_lowercase(), negative_type(rcode, question.qtype), question.qclass);
+        self.entries.insert(key, Entry { rcode, records: vec![soa], negative: true, stored_at: now, ttl });
+    }
+
+    // builds a response from cached data, following cached CNAMEs; None on a miss
+    pub fn answer_at(&mut self, question: &Question, now: u64) -> Option<Message> {
+        let mut answers = vec![];
+        let mut name = question.qname.to_ascii_lowercase();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let nxdomain = (name.clone(), negative_type(NXDOMAIN, question.qtype), question.qclass);
+            let key = (name.clone(), question.qtype, question.qclass);
+            if let Some(entry) = self.live(&nxdomain, now).or_else(|| self.live(&key, now)) {
+                let (answer, authority) = if entry.negative {
+                    (vec![], entry.aged_records(now))
+                } else {
+                    (entry.aged_records(now), vec![])
+                };
+                answers.extend(answer);
+                return Some(synthesize(question, entry.rcode, answers, authority));
+            }
+
+            let cname_key = (name.clone(), RecordType::CNAME, question.qclass);
+            let entry = self.live(&cname_key, now)?;
+            let records = entry.aged_records(now);
+            name = match records.first().map(|r| &r.rdata) {
+                Some(RData::CNAME(target)) => target.to_ascii_lowercase(),
+                _ => {
+                    return None;
+                }
+            };
+            answers.extend(records);
+        }
+        None
+    }
+
+    // the entry under key unless it has expired, in which case it goes
+    fn live(&mut self, key: &Key, now: u64) -> Option<Entry> {
+        let entry = self.entries.get(key)?;
+        if entry.remaining(now).is_none() {
+            self.entries.remove(key);
+            return None;
+        }
+        Some(entry.clone())
+    }
+
+    // one line per entry: stored_at ttl rcode negative qname qtype qclass hex(records)
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut out = String::new();
+        for ((name, rtype, class), entry) in self.entries.iter() {
+            let mut wire = vec![];
+            for record in entry.records.iter() {
+                wire.extend(record.to_bytes()?);
+            }
+            out.push_str(
+                &format!(
+                    "{} {} {} {} {} {} {} {}\n",
+                    entry.stored_at,
+                    entry.ttl,
+                    entry.rcode,
+                    entry.negative as u8,
+                    if name.is_empty() { "." } else { name },
+                    rtype.to_u16(),
+                    class,
+                    to_hex(&wire)
+                )
+            );
+        }
+        fs::write(path, out)
+    }
+
+    // a missing file is an empty cache; expired entries are dropped on the way in
+    pub fn load(path: &Path) -> Result<Cache, Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Cache::new());
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+
+        let now = unix_now();
+        let mut cache = Cache::new();
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            if fields.len() != 8 {
+                return Err(malformed("bad cache file line"));
+            }
+            let number = |i: usize| fields[i].parse::<u64>().map_err(|_| malformed("bad number in cache file"));
+            let wire = from_hex(fields[7]).ok_or_else(|| malformed("bad hex in cache file"))?;
+            let mut cursor = Cursor::new(wire.as_slice());
+            let mut records = vec![];
+            while (cursor.position() as usize) < wire.len() {
+                records.push(ResourceRecord::from_bytes(&mut cursor)?);
+            }
+
+            let entry = Entry {
+                stored_at: number(0)?,
+                ttl: number(1)? as u32,
+                rcode: number(2)? as u16,
+                negative: number(3)? != 0,
+                records,
+            };
+            if entry.remaining(now).is_none() {
+                continue;
+            }
+            let name = if fields[4] == "." { String::new() } else { fields[4].to_string() };
+            let key = (name, RecordType::from(number(5)? as u16), number(6)? as u16);
+            cache.entries.insert(key, entry);
+        }
+        Ok(cache)
+    }
+}
+
+pub fn synthesize(
+    question: &Question,
+    rcode: u16,
+    answers: Vec<ResourceRecord>,
+    authorities: Vec<ResourceRecord>
+) -> Message {
+    Message {
+        header: Header {
+            id: 0,
+            flags: 0x8180 | rcode, // qr rd ra
+            qdcount: 1,
+            ancount: answers.len() as u16,
+            nscount: authorities.len() as u16,
+            arcount: 0,
+        },
+        questions: vec![question.clone()],
+        answers,
+        authorities,
+        additionals: vec![],
+    }
+}
+
+// a name that doesn't exist has no types at all (RFC 2308 5), so NXDOMAIN is kept under ANY for
+// every type; ANY itself never gets a NODATA entry to clash with it
+fn negative_type(rcode: u16, qtype: RecordType) -> RecordType {
+    if rcode == NXDOMAIN { RecordType::ANY } else { qtype }
+}
+
+// the name a CNAME chain in the answer section ends at
+pub fn follow_cnames(name: &str, answers: &[ResourceRecord]) -> String {
+    cname_chain(name, answers).pop().unwrap_or_default()
+}
+
+// name and every CNAME target after it, in order
+fn cname_chain(name: &str, answers: &[ResourceRecord]) -> Vec<String> {
+    let mut chain = vec![name.to_string()];
+    for _ in 0..MAX_CNAME_CHAIN {
+        let current = &chain[chain.len() - 1];
+        let next = answers.iter().find_map(|r| match &r.rdata {
+            RData::CNAME(target) if r.name.eq_ignore_ascii_case(current) => Some(target.clone()),
+            _ => None,
+        });
+        match next {
+            Some(target) => chain.push(target),
+            None => break,
+        }
+    }
+    chain
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(name: &str, qtype: RecordType) -> Question {
+        Question { qname: name.to_string(), qtype, qclass: 1 }
+    }
+
+    fn record(name: &str, ttl: u32, rdata: RData) -> ResourceRecord {
+        let rtype = match rdata {
+            RData::A(_) => RecordType::A,
+            RData::CNAME(_) => RecordType::CNAME,
+            RData::SOA { .. } => RecordType::SOA,
+            _ => unreachable!(),
+        };
+        ResourceRecord { name: name.to_string(), rtype, class: 1, ttl, rdata }
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> ResourceRecord {
+        record("example.com", ttl, RData::SOA {
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum,
+        })
+    }
+
+    fn response(rcode: u16, answers: Vec<ResourceRecord>, authorities: Vec<ResourceRecord>) -> Message {
+        synthesize(&question("ignored", RecordType::A), rcode, answers, authorities)
+    }
+
+    #[test]
+    fn test_positive_entry_counts_down_and_expires() {
+        let mut cache = Cache::new();
+        let q = question("www.example.com", RecordType::A);
+        let answer = record("www.example.com", 300, RData::A([192, 0, 2, 1].into()));
+        cache.insert_at(&q, &response(0, vec![answer], vec![]), 1000);
+
+        let hit = cache.answer_at(&question("WWW.example.com", RecordType::A), 1100).unwrap();
+        assert_eq!(hit.answers[0].ttl, 200);
+        assert!(cache.answer_at(&q, 1300).is_none());
+        assert_eq!(cache.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_cname_chain_is_reassembled() {
+        let mut cache = Cache::new();
+        let q = question("www.example.com", RecordType::A);
+        let answers = vec![
+            record("www.example.com", 600, RData::CNAME("web.example.com".to_string())),
+            record("web.example.com", 60, RData::A([192, 0, 2, 2].into())),
+        ];
+        cache.insert_at(&q, &response(0, answers, vec![]), 0);
+
+        let hit = cache.answer_at(&q, 10).unwrap();
+        assert_eq!(hit.answers.len(), 2);
+        assert_eq!(hit.answers[1].ttl, 50);
+    }
+
+    #[test]
+    fn test_negative_ttl_uses_soa_minimum() {
+        let mut cache = Cache::new();
+        let q = question("missing.example.com", RecordType::A);
+        cache.insert_at(&q, &response(3, vec![], vec![soa(3600, 60)]), 0);
+
+        let hit = cache.answer_at(&q, 30).unwrap();
+        assert_eq!(hit.header.rcode(), 3);
+        assert_eq!(hit.authorities[0].ttl, 30);
+        // a name that doesn't exist has no other types either
+        assert_eq!(cache.answer_at(&question("missing.example.com", RecordType::MX), 30).unwrap().header.rcode(), 3);
+        assert!(cache.answer_at(&q, 61).is_none());
+
+        // NODATA without an SOA is not cached
+        let q = question("www.example.com", RecordType::AAAA);
+        cache.insert_at(&q, &response(0, vec![], vec![]), 0);
+        assert!(cache.answer_at(&q, 1).is_none());
+    }
+
+    #[test]
+    fn test_only_the_question_is_cached() {
+        let mut cache = Cache::new();
+        let q = question("www.example.com", RecordType::A);
+        let answers = vec![
+            record("www.example.com", 600, RData::CNAME("web.example.com".to_string())),
+            record("web.example.com", 600, RData::A([192, 0, 2, 2].into())),
+            record("bank.example.net", 600, RData::A([192, 0, 2, 66].into())),
+            record("www.example.com", 600, RData::SOA {
+                mname: "ns1".to_string(),
+                rname: "hostmaster".to_string(),
+                serial: 1,
+                refresh: 1,
+                retry: 1,
+                expire: 1,
+                minimum: 1,
+            }),
+        ];
+        cache.insert_at(&q, &response(0, answers, vec![]), 0);
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.answer_at(&question("bank.example.net", RecordType::A), 1).is_none());
+        assert_eq!(cache.answer_at(&q, 1).unwrap().answers.len(), 2);
+
+        // expired entries go when they are next looked at, or on the next sweep
+        cache.insert_at(&question("other.example.com", RecordType::A), &response(2, vec![], vec![]), 700);
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_servfail_not_cached() {
+        let mut cache = Cache::new();
+        let q = question("www.example.com", RecordType::A);
+        cache.insert_at(&q, &response(2, vec![], vec![soa(300, 300)]), 0);
+        assert_eq!(cache.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("dnsres-cache-{}", std::process::id()));
+        let mut cache = Cache::new();
+        let q = question("www.example.com", RecordType::A);
+        let now = unix_now();
+        let answer = record("www.example.com", 300, RData::A([192, 0, 2, 1].into()));
+        cache.insert_at(&q, &response(0, vec![answer], vec![]), now);
+        cache.insert_at(&question("gone.example.com", RecordType::A), &response(3, vec![], vec![soa(60, 60)]), now);
+        cache.save(&path).unwrap();
+
+        let mut loaded = Cache::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.entries.len(), 2);
+        let hit = loaded.answer_at(&q, now).unwrap();
+        assert_eq!(hit.answers[0].rdata, RData::A([192, 0, 2, 1].into()));
+    }
+}
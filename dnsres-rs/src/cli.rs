@@ -0,0 +1,939 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::doh::{ Endpoint, Method, DOH_PATH };
+use crate::edns::{ self, Edns, EdnsOption };
+use crate::hosts::HOSTS;
+use crate::message::parse_class;
+use crate::name::{ is_absolute, Name };
+use crate::rdata::RecordType;
+use crate::resolvconf::{ ResolvConf, DNS_PORT, RESOLV_CONF };
+use crate::reverse::reverse_name;
+use crate::tls::{ DOH_PORT, DOT_PORT };
+
+pub const USAGE: &str = "Usage: dnsres <name> [type] [@server[:port]]... [-x <address>] [-c <class>] [-p <port>] \
+[+[no]recurse] [+tcp] [+tls] [+https[=<path>]] [+https-get[=<path>]] [+tls-ca=<file>] [+tls-hostname=<name>] [+0x20] [+iterative] [+trace] [+time=<seconds>] [+retry=<n>] [+backoff=<factor>] [+[no]edns] [+bufsize=<n>] [+dnssec] [+nsid] \
+[+subnet=<addr>[/<prefix>]] [+padding] [+validate] [+anchor=<file>] [+cache=<file>] [+[no]search] [+[no]hosts] \
+[+resolvconf=<file>] [+hosts=<file>] [-k <keyfile>] [+short] [+json] [-v]";
+
+pub const SERVE_USAGE: &str = "Usage: dnsres serve [-l <address:port>] [@upstream[:port]]... [-z <zonefile>]... \
+[+time=<seconds>] [+retry=<n>] [+cache=<file>] [+resolvconf=<file>]";
+
+pub const BULK_USAGE: &str = "Usage: dnsres bulk [-f <file>] [-t <type>] [@server[:port]]... [-p <port>] [-j <in-flight>] \
+[+qps=<n>] [+csv|+jsonl] [+tcp] [+time=<seconds>] [+retry=<n>] [+resolvconf=<file>]";
+
+pub const UPDATE_USAGE: &str = "Usage: dnsres update [-f <file>] [-z <zone>] [-k <keyfile>] [@server[:port]]... [-p <port>] \
+[+tcp] [+time=<seconds>] [+retry=<n>] [+resolvconf=<file>]";
+
+pub const XFR_USAGE: &str = "Usage: dnsres xfr <zone> [@server[:port]]... [-p <port>] [-k <keyfile>] [-o <zonefile>] \
+[+ixfr[=<serial>]] [+time=<seconds>] [+resolvconf=<file>]";
+
+pub const MDNS_USAGE: &str = "Usage: dnsres mdns <name> [type] [-6 [-I <interface>]] [+browse] [+window=<milliseconds>] [+short]";
+
+pub const DECODE_USAGE: &str = "Usage: dnsres decode [<file>|-] [+hex|+raw] [+tcp]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Short, // rdata only, like dig +short
+    Normal,
+    Verbose, // also dump the query we sent
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub name: String,
+    pub qtype: RecordType,
+    pub qclass: u16,
+    pub servers: Vec<(String, Option<u16>)>, // tried in order, port falls back to `port`
+    pub search: bool, // expand short names with the resolv.conf search list
+    pub port: u16,
+    pub recursion_desired: bool,
+    pub tcp: bool, // skip udp and always query over tcp
+    pub tls: bool, // DNS over TLS (RFC 7858), or over HTTPS when https is set
+    pub https: Option<Endpoint>, // DNS over HTTPS (RFC 8484)
+    pub tls_ca: Option<PathBuf>, // CA bundle to check server certificates against, the system one if unset
+    pub tls_hostname: Option<String>, // name the certificates must be issued to, each server as given if unset
+    pub randomize_case: bool, // 0x20 encoding of the qname
+    pub iterative: bool, // resolve from the root hints ourselves
+    pub trace: bool, // show every query made on the way down from the root
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: u32,
+    pub edns: Option<Edns>, // OPT record to attach, None sends a plain RFC 1035 query
+    pub cache_file: Option<PathBuf>, // answers are cached here between runs
+    pub validate: bool, // check DNSSEC signatures up to the trust anchor
+    pub trust_anchor: Option<PathBuf>, // DS or DNSKEY records, the root KSKs if unset
+    pub hosts_file: Option<PathBuf>, // answers A/AAAA/PTR from here before asking, None skips it
+    pub key_file: Option<PathBuf>, // TSIG key to sign the query and check the reply with
+    pub verbosity: Verbosity,
+    pub json: bool, // print the response as RFC 8427 JSON
+}
+
+impl Options {
+    pub fn new(name: &str, server: &str, port: u16) -> Options {
+        Options {
+            name: name.to_string(),
+            qtype: RecordType::A,
+            qclass: 1, // internet class
+            servers: vec![(server.to_string(), None)],
+            search: true,
+            port,
+            recursion_desired: true,
+            tcp: false,
+            tls: false,
+            https: None,
+            tls_ca: None,
+            tls_hostname: None,
+            randomize_case: false,
+            iterative: false,
+            trace: false,
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            backoff: 2,
+            edns: Some(Edns::new(edns::DEFAULT_UDP_SIZE)),
+            cache_file: None,
+            validate: false,
+            trust_anchor: None,
+            hosts_file: Some(PathBuf::from(HOSTS)),
+            key_file: None,
+            verbosity: Verbosity::Normal,
+            json: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub listen: SocketAddr,
+    pub upstreams: Vec<(String, Option<u16>)>,
+    pub port: u16,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub cache_file: Option<PathBuf>,
+    pub zones: Vec<PathBuf>, // answer authoritatively from these instead of forwarding
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkFormat {
+    Csv,
+    JsonLines,
+}
+
+#[derive(Debug, Clone)]
+pub struct BulkOptions {
+    pub input: Option<PathBuf>, // one name and optional type per line, stdin if unset
+    pub qtype: RecordType, // for lines that don't name a type
+    pub servers: Vec<(String, Option<u16>)>,
+    pub port: u16,
+    pub in_flight: usize, // queries outstanding at once
+    pub qps: u32, // cap on names sent per second, 0 for none
+    pub format: BulkFormat,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub tcp: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateOptions {
+    pub script: Option<PathBuf>, // nsupdate-style commands, stdin if unset
+    pub zone: Option<String>, // for scripts without a zone line
+    pub servers: Vec<(String, Option<u16>)>,
+    pub port: u16,
+    pub key_file: Option<PathBuf>,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub tcp: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct XfrOptions {
+    pub zone: String,
+    pub servers: Vec<(String, Option<u16>)>, // tried in order until one hands over the zone
+    pub port: u16,
+    pub key_file: Option<PathBuf>,
+    pub output: Option<PathBuf>, // zone file to write, and for +ixfr to start from; stdout if unset
+    pub ixfr: bool,
+    pub serial: Option<u32>, // where the IXFR starts, the serial in the output file if unset
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct MdnsOptions {
+    pub name: String,
+    pub qtype: RecordType,
+    pub browse: bool, // the name is a DNS-SD service type whose instances we want
+    pub ipv6: bool, // ask ff02::fb rather than 224.0.0.251
+    pub interface: Option<String>, // index or name of the interface ff02::fb is asked on
+    pub window: Duration, // how long to keep listening for responders after each query
+    pub verbosity: Verbosity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Hex,
+    Raw,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    pub input: Option<PathBuf>, // stdin if unset
+    pub format: Option<DumpFormat>, // guessed from the contents if unset
+    pub tcp: bool, // the message starts with the length it has over tcp
+}
+
+// the resolv.conf to take defaults from; found before the other arguments since those defaults depend on it
+pub fn resolv_conf_path(args: &[String]) -> PathBuf {
+    let path = args.iter().rev().find_map(|a| a.strip_prefix("+resolvconf="));
+    PathBuf::from(path.unwrap_or(RESOLV_CONF))
+}
+
+fn system_servers(conf: &ResolvConf) -> Vec<(String, Option<u16>)> {
+    conf.nameservers.iter().map(|ip| (ip.to_string(), None)).collect()
+}
+
+// parses the arguments following `serve`, forwarding to the system nameservers by default
+pub fn parse_serve_args(args: &[String], conf: &ResolvConf) -> Result<ServeOptions, String> {
+    let mut options = ServeOptions {
+        listen: SocketAddr::from(([127, 0, 0, 1], DNS_PORT)),
+        upstreams: vec![],
+        port: DNS_PORT,
+        timeout: Duration::from_secs(2),
+        retries: 1,
+        cache_file: None,
+        zones: vec![],
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-l" => {
+                let value = iter.next().ok_or("-l needs an address")?;
+                options.listen = value.parse().map_err(|_| format!("invalid listen address: {}", value))?;
+            }
+            "-z" => {
+                let value = iter.next().ok_or("-z needs a zone file")?;
+                options.zones.push(PathBuf::from(value));
+            }
+            _ if arg.starts_with("+time=") => {
+                let secs: u64 = arg[6..].parse().map_err(|_| format!("invalid timeout: {}", arg))?;
+                options.timeout = Duration::from_secs(secs.max(1));
+            }
+            _ if arg.starts_with("+retry=") => {
+                options.retries = arg[7..].parse().map_err(|_| format!("invalid retry count: {}", arg))?;
+            }
+            _ if arg.starts_with("+cache=") => {
+                options.cache_file = Some(PathBuf::from(&arg[7..]));
+            }
+            _ if arg.starts_with("+resolvconf=") => {} // already read by resolv_conf_path
+            _ if arg.starts_with('@') => {
+                options.upstreams.push(parse_server(&arg[1..])?);
+            }
+            _ => {
+                return Err(format!("unknown option: {}", arg));
+            }
+        }
+    }
+
+    if options.upstreams.is_empty() {
+        options.upstreams = system_servers(conf);
+    }
+    Ok(options)
+}
+
+// parses the arguments following `bulk`
+pub fn parse_bulk_args(args: &[String], conf: &ResolvConf) -> Result<BulkOptions, String> {
+    let mut options = BulkOptions {
+        input: None,
+        qtype: RecordType::A,
+        servers: system_servers(conf),
+        port: DNS_PORT,
+        in_flight: 32,
+        qps: 0,
+        format: BulkFormat::Csv,
+        timeout: conf.timeout,
+        retries: conf.attempts.saturating_sub(1),
+        tcp: false,
+    };
+    let mut servers = vec![];
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-f" => {
+                let value = iter.next().ok_or("-f needs a file")?;
+                if value != "-" {
+                    options.input = Some(PathBuf::from(value));
+                }
+            }
+            "-t" => {
+                let value = iter.next().ok_or("-t needs a type")?;
+                options.qtype = value.parse()?;
+            }
+            "-p" => {
+                let value = iter.next().ok_or("-p needs a port")?;
+                options.port = value.parse().map_err(|_| format!("invalid port: {}", value))?;
+            }
+            "-j" => {
+                let value = iter.next().ok_or("-j needs a count")?;
+                options.in_flight = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid in-flight limit: {}", value)),
+                };
+            }
+            "+csv" => options.format = BulkFormat::Csv,
+            "+jsonl" => options.format = BulkFormat::JsonLines,
+            "+tcp" | "+vc" => options.tcp = true,
+            _ if arg.starts_with("+qps=") => {
+                options.qps = arg[5..].parse().map_err(|_| format!("invalid rate: {}", arg))?;
+            }
+            _ if arg.starts_with("+time=") => {
+                let secs: u64 = arg[6..].parse().map_err(|_| format!("invalid timeout: {}", arg))?;
+                options.timeout = Duration::from_secs(secs.max(1));
+            }
+            _ if arg.starts_with("+retry=") => {
+                options.retries = arg[7..].parse().map_err(|_| format!("invalid retry count: {}", arg))?;
+            }
+            _ if arg.starts_with("+resolvconf=") => {} // already read by resolv_conf_path
+            _ if arg.starts_with('@') => {
+                servers.push(parse_server(&arg[1..])?);
+            }
+            _ => {
+                return Err(format!("unknown option: {}", arg));
+            }
+        }
+    }
+
+    if !servers.is_empty() {
+        options.servers = servers;
+    }
+    Ok(options)
+}
+
+// parses the arguments following `update`
+pub fn parse_update_args(args: &[String], conf: &ResolvConf) -> Result<UpdateOptions, String> {
+    let mut options = UpdateOptions {
+        script: None,
+        zone: None,
+        servers: system_servers(conf),
+        port: DNS_PORT,
+        key_file: None,
+        timeout: conf.timeout,
+        retries: conf.attempts.saturating_sub(1),
+        tcp: false,
+    };
+    let mut servers = vec![];
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-f" => {
+                let value = iter.next().ok_or("-f needs a file")?;
+                if value != "-" {
+                    options.script = Some(PathBuf::from(value));
+                }
+            }
+            "-z" => {
+                let value = iter.next().ok_or("-z needs a zone")?;
+                options.zone = Some(parse_name(value)?);
+            }
+            "-k" => {
+                let value = iter.next().ok_or("-k needs a key file")?;
+                options.key_file = Some(PathBuf::from(value));
+            }
+            "-p" => {
+                let value = iter.next().ok_or("-p needs a port")?;
+                options.port = value.parse().map_err(|_| format!("invalid port: {}", value))?;
+            }
+            "+tcp" | "+vc" => options.tcp = true,
+            _ if arg.starts_with("+time=") => {
+                let secs: u64 = arg[6..].parse().map_err(|_| format!("invalid timeout: {}", arg))?;
+                options.timeout = Duration::from_secs(secs.max(1));
+            }
+            _ if arg.starts_with("+retry=") => {
+                options.retries = arg[7..].parse().map_err(|_| format!("invalid retry count: {}", arg))?;
+            }
+            _ if arg.starts_with("+resolvconf=") => {} // already read by resolv_conf_path
+            _ if arg.starts_with('@') => {
+                servers.push(parse_server(&arg[1..])?);
+            }
+            _ => {
+                return Err(format!("unknown option: {}", arg));
+            }
+        }
+    }
+
+    if !servers.is_empty() {
+        options.servers = servers;
+    }
+    Ok(options)
+}
+
+// parses the arguments following `xfr`
+pub fn parse_xfr_args(args: &[String], conf: &ResolvConf) -> Result<XfrOptions, String> {
+    let mut options = XfrOptions {
+        zone: String::new(),
+        servers: system_servers(conf),
+        port: DNS_PORT,
+        key_file: None,
+        output: None,
+        ixfr: false,
+        serial: None,
+        timeout: conf.timeout,
+    };
+    let mut zone = None;
+    let mut servers = vec![];
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-p" => {
+                let value = iter.next().ok_or("-p needs a port")?;
+                options.port = value.parse().map_err(|_| format!("invalid port: {}", value))?;
+            }
+            "-k" => {
+                let value = iter.next().ok_or("-k needs a key file")?;
+                options.key_file = Some(PathBuf::from(value));
+            }
+            "-o" => {
+                let value = iter.next().ok_or("-o needs a file")?;
+                options.output = Some(PathBuf::from(value));
+            }
+            "+ixfr" => options.ixfr = true,
+            _ if arg.starts_with("+ixfr=") => {
+                options.ixfr = true;
+                options.serial = Some(arg[6..].parse().map_err(|_| format!("invalid serial: {}", arg))?);
+            }
+            _ if arg.starts_with("+time=") => {
+                let secs: u64 = arg[6..].parse().map_err(|_| format!("invalid timeout: {}", arg))?;
+                options.timeout = Duration::from_secs(secs.max(1));
+            }
+            _ if arg.starts_with("+resolvconf=") => {} // already read by resolv_conf_path
+            _ if arg.starts_with('@') => {
+                servers.push(parse_server(&arg[1..])?);
+            }
+            _ if arg.starts_with('-') || arg.starts_with('+') => {
+                return Err(format!("unknown option: {}", arg));
+            }
+            _ if zone.is_none() => zone = Some(Name::parse(arg)?),
+            _ => {
+                return Err(format!("unexpected argument: {}", arg));
+            }
+        }
+    }
+
+    options.zone = zone.ok_or("missing zone to transfer")?.to_string().to_ascii_lowercase();
+    if !servers.is_empty() {
+        options.servers = servers;
+    }
+    // an IXFR needs to know what we already have
+    if options.ixfr && options.serial.is_none() && options.output.is_none() {
+        return Err("+ixfr needs a serial or a zone file to start from".to_string());
+    }
+    Ok(options)
+}
+
+// parses the arguments following `mdns`
+pub fn parse_mdns_args(args: &[String]) -> Result<MdnsOptions, String> {
+    let mut options = MdnsOptions {
+        name: String::new(),
+        qtype: RecordType::A,
+        browse: false,
+        ipv6: false,
+        interface: None,
+        window: Duration::from_secs(1),
+        verbosity: Verbosity::Normal,
+    };
+    let mut name = None;
+    let mut qtype = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-6" => options.ipv6 = true,
+            "-I" => {
+                let value = iter.next().ok_or("-I needs an interface")?;
+                options.interface = Some(value.clone());
+            }
+            "+browse" => options.browse = true,
+            "+short" => options.verbosity = Verbosity::Short,
+            _ if arg.starts_with("+window=") => {
+                let millis: u64 = arg[8..].parse().map_err(|_| format!("invalid window: {}", arg))?;
+                options.window = Duration::from_millis(millis.max(1));
+            }
+            _ if arg.starts_with('-') || arg.starts_with('+') => {
+                return Err(format!("unknown option: {}", arg));
+            }
+            _ => {
+                if name.is_none() {
+                    name = Some(Name::parse(arg)?);
+                } else if qtype.is_none() {
+                    qtype = Some(arg.parse::<RecordType>()?);
+                } else {
+                    return Err(format!("unexpected argument: {}", arg));
+                }
+            }
+        }
+    }
+
+    // a bare host name or service type (_http._tcp) is in .local
+    let name = name.ok_or("missing name to look up")?.to_string();
+    let bare = !name.contains('.') || name.ends_with("._tcp") || name.ends_with("._udp");
+    options.name = if bare { format!("{}.local", name) } else { name };
+    if let Some(qtype) = qtype {
+        options.qtype = qtype;
+    }
+    if options.browse && qtype.is_some_and(|t| t != RecordType::PTR) {
+        return Err("+browse always asks for PTR records".to_string());
+    }
+    // there's no portable way to pick the outgoing interface for 224.0.0.251
+    if options.interface.is_some() && !options.ipv6 {
+        return Err("-I only applies to queries over IPv6 (-6)".to_string());
+    }
+    Ok(options)
+}
+
+// parses the arguments following `decode`
+pub fn parse_decode_args(args: &[String]) -> Result<DecodeOptions, String> {
+    let mut options = DecodeOptions { input: None, format: None, tcp: false };
+    for arg in args.iter() {
+        match arg.as_str() {
+            "+hex" => options.format = Some(DumpFormat::Hex),
+            "+raw" => options.format = Some(DumpFormat::Raw),
+            "+tcp" => options.tcp = true,
+            "-" if options.input.is_none() => {}
+            _ if arg.starts_with('-') || arg.starts_with('+') => {
+                return Err(format!("unknown option: {}", arg));
+            }
+            _ if options.input.is_none() => options.input = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+// parses dig-style arguments (without the program name), with defaults from resolv.conf
+pub fn parse_args(args: &[String], conf: &ResolvConf) -> Result<Options, String> {
+    let mut options = Options::new("", "", DNS_PORT);
+    options.servers = system_servers(conf);
+    options.timeout = conf.timeout;
+    options.retries = conf.attempts.saturating_sub(1);
+    let mut name = None;
+    let mut qtype = None;
+    let mut port = None;
+    let mut servers = vec![];
+    let mut use_edns = true;
+    let mut edns = Edns::new(edns::DEFAULT_UDP_SIZE);
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-c" => {
+                let value = iter.next().ok_or("-c needs a class")?;
+                options.qclass = parse_class(value)?;
+            }
+            "-p" => {
+                let value = iter.next().ok_or("-p needs a port")?;
+                port = Some(value.parse().map_err(|_| format!("invalid port: {}", value))?);
+            }
+            "-x" => {
+                let value = iter.next().ok_or("-x needs an address")?;
+                let address = value.parse().map_err(|_| format!("invalid address: {}", value))?;
+                if name.is_some() {
+                    return Err("-x cannot be combined with a name".to_string());
+                }
+                name = Some(reverse_name(address));
+                qtype = Some(RecordType::PTR);
+                options.search = false;
+            }
+            "-k" => {
+                let value = iter.next().ok_or("-k needs a key file")?;
+                options.key_file = Some(PathBuf::from(value));
+            }
+            "-v" => options.verbosity = Verbosity::Verbose,
+            "+short" => options.verbosity = Verbosity::Short,
+            "+json" => options.json = true,
+            "+recurse" | "+rec" => options.recursion_desired = true,
+            "+norecurse" | "+norec" => options.recursion_desired = false,
+            "+tcp" | "+vc" => options.tcp = true,
+            "+tls" => options.tls = true,
+            _ if arg.starts_with("+https") => {
+                let (method, path) = match arg.split_once('=') {
+                    Some((flag, path)) => (flag, path),
+                    None => (arg.as_str(), DOH_PATH),
+                };
+                let method = match method {
+                    "+https" | "+https-post" => Method::Post,
+                    "+https-get" => Method::Get,
+                    _ => return Err(format!("unknown option: {}", arg)),
+                };
+                if !path.starts_with('/') {
+                    return Err(format!("invalid DoH path: {}", path));
+                }
+                options.tls = true;
+                options.https = Some(Endpoint { path: path.to_string(), method });
+            }
+            _ if arg.starts_with("+tls-ca=") => {
+                options.tls_ca = Some(PathBuf::from(&arg[8..]));
+            }
+            _ if arg.starts_with("+tls-hostname=") => {
+                options.tls_hostname = Some(Name::parse(&arg[14..])?.to_string());
+            }
+            "+0x20" => options.randomize_case = true,
+            "+iterative" => options.iterative = true,
+            "+trace" => options.trace = true,
+            "+search" => options.search = true,
+            "+nosearch" => options.search = false,
+            "+hosts" => options.hosts_file = Some(PathBuf::from(HOSTS)),
+            "+nohosts" => options.hosts_file = None,
+            _ if arg.starts_with("+hosts=") => {
+                options.hosts_file = Some(PathBuf::from(&arg[7..]));
+            }
+            _ if arg.starts_with("+resolvconf=") => {} // already read by resolv_conf_path
+            _ if arg.starts_with("+time=") => {
+                let secs: u64 = arg[6..].parse().map_err(|_| format!("invalid timeout: {}", arg))?;
+                options.timeout = Duration::from_secs(secs.max(1));
+            }
+            _ if arg.starts_with("+retry=") => {
+                options.retries = arg[7..].parse().map_err(|_| format!("invalid retry count: {}", arg))?;
+            }
+            "+edns" => use_edns = true,
+            "+noedns" => use_edns = false,
+            "+dnssec" => edns.dnssec_ok = true,
+            "+validate" => options.validate = true,
+            _ if arg.starts_with("+anchor=") => {
+                options.trust_anchor = Some(PathBuf::from(&arg[8..]));
+            }
+            "+nsid" => edns.options.push(EdnsOption::Nsid(vec![])),
+            "+padding" => edns.options.push(EdnsOption::Padding(0)),
+            _ if arg.starts_with("+bufsize=") => {
+                edns.udp_size = arg[9..].parse().map_err(|_| format!("invalid buffer size: {}", arg))?;
+            }
+            _ if arg.starts_with("+subnet=") => {
+                edns.options.push(edns::parse_subnet(&arg[8..])?);
+            }
+            _ if arg.starts_with("+cache=") => {
+                options.cache_file = Some(PathBuf::from(&arg[7..]));
+            }
+            _ if arg.starts_with("+backoff=") => {
+                options.backoff = arg[9..].parse().map_err(|_| format!("invalid backoff: {}", arg))?;
+            }
+            _ if arg.starts_with('@') => {
+                servers.push(parse_server(&arg[1..])?);
+            }
+            _ if arg.starts_with('-') || arg.starts_with('+') => {
+                return Err(format!("unknown option: {}", arg));
+            }
+            _ => {
+                // the first bare word is the name, a second one the type
+                if name.is_none() {
+                    name = Some(parse_name(arg)?);
+                } else if qtype.is_none() {
+                    qtype = Some(arg.parse::<RecordType>()?);
+                } else {
+                    return Err(format!("unexpected argument: {}", arg));
+                }
+            }
+        }
+    }
+
+    options.name = name.ok_or("missing name to look up")?;
+    if !servers.is_empty() {
+        options.servers = servers;
+    }
+    // the MAC covers the qname exactly as it was signed
+    if options.key_file.is_some() && options.randomize_case {
+        return Err("+0x20 cannot be combined with -k".to_string());
+    }
+    // a trace is the record of an iterative resolution
+    if options.trace {
+        options.iterative = true;
+    }
+    // authoritative servers are only reachable in the clear
+    if options.tls && options.iterative {
+        return Err("+tls and +https cannot be combined with +iterative".to_string());
+    }
+    options.port = match (port, &options.https) {
+        (Some(port), _) => port,
+        (None, Some(_)) => DOH_PORT,
+        (None, None) if options.tls => DOT_PORT,
+        (None, None) => DNS_PORT,
+    };
+    // validating needs the signatures, so it implies +edns +dnssec
+    if options.validate {
+        use_edns = true;
+        edns.dnssec_ok = true;
+    }
+    options.edns = if use_edns { Some(edns) } else { None };
+    if let Some(qtype) = qtype {
+        if matches!(qtype, RecordType::AXFR | RecordType::IXFR) {
+            return Err("zone transfers are done with dnsres xfr".to_string());
+        }
+        options.qtype = qtype;
+    }
+    Ok(options)
+}
+
+// checks the name and converts it to ASCII, keeping a trailing dot since that turns off the search list
+fn parse_name(text: &str) -> Result<String, String> {
+    let name = Name::parse(text)?;
+    if name.is_root() || is_absolute(text) {
+        Ok(format!("{}.", name))
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+// accepts host, host:port, [v6] and [v6]:port
+fn parse_server(value: &str) -> Result<(String, Option<u16>), String> {
+    let invalid = || format!("invalid server: {}", value);
+
+    if let Some(rest) = value.strip_prefix('[') {
+        let (host, tail) = rest.split_once(']').ok_or_else(invalid)?;
+        let port = match tail.strip_prefix(':') {
+            Some(port) => Some(port.parse().map_err(|_| invalid())?),
+            None if tail.is_empty() => None,
+            None => return Err(invalid()),
+        };
+        return Ok((host.to_string(), port));
+    }
+
+    // a bare IPv6 address has more than one colon
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => {
+            Ok((host.to_string(), Some(port.parse().map_err(|_| invalid())?)))
+        }
+        _ => Ok((value.to_string(), None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn conf() -> ResolvConf {
+        ResolvConf::parse("nameserver 8.8.8.8\noptions timeout:3 attempts:4\n")
+    }
+
+    #[test]
+    fn test_parse_full_command_line() {
+        let options = parse_args(
+            &args("example.com MX @1.1.1.1:5353 @9.9.9.9 -c CH +norec +tcp +0x20 +time=2 +retry=4 +backoff=3 +short"),
+            &conf()
+        ).unwrap();
+        assert_eq!(options.name, "example.com");
+        assert_eq!(options.qtype, RecordType::MX);
+        assert_eq!(options.servers, vec![
+            ("1.1.1.1".to_string(), Some(5353)),
+            ("9.9.9.9".to_string(), None),
+        ]);
+        assert_eq!(options.port, 53);
+        assert_eq!(options.qclass, 3);
+        assert!(!options.recursion_desired);
+        assert!(options.tcp);
+        assert!(options.randomize_case);
+        assert_eq!(options.timeout, Duration::from_secs(2));
+        assert_eq!(options.retries, 4);
+        assert_eq!(options.backoff, 3);
+        assert_eq!(options.verbosity, Verbosity::Short);
+        assert!(!options.json);
+        assert!(parse_args(&args("example.com +json"), &conf()).unwrap().json);
+        let options = parse_args(&args("example.com +trace"), &conf()).unwrap();
+        assert!(options.trace && options.iterative);
+    }
+
+    #[test]
+    fn test_defaults_and_errors() {
+        let options = parse_args(&args("example.com"), &conf()).unwrap();
+        assert_eq!(options.qtype, RecordType::A);
+        assert_eq!(options.servers, vec![("8.8.8.8".to_string(), None)]);
+        assert!(options.recursion_desired);
+        assert!(options.search);
+        assert_eq!(options.timeout, Duration::from_secs(3));
+        assert_eq!(options.retries, 3);
+        assert_eq!(options.hosts_file, Some(PathBuf::from(HOSTS)));
+        assert_eq!(options.edns, Some(Edns::new(edns::DEFAULT_UDP_SIZE)));
+        assert!(parse_args(&args("+short"), &conf()).is_err());
+        assert!(parse_args(&args("example.com BOGUS"), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_edns_options() {
+        let options = parse_args(&args("example.com +bufsize=4096 +dnssec +nsid"), &conf()).unwrap();
+        let edns = options.edns.unwrap();
+        assert_eq!(edns.udp_size, 4096);
+        assert!(edns.dnssec_ok);
+        assert_eq!(edns.options, vec![EdnsOption::Nsid(vec![])]);
+
+        let options = parse_args(&args("example.com +nsid +noedns"), &conf()).unwrap();
+        assert!(options.edns.is_none());
+
+        let options = parse_args(&args("example.com +noedns +validate +anchor=root.key"), &conf()).unwrap();
+        assert!(options.validate);
+        assert!(options.edns.unwrap().dnssec_ok);
+        assert_eq!(options.trust_anchor, Some(PathBuf::from("root.key")));
+    }
+
+    #[test]
+    fn test_reverse_lookup() {
+        let options = parse_args(&args("-x 192.0.2.10 +short"), &conf()).unwrap();
+        assert_eq!(options.name, "10.2.0.192.in-addr.arpa");
+        assert_eq!(options.qtype, RecordType::PTR);
+        assert!(!options.search);
+        assert!(parse_args(&args("-x not-an-ip"), &conf()).is_err());
+        assert!(parse_args(&args("example.com -x ::1"), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_serve_options() {
+        let options = parse_serve_args(&args("-l 0.0.0.0:53 @1.1.1.1 @[::1]:5300 +cache=/tmp/c"), &conf()).unwrap();
+        assert_eq!(options.listen, "0.0.0.0:53".parse().unwrap());
+        assert_eq!(options.upstreams.len(), 2);
+        assert_eq!(options.cache_file, Some(PathBuf::from("/tmp/c")));
+        assert!(options.zones.is_empty());
+
+        let options = parse_serve_args(&args("-z a.zone -z b.zone"), &conf()).unwrap();
+        assert_eq!(options.zones, vec![PathBuf::from("a.zone"), PathBuf::from("b.zone")]);
+        assert!(parse_serve_args(&args("-z"), &conf()).is_err());
+
+        let options = parse_serve_args(&[], &conf()).unwrap();
+        assert_eq!(options.upstreams, vec![("8.8.8.8".to_string(), None)]);
+        assert!(parse_serve_args(&args("example.com"), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_bulk_options() {
+        let options = parse_bulk_args(&args("-f names.txt -t AAAA -j 100 +qps=500 +jsonl @192.0.2.1:5300"), &conf()).unwrap();
+        assert_eq!(options.input, Some(PathBuf::from("names.txt")));
+        assert_eq!(options.qtype, RecordType::AAAA);
+        assert_eq!(options.in_flight, 100);
+        assert_eq!(options.qps, 500);
+        assert_eq!(options.format, BulkFormat::JsonLines);
+        assert_eq!(options.servers, vec![("192.0.2.1".to_string(), Some(5300))]);
+
+        let options = parse_bulk_args(&args("-f -"), &conf()).unwrap();
+        assert!(options.input.is_none());
+        assert_eq!(options.format, BulkFormat::Csv);
+        assert_eq!(options.servers, vec![("8.8.8.8".to_string(), None)]);
+        assert!(parse_bulk_args(&args("-j 0"), &conf()).is_err());
+        assert!(parse_bulk_args(&args("www.example.com"), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_transports() {
+        let options = parse_args(&args("example.com @9.9.9.9 +tls +tls-ca=ca.pem"), &conf()).unwrap();
+        assert!(options.tls && options.https.is_none());
+        assert_eq!(options.port, 853);
+        assert_eq!(options.tls_ca, Some(PathBuf::from("ca.pem")));
+
+        let options = parse_args(&args("example.com @dns.google +https-get +tls-hostname=dns.google"), &conf()).unwrap();
+        assert!(options.tls);
+        assert_eq!(options.https, Some(Endpoint { path: "/dns-query".to_string(), method: Method::Get }));
+        assert_eq!(options.port, 443);
+        assert_eq!(options.tls_hostname.as_deref(), Some("dns.google"));
+
+        let options = parse_args(&args("example.com +https=/resolve -p 8443"), &conf()).unwrap();
+        assert_eq!(options.https.unwrap().method, Method::Post);
+        assert_eq!(options.port, 8443);
+        assert_eq!(parse_args(&args("example.com"), &conf()).unwrap().port, 53);
+        assert!(parse_args(&args("example.com +https=resolve"), &conf()).is_err());
+        assert!(parse_args(&args("example.com +https-put"), &conf()).is_err());
+        assert!(parse_args(&args("example.com +tls +trace"), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_update_options() {
+        let options = parse_update_args(&args("-f changes.txt -z example.com -k ops.key @192.0.2.53 +tcp"), &conf()).unwrap();
+        assert_eq!(options.script, Some(PathBuf::from("changes.txt")));
+        assert_eq!(options.zone.as_deref(), Some("example.com"));
+        assert_eq!(options.key_file, Some(PathBuf::from("ops.key")));
+        assert_eq!(options.servers, vec![("192.0.2.53".to_string(), None)]);
+        assert!(options.tcp);
+        assert!(parse_update_args(&args("-z a..b"), &conf()).is_err());
+        assert!(parse_update_args(&args("www.example.com"), &conf()).is_err());
+
+        let options = parse_args(&args("example.com SOA -k ops.key"), &conf()).unwrap();
+        assert_eq!(options.key_file, Some(PathBuf::from("ops.key")));
+        assert!(parse_args(&args("example.com -k ops.key +0x20"), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_xfr_options() {
+        let options = parse_xfr_args(&args("Example.COM. @192.0.2.53 -k ops.key -o example.com.zone +ixfr"), &conf()).unwrap();
+        assert_eq!(options.zone, "example.com");
+        assert_eq!(options.servers, vec![("192.0.2.53".to_string(), None)]);
+        assert_eq!(options.output, Some(PathBuf::from("example.com.zone")));
+        assert!(options.ixfr);
+        assert_eq!(parse_xfr_args(&args("example.com +ixfr=2024010101"), &conf()).unwrap().serial, Some(2024010101));
+        assert!(!parse_xfr_args(&args("example.com"), &conf()).unwrap().ixfr);
+        assert!(parse_xfr_args(&args("example.com +ixfr"), &conf()).is_err());
+        assert!(parse_xfr_args(&args("+ixfr=1"), &conf()).is_err());
+        assert!(parse_args(&args("example.com AXFR"), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_mdns_options() {
+        let options = parse_mdns_args(&args("printer AAAA -6 +window=250")).unwrap();
+        assert_eq!(options.name, "printer.local");
+        assert_eq!(options.qtype, RecordType::AAAA);
+        assert!(options.ipv6);
+        assert_eq!(options.window, Duration::from_millis(250));
+        assert_eq!(options.interface, None);
+        let options = parse_mdns_args(&args("-6 -I eth0 printer")).unwrap();
+        assert_eq!(options.interface.as_deref(), Some("eth0"));
+        assert!(parse_mdns_args(&args("printer -I 2")).is_err());
+        assert!(parse_mdns_args(&args("printer -6 -I")).is_err());
+
+        let options = parse_mdns_args(&args("_http._tcp +browse")).unwrap();
+        assert_eq!(options.name, "_http._tcp.local");
+        assert_eq!(parse_mdns_args(&args("_ipp._tcp.lab.example.")).unwrap().name, "_ipp._tcp.lab.example");
+        assert!(options.browse);
+        assert!(parse_mdns_args(&args("_http._tcp.local +browse TXT")).is_err());
+        assert!(parse_mdns_args(&args("+window=soon printer")).is_err());
+        assert!(parse_mdns_args(&[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_decode_args() {
+        let options = parse_decode_args(&args("capture.hex +tcp")).unwrap();
+        assert_eq!(options.input, Some(PathBuf::from("capture.hex")));
+        assert_eq!(options.format, None);
+        assert!(options.tcp);
+        let options = parse_decode_args(&args("- +raw")).unwrap();
+        assert!(options.input.is_none());
+        assert_eq!(options.format, Some(DumpFormat::Raw));
+        assert!(parse_decode_args(&args("a.bin b.bin")).is_err());
+        assert!(parse_decode_args(&args("+binary")).is_err());
+    }
+
+    #[test]
+    fn test_system_files() {
+        let line = args("www +resolvconf=a.conf +nosearch +hosts=fixture.hosts +resolvconf=b.conf");
+        assert_eq!(resolv_conf_path(&line), PathBuf::from("b.conf"));
+        assert_eq!(resolv_conf_path(&args("www")), PathBuf::from(RESOLV_CONF));
+        let options = parse_args(&line, &conf()).unwrap();
+        assert!(!options.search);
+        assert_eq!(options.hosts_file, Some(PathBuf::from("fixture.hosts")));
+        assert!(parse_args(&args("www +nohosts"), &conf()).unwrap().hosts_file.is_none());
+    }
+
+    #[test]
+    fn test_names_are_checked() {
+        assert_eq!(parse_args(&args("Bücher.example."), &conf()).unwrap().name, "xn--bcher-kva.example.");
+        assert_eq!(parse_args(&args("."), &conf()).unwrap().name, ".");
+        assert!(parse_args(&args("a..b"), &conf()).is_err());
+        assert!(parse_args(&args(&"x".repeat(64)), &conf()).is_err());
+    }
+
+    #[test]
+    fn test_parse_server() {
+        assert_eq!(parse_server("[::1]:5300").unwrap(), ("::1".to_string(), Some(5300)));
+        assert_eq!(parse_server("2001:db8::53").unwrap(), ("2001:db8::53".to_string(), None));
+        assert_eq!(parse_server("ns1:53").unwrap(), ("ns1".to_string(), Some(53)));
+        assert!(parse_server("[::1]x").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use byteorder::{ BigEndian, ReadBytesExt };
+use std::io::Cursor;
+
+use crate::encoding::from_hex;
+use crate::message::{ class_name, opcode_name, rcode_name, read_name, Header, CLASS_ANY, CLASS_NONE };
+use crate::rdata::{ RData, RecordType };
+
+// one line of a breakdown, at an offset into the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Section(usize, String),
+    Field(usize, &'static str, String),
+    Problem(usize, String),
+}
+
+// the bytes of a hex dump: a bare hex stream, Wireshark's "Copy as Hex Dump" with its offset and
+// ASCII columns, or our own -v output. Lines starting with # are comments.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // an offset column is followed by single bytes, a hex stream is one long run
+        let mut tokens = line.split_whitespace();
+        let first = tokens.next().unwrap_or_default();
+        let offset = first.ends_with(':') || (first.len() >= 4 && tokens.next().is_some_and(|t| t.len() == 2));
+        let line = if offset { line[first.len()..].trim_start() } else { line };
+        // the ASCII column sits behind a wider gap than the one between the two halves of a row
+        let line = line.split("   ").next().unwrap_or_default();
+        let digits: String = line.split_whitespace().collect();
+        bytes.extend(from_hex(&digits).ok_or_else(|| format!("line {}: not a hex dump: {}", number + 1, line))?);
+    }
+    if bytes.is_empty() {
+        return Err("no bytes in hex dump".to_string());
+    }
+    Ok(bytes)
+}
+
+// a field-by-field breakdown of a message, going as far as the bytes allow. Anything that
+// doesn't add up is flagged as a problem rather than ending the walk, unless the rest can't be
+// found. When framed the message carries the two-byte length it has over tcp.
+pub fn dissect(bytes: &[u8], framed: bool) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut base = 0;
+    if framed {
+        if bytes.len() < 2 {
+            entries.push(Entry::Problem(0, "no room for the tcp length".to_string()));
+            return entries;
+        }
+        let length = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+        entries.push(Entry::Field(0, "length", length.to_string()));
+        if length != bytes.len() - 2 {
+            entries.push(Entry::Problem(0, format!("length says {} bytes but {} follow", length, bytes.len() - 2)));
+        }
+        base = 2;
+    }
+    let mut walk = Walk { bytes: &bytes[base..], base, entries };
+    walk.message();
+    walk.entries
+}
+
+struct Walk<'a> {
+    bytes: &'a [u8], // the message alone, which compression pointers are relative to
+    base: usize, // where the message starts in the input
+    entries: Vec<Entry>,
+}
+
+impl Walk<'_> {
+    fn field(&mut self, pos: usize, name: &'static str, value: String) {
+        self.entries.push(Entry::Field(self.base + pos, name, value));
+    }
+
+    fn problem(&mut self, pos: usize, reason: String) {
+        self.entries.push(Entry::Problem(self.base + pos, reason));
+    }
+
+    fn message(&mut self) {
+        self.entries.push(Entry::Section(self.base, "header".to_string()));
+        let mut cursor = Cursor::new(self.bytes);
+        let header = match Header::from_bytes(&mut cursor) {
+            Ok(header) => header,
+            Err(_) => {
+                self.problem(0, format!("header needs 12 bytes, only {} here", self.bytes.len()));
+                return;
+            }
+        };
+        let mut flags = header.flag_names().join(" ");
+        if header.flags & 0x0040 != 0 {
+            flags.push_str(" z");
+        }
+        self.field(0, "id", header.id.to_string());
+        self.field(
+            2,
+            "flags",
+            format!(
+                "0x{:04x} opcode {} rcode {} [{}]",
+                header.flags,
+                opcode_name(header.opcode()),
+                rcode_name(header.rcode() as u16),
+                flags.trim()
+            )
+        );
+        let update = header.opcode() == 5;
+        let sections = if update {
+            ["zone", "prerequisite", "update", "additional"]
+        } else {
+            ["question", "answer", "authority", "additional"]
+        };
+        let counts = [header.qdcount, header.ancount, header.nscount, header.arcount];
+        for (i, (section, count)) in sections.iter().zip(counts).enumerate() {
+            self.field(4 + 2 * i, "count", format!("{} {}", count, section));
+        }
+
+        for (i, (section, count)) in sections.iter().zip(counts).enumerate() {
+            for n in 0..count {
+                let pos = cursor.position() as usize;
+                if pos == self.bytes.len() {
+                    self.problem(pos, format!("header counts {} {} records but the message ends after {}", count, section, n));
+                    return;
+                }
+                self.entries.push(Entry::Section(self.base + pos, format!("{} {}", section, n + 1)));
+                let read = if i == 0 { self.question(&mut cursor) } else { self.record(&mut cursor) };
+                if !read {
+                    return;
+                }
+            }
+        }
+
+        let end = cursor.position() as usize;
+        if end < self.bytes.len() {
+            self.problem(end, format!("{} bytes after the last record", self.bytes.len() - end));
+        }
+    }
+
+    // false when the walk can't go on from here
+    fn question(&mut self, cursor: &mut Cursor<&[u8]>) -> bool {
+        if !self.name(cursor) {
+            return false;
+        }
+        let pos = cursor.position() as usize;
+        let (Ok(qtype), Ok(qclass)) = (cursor.read_u16::<BigEndian>(), cursor.read_u16::<BigEndian>()) else {
+            self.problem(pos, "question runs past end of message".to_string());
+            return false;
+        };
+        self.field(pos, "type", RecordType::from(qtype).to_string());
+        self.field(pos + 2, "class", class_name(qclass));
+        true
+    }
+
+    fn record(&mut self, cursor: &mut Cursor<&[u8]>) -> bool {
+        if !self.name(cursor) {
+            return false;
+        }
+        let pos = cursor.position() as usize;
+        let fixed = (
+            cursor.read_u16::<BigEndian>(),
+            cursor.read_u16::<BigEndian>(),
+            cursor.read_u32::<BigEndian>(),
+            cursor.read_u16::<BigEndian>(),
+        );
+        let (Ok(rtype), Ok(class), Ok(ttl), Ok(rdlength)) = fixed else {
+            self.problem(pos, "record runs past end of message".to_string());
+            return false;
+        };
+        let rtype = RecordType::from(rtype);
+        self.field(pos, "type", rtype.to_string());
+        // OPT puts the udp payload size in class and the extended rcode, version and flags in ttl (RFC 6891 6.1.3)
+        if rtype == RecordType::OPT {
+            self.field(pos + 2, "udp size", class.to_string());
+            self.field(
+                pos + 4,
+                "extended",
+                format!("rcode {} version {} flags 0x{:04x}", ttl >> 24, (ttl >> 16) & 0xff, ttl & 0xffff)
+            );
+        } else {
+            self.field(pos + 2, "class", class_name(class));
+            self.field(pos + 4, "ttl", ttl.to_string());
+        }
+        self.field(pos + 8, "rdlength", rdlength.to_string());
+
+        let start = pos + 10;
+        let end = start + rdlength as usize;
+        if end > self.bytes.len() {
+            self.problem(start, format!("rdata needs {} bytes, only {} left", rdlength, self.bytes.len() - start));
+            return false;
+        }
+        if rdlength == 0 && matches!(class, CLASS_NONE | CLASS_ANY) {
+            self.field(start, "rdata", "(empty)".to_string());
+        } else {
+            // rdata can hold names too, pointing anywhere earlier in the message
+            let mut rdata = Cursor::new(self.bytes);
+            rdata.set_position(start as u64);
+            match RData::from_bytes(&mut rdata, rtype, rdlength as usize) {
+                Ok(RData::OPT(options)) if options.is_empty() => self.field(start, "rdata", "(no options)".to_string()),
+                Ok(rdata) => self.field(start, "rdata", rdata.to_string()),
+                Err(e) => self.problem(start, format!("bad {} rdata: {}", rtype, e)),
+            }
+        }
+        cursor.set_position(end as u64);
+        true
+    }
+
+    // the name as read_name sees it, with every compression pointer on the way shown
+    fn name(&mut self, cursor: &mut Cursor<&[u8]>) -> bool {
+        let start = cursor.position() as usize;
+        match read_name(cursor) {
+            Ok(name) => {
+                let name = if name.is_empty() { ".".to_string() } else { format!("{}.", name) };
+                self.field(start, "name", name);
+                for (at, target) in pointers(self.bytes, start) {
+                    self.field(at, "pointer", format!("to {:04x}", self.base + target));
+                }
+                true
+            }
+            Err(e) => {
+                // point at the label that went wrong rather than the start of the name
+                let at = pointers(self.bytes, start).last().map_or(start, |&(at, _)| at);
+                let at = if e.to_string().contains("pointer") { at } else { start };
+                self.problem(at, format!("bad name: {}", e));
+                false
+            }
+        }
+    }
+}
+
+// the (offset, target) of each compression pointer followed while reading the name at start,
+// as far as it can be read
+fn pointers(bytes: &[u8], start: usize) -> Vec<(usize, usize)> {
+    let mut found = vec![];
+    let mut pos = start;
+    while let Some(&len) = bytes.get(pos) {
+        match len & 0xc0 {
+            0x00 if len == 0 => break,
+            0x00 => pos += 1 + len as usize,
+            0xc0 => {
+                let Some(&low) = bytes.get(pos + 1) else { break };
+                let target = (((len & 0x3f) as usize) << 8) | low as usize;
+                found.push((pos, target));
+                if target >= pos {
+                    break;
+                }
+                pos = target;
+            }
+            _ => break,
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{ Message, Question, ResourceRecord };
+
+    fn problems(entries: &[Entry]) -> Vec<String> {
+        entries
+            .iter()
+            .filter_map(|e| match e {
+                Entry::Problem(at, reason) => Some(format!("{:04x} {}", at, reason)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn response() -> Vec<u8> {
+        let mut message = Message::from_bytes(&[0x12, 0x34, 0x81, 0x80, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        message.questions.push(Question { qname: "example.com".to_string(), qtype: RecordType::A, qclass: 1 });
+        message.answers.push(ResourceRecord {
+            name: "example.com".to_string(),
+            rtype: RecordType::A,
+            class: 1,
+            ttl: 300,
+            rdata: RData::A("192.0.2.1".parse().unwrap()),
+        });
+        message.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        let wireshark = "0000   12 34 81 80 00 01 00 01 00 00 00 00 07 65 78 61   .4...........exa\n\
+0010   6d 70 6c 65                                       mple\n";
+        let bytes = parse_hex(wireshark).unwrap();
+        assert_eq!(bytes.len(), 20);
+        assert_eq!(&bytes[16..], b"mple");
+        assert_eq!(parse_hex(";; 0000  12 34 81\n;; 0010  80\n").unwrap(), vec![0x12, 0x34, 0x81, 0x80]);
+        assert_eq!(parse_hex("# from a capture\n1234 8180\n0001\n").unwrap(), vec![0x12, 0x34, 0x81, 0x80, 0, 1]);
+        assert_eq!(parse_hex("00000010: ab cd\n").unwrap(), vec![0xab, 0xcd]);
+        assert!(parse_hex("12 3").is_err());
+        assert!(parse_hex("hello").is_err());
+        assert!(parse_hex("\n").is_err());
+    }
+
+    #[test]
+    fn test_dissect() {
+        let bytes = response();
+        let entries = dissect(&bytes, false);
+        assert!(problems(&entries).is_empty());
+        assert!(entries.contains(&Entry::Field(0, "id", "4660".to_string())));
+        assert!(entries.contains(&Entry::Field(2, "flags", "0x8180 opcode QUERY rcode NOERROR [qr rd ra]".to_string())));
+        assert!(entries.contains(&Entry::Section(0x1d, "answer 1".to_string())));
+        assert!(entries.contains(&Entry::Field(0x1d, "pointer", "to 000c".to_string())));
+        assert!(entries.contains(&Entry::Field(0x29, "rdata", "192.0.2.1".to_string())));
+
+        // the same over tcp, everything two bytes further on
+        let framed = [&(bytes.len() as u16).to_be_bytes()[..], &bytes].concat();
+        let entries = dissect(&framed, true);
+        assert!(problems(&entries).is_empty());
+        assert!(entries.contains(&Entry::Field(0x1f, "pointer", "to 000e".to_string())));
+        assert_eq!(problems(&dissect(&framed[..framed.len() - 1], true))[0], "0000 length says 45 bytes but 44 follow");
+    }
+
+    #[test]
+    fn test_problems() {
+        let bytes = response();
+        assert_eq!(problems(&dissect(&bytes[..8], false)), ["0000 header needs 12 bytes, only 8 here"]);
+        assert_eq!(problems(&dissect(&[&bytes[..], &[0, 0]].concat(), false)), ["002d 2 bytes after the last record"]);
+        assert_eq!(
+            problems(&dissect(&bytes[..0x1d], false)),
+            ["001d header counts 1 answer records but the message ends after 0"]
+        );
+
+        // the answer's name pointing at itself
+        let mut looped = bytes.clone();
+        looped[0x1e] = 0x1d;
+        assert_eq!(
+            problems(&dissect(&looped, false)),
+            ["001d bad name: compression pointer does not point backwards"]
+        );
+
+        // an A record two bytes short, with the walk carrying on past it
+        let mut short = bytes.clone();
+        short[0x28] = 2;
+        let found = problems(&dissect(&short, false));
+        assert_eq!(found[0], "0029 bad A rdata: A record must be 4 bytes");
+        assert_eq!(found[1], "002b 2 bytes after the last record");
+    }
+}
@@ -0,0 +1,845 @@
+use ring::signature::{ self, RsaPublicKeyComponents, UnparsedPublicKey };
+use std::cmp::Ordering;
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::cache::follow_cnames;
+use crate::edns::{ self, Edns };
+use crate::encoding::from_base32hex;
+use crate::iterative::is_subdomain;
+use crate::message::{ rcode_name, write_name, Message, Question, ResourceRecord };
+use crate::rdata::{ RData, RecordType };
+use crate::transport::{ self, Client };
+
+// the root zone KSKs (2017 and 2024) as published by IANA
+pub const ROOT_ANCHORS: &str = "\
+. 172800 IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D
+. 172800 IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16
+";
+
+const ALGORITHM_RSASHA256: u8 = 8;
+const ALGORITHM_ECDSAP256SHA256: u8 = 13;
+const DIGEST_SHA1: u8 = 1;
+const DIGEST_SHA256: u8 = 2;
+const FLAG_ZONE_KEY: u16 = 0x0100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Security {
+    Secure, // signatures chain up to a trust anchor
+    Insecure, // provably unsigned, e.g. a delegation without DS
+    Bogus(String), // should have been signed but doesn't check out
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Security::Secure => write!(f, "secure"),
+            Security::Insecure => write!(f, "insecure"),
+            Security::Bogus(reason) => write!(f, "bogus ({})", reason),
+        }
+    }
+}
+
+// where the validator gets the DS, DNSKEY and SOA records it needs
+pub trait Lookup {
+    fn lookup(&mut self, name: &str, qtype: RecordType) -> Result<Message, String>;
+}
+
+// asks ordinary recursive servers, with CD set so they hand over data they think is bogus
+pub struct Upstream<'a> {
+    pub client: &'a mut Client,
+    pub servers: &'a [SocketAddr],
+}
+
+impl Lookup for Upstream<'_> {
+    fn lookup(&mut self, name: &str, qtype: RecordType) -> Result<Message, String> {
+        let question = Question { qname: name.to_string(), qtype, qclass: 1 };
+        let mut message = transport::build_query(question, true, Some(&dnssec_edns())).map_err(|e| e.to_string())?;
+        set_checking_disabled(&mut message);
+        self.client
+            .exchange(&message, self.servers, &mut vec![])
+            .map(|(response, _)| response)
+            .map_err(|e| e.to_string())
+    }
+}
+
+pub fn dnssec_edns() -> Edns {
+    let mut edns = Edns::new(edns::DEFAULT_UDP_SIZE);
+    edns.dnssec_ok = true;
+    edns
+}
+
+// sets the CD bit in an encoded query
+pub fn set_checking_disabled(query: &mut [u8]) {
+    query[3] |= 0x10;
+}
+
+// records of one name and type, with the signatures covering them
+struct RRset<'a> {
+    name: String,
+    rtype: RecordType,
+    records: Vec<&'a ResourceRecord>,
+    signatures: Vec<&'a ResourceRecord>,
+}
+
+fn rrsets(records: &[ResourceRecord]) -> Vec<RRset<'_>> {
+    let mut sets: Vec<RRset> = vec![];
+    for record in records.iter().filter(|r| !matches!(r.rtype, RecordType::RRSIG | RecordType::OPT)) {
+        match sets.iter_mut().find(|s| s.rtype == record.rtype && s.name.eq_ignore_ascii_case(&record.name)) {
+            Some(set) => set.records.push(record),
+            None => sets.push(RRset {
+                name: record.name.clone(),
+                rtype: record.rtype,
+                records: vec![record],
+                signatures: vec![],
+            }),
+        }
+    }
+    for sig in records.iter() {
+        if let RData::RRSIG { type_covered, .. } = &sig.rdata {
+            if let Some(set) = sets.iter_mut().find(|s| s.rtype == *type_covered && s.name.eq_ignore_ascii_case(&sig.name)) {
+                set.signatures.push(sig);
+            }
+        }
+    }
+    sets
+}
+
+// a validating stub: checks responses against keys it fetches and authenticates itself
+pub struct Validator<L: Lookup> {
+    lookup: L,
+    anchors: Vec<ResourceRecord>, // DS or DNSKEY records
+    pub now: u32,
+    keys: HashMap<String, Option<Vec<ResourceRecord>>>, // trusted DNSKEYs per zone, None when unsigned
+    zones: HashMap<String, String>, // the zone apex each name was found in
+    pending: HashSet<String>, // zones whose keys are being fetched, to stop loops
+}
+
+impl<L: Lookup> Validator<L> {
+    pub fn new(lookup: L, anchors: Vec<ResourceRecord>) -> Validator<L> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0);
+        Validator { lookup, anchors, now, keys: HashMap::new(), zones: HashMap::new(), pending: HashSet::new() }
+    }
+
+    pub fn validate(&mut self, response: &Message) -> Security {
+        match self.check(response) {
+            Ok(security) => security,
+            Err(reason) => Security::Bogus(reason),
+        }
+    }
+
+    fn check(&mut self, response: &Message) -> Result<Security, String> {
+        let question = response.questions.first().ok_or("response has no question")?;
+        let rcode = response.rcode();
+        if rcode != 0 && rcode != 3 {
+            return Err(format!("cannot validate a {} response", rcode_name(rcode)));
+        }
+
+        let answers = rrsets(&response.answers);
+        let authority = rrsets(&response.authorities);
+        let proofs: Vec<&RRset> = authority
+            .iter()
+            .filter(|s| matches!(s.rtype, RecordType::SOA | RecordType::NSEC | RecordType::NSEC3))
+            .collect();
+        let mut secure = true;
+        for set in answers.iter().chain(proofs.iter().copied()) {
+            if self.verify_rrset(set)? == Security::Insecure {
+                secure = false;
+            }
+        }
+        if !secure {
+            return Ok(Security::Insecure);
+        }
+
+        // a wildcard expansion is only valid if the name itself doesn't exist
+        for set in answers.iter() {
+            if let Some(labels) = wildcard_labels(set) {
+                if !denies_name(&set.name, labels, &response.authorities) {
+                    return Err(format!("wildcard answer for {} without proof of its absence", set.name));
+                }
+            }
+        }
+
+        let target = follow_cnames(&question.qname, &response.answers);
+        let answered = answers.iter().any(|s| s.rtype == question.qtype && s.name.eq_ignore_ascii_case(&target));
+        if rcode == 0 && answered {
+            return Ok(Security::Secure);
+        }
+        if proofs.is_empty() {
+            // nothing signed to go on: fine for unsigned zones only
+            let zone = self.zone_of(&target)?;
+            return match self.trusted_keys(&zone)? {
+                None => Ok(Security::Insecure),
+                Some(_) => Err(format!("negative answer for {} from signed zone {} is unsigned", target, zone)),
+            };
+        }
+        let proven = if rcode == 3 {
+            proves_nxdomain(&target, &response.authorities)
+        } else {
+            proves_nodata(&target, question.qtype, &response.authorities)
+        };
+        if !proven {
+            return Err(format!("no proof of denial for {} {}", target, question.qtype));
+        }
+        Ok(Security::Secure)
+    }
+
+    fn verify_rrset(&mut self, set: &RRset) -> Result<Security, String> {
+        let zone = self.zone_of_set(set)?;
+        let keys = self.trusted_keys(&zone)?;
+        if set.signatures.is_empty() {
+            return match keys {
+                None => Ok(Security::Insecure),
+                Some(_) => Err(format!("{} {} in signed zone {} is unsigned", set.name, set.rtype, zone)),
+            };
+        }
+        let keys = match keys {
+            Some(keys) => keys,
+            None => {
+                return Ok(Security::Insecure);
+            }
+        };
+
+        // only the zone holding the set may sign it (RFC 4035 5.3.1); keys from anywhere else are no proof
+        let mut failure = format!("no usable signature over {} {}", set.name, set.rtype);
+        for sig in set.signatures.iter() {
+            let signer = match &sig.rdata {
+                RData::RRSIG { signer, .. } => signer,
+                _ => continue,
+            };
+            if !signer.eq_ignore_ascii_case(&zone) {
+                failure = format!(
+                    "{} {} is signed by {}, not by its zone {}",
+                    set.name,
+                    set.rtype,
+                    display_name(signer),
+                    display_name(&zone)
+                );
+                continue;
+            }
+            match verify_signature(set, sig, &keys, self.now) {
+                Ok(()) => {
+                    return Ok(Security::Secure);
+                }
+                Err(reason) => failure = reason,
+            }
+        }
+        Err(failure)
+    }
+
+    // the zone an RRset is served from: records at a delegation point other than NS belong to the parent
+    fn zone_of_set(&mut self, set: &RRset) -> Result<String, String> {
+        let parent_side = match set.rtype {
+            RecordType::DS => true,
+            RecordType::NSEC => set.records.iter().any(|r| match &r.rdata {
+                RData::NSEC { types, .. } => types.contains(&RecordType::NS) && !types.contains(&RecordType::SOA),
+                _ => false,
+            }),
+            _ => false,
+        };
+        match set.rtype {
+            RecordType::SOA => Ok(set.name.to_ascii_lowercase()),
+            RecordType::NSEC3 => Ok(parent(&set.name).to_ascii_lowercase()), // owners are <hash>.<zone>
+            _ if parent_side => self.zone_of(parent(&set.name)),
+            _ => self.zone_of(&set.name),
+        }
+    }
+
+    // the apex of the zone a name belongs to, from the SOA that comes back for it
+    fn zone_of(&mut self, name: &str) -> Result<String, String> {
+        let name = name.to_ascii_lowercase();
+        if let Some(zone) = self.zones.get(&name) {
+            return Ok(zone.clone());
+        }
+        let response = self.lookup.lookup(&name, RecordType::SOA)?;
+        let zone = response
+            .answers
+            .iter()
+            .chain(response.authorities.iter())
+            .find(|r| r.rtype == RecordType::SOA && is_subdomain(&name, &r.name))
+            .map(|r| r.name.to_ascii_lowercase())
+            .ok_or_else(|| format!("cannot find the zone {} belongs to", name))?;
+        self.zones.insert(name, zone.clone());
+        Ok(zone)
+    }
+
+    fn trusted_keys(&mut self, zone: &str) -> Result<Option<Vec<ResourceRecord>>, String> {
+        let zone = zone.to_ascii_lowercase();
+        if let Some(keys) = self.keys.get(&zone) {
+            return Ok(keys.clone());
+        }
+        if !self.pending.insert(zone.clone()) {
+            return Err(format!("loop in the chain of trust at {}", display_name(&zone)));
+        }
+        let result = self.find_keys(&zone);
+        self.pending.remove(&zone);
+        let keys = result?;
+        self.keys.insert(zone, keys.clone());
+        Ok(keys)
+    }
+
+    // authenticates a zone's DNSKEY set through a trust anchor or the parent's DS records
+    fn find_keys(&mut self, zone: &str) -> Result<Option<Vec<ResourceRecord>>, String> {
+        let mut anchors: Vec<ResourceRecord> = self
+            .anchors
+            .iter()
+            .filter(|a| a.name.eq_ignore_ascii_case(zone))
+            .cloned()
+            .collect();
+        if anchors.is_empty() {
+            if zone.is_empty() {
+                return Err("no trust anchor for the root zone".to_string());
+            }
+            let response = self.lookup.lookup(zone, RecordType::DS)?;
+            let answers = rrsets(&response.answers);
+            match answers.iter().find(|s| s.rtype == RecordType::DS && s.name.eq_ignore_ascii_case(zone)) {
+                Some(ds) => {
+                    if self.verify_rrset(ds)? == Security::Insecure {
+                        return Ok(None);
+                    }
+                    anchors = ds.records.iter().map(|r| (*r).clone()).collect();
+                }
+                None => {
+                    // the parent has to prove the delegation is unsigned
+                    for set in rrsets(&response.authorities).iter() {
+                        if !matches!(set.rtype, RecordType::SOA | RecordType::NSEC | RecordType::NSEC3) {
+                            continue;
+                        }
+                        if self.verify_rrset(set)? == Security::Insecure {
+                            return Ok(None);
+                        }
+                    }
+                    if proves_unsigned_delegation(zone, &response.authorities) {
+                        return Ok(None);
+                    }
+                    return Err(format!("no DS for {} and no proof of its absence", zone));
+                }
+            }
+        }
+
+        // a zone signed only with algorithms we don't know is treated as unsigned (RFC 4035 5.2)
+        if !anchors.iter().any(|a| supported(&a.rdata)) {
+            return Ok(None);
+        }
+
+        let response = self.lookup.lookup(zone, RecordType::DNSKEY)?;
+        let sets = rrsets(&response.answers);
+        let keyset = sets
+            .iter()
+            .find(|s| s.rtype == RecordType::DNSKEY && s.name.eq_ignore_ascii_case(zone))
+            .ok_or_else(|| format!("{} has a DS record but no DNSKEY", display_name(zone)))?;
+        let mut failure = format!("no DNSKEY for {} matches its DS", display_name(zone));
+        for anchor in anchors.iter() {
+            for key in keyset.records.iter().filter(|k| matches_anchor(k, anchor)) {
+                for sig in keyset.signatures.iter() {
+                    match verify_signature(keyset, sig, &[(*key).clone()], self.now) {
+                        Ok(()) => {
+                            return Ok(Some(keyset.records.iter().map(|r| (*r).clone()).collect()));
+                        }
+                        Err(reason) => failure = reason,
+                    }
+                }
+            }
+        }
+        Err(failure)
+    }
+}
+
+fn supported(rdata: &RData) -> bool {
+    let algorithm = match rdata {
+        RData::DS { algorithm, digest_type, .. } if matches!(*digest_type, DIGEST_SHA1 | DIGEST_SHA256) => *algorithm,
+        RData::DNSKEY { algorithm, .. } => *algorithm,
+        _ => {
+            return false;
+        }
+    };
+    matches!(algorithm, ALGORITHM_RSASHA256 | ALGORITHM_ECDSAP256SHA256)
+}
+
+fn matches_anchor(key: &ResourceRecord, anchor: &ResourceRecord) -> bool {
+    match &anchor.rdata {
+        RData::DNSKEY { .. } => key.rdata == anchor.rdata,
+        RData::DS { key_tag: tag, algorithm, digest_type, digest } => {
+            let key_algorithm = match &key.rdata {
+                RData::DNSKEY { algorithm, .. } => *algorithm,
+                _ => {
+                    return false;
+                }
+            };
+            key_algorithm == *algorithm &&
+                key_tag(&key.rdata) == *tag &&
+                ds_digest(&key.name, &key.rdata, *digest_type).as_ref() == Some(digest)
+        }
+        _ => false,
+    }
+}
+
+// RFC 4034 appendix B
+pub fn key_tag(dnskey: &RData) -> u16 {
+    let bytes = dnskey.to_bytes().unwrap_or_default();
+    let mut sum: u32 = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        sum += if i % 2 == 0 { (b as u32) << 8 } else { b as u32 };
+    }
+    sum += (sum >> 16) & 0xffff;
+    (sum & 0xffff) as u16
+}
+
+pub fn ds_digest(owner: &str, dnskey: &RData, digest_type: u8) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    write_name(&mut data, &owner.to_ascii_lowercase()).ok()?;
+    data.extend(dnskey.to_bytes().ok()?);
+    match digest_type {
+        DIGEST_SHA1 => Some(sha1(&data)),
+        DIGEST_SHA256 => Some(ring::digest::digest(&ring::digest::SHA256, &data).as_ref().to_vec()),
+        _ => None,
+    }
+}
+
+// checks one RRSIG over a set against the given keys (RFC 4035 5.3)
+fn verify_signature(
+    set: &RRset,
+    sig: &ResourceRecord,
+    keys: &[ResourceRecord],
+    now: u32
+) -> Result<(), String> {
+    let (type_covered, algorithm, labels, original_ttl, expiration, inception, tag, signer, signature) = match &sig.rdata {
+        RData::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature } =>
+            (*type_covered, *algorithm, *labels as usize, *original_ttl, *expiration, *inception, *key_tag, signer, signature),
+        _ => {
+            return Err("not an RRSIG".to_string());
+        }
+    };
+    let what = format!("{} {}", display_name(&set.name), set.rtype);
+    if type_covered != set.rtype {
+        return Err(format!("RRSIG over {} covers {}", what, type_covered));
+    }
+    if !serial_le(inception, now) {
+        return Err(format!("signature over {} is not valid yet", what));
+    }
+    if !serial_le(now, expiration) {
+        return Err(format!("signature over {} has expired", what));
+    }
+    let owner_labels = label_count(&set.name);
+    if labels > owner_labels {
+        return Err(format!("RRSIG over {} claims too many labels", what));
+    }
+
+    // wildcard expansions are signed under the wildcard name
+    let owner = if labels < owner_labels {
+        let kept: Vec<&str> = set.name.split('.').skip(owner_labels - labels).collect();
+        format!("*.{}", kept.join(".")).trim_end_matches('.').to_ascii_lowercase()
+    } else {
+        set.name.to_ascii_lowercase()
+    };
+
+    let mut header = sig.rdata.clone();
+    if let RData::RRSIG { signer, .. } = &mut header {
+        *signer = signer.to_ascii_lowercase();
+    }
+    let mut data = header.rrsig_header().map_err(|e| e.to_string())?;
+    let mut rdatas = set
+        .records
+        .iter()
+        .map(|r| canonical(&r.rdata).to_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    rdatas.sort();
+    rdatas.dedup();
+    for rdata in rdatas {
+        write_name(&mut data, &owner).map_err(|e| e.to_string())?;
+        data.extend(set.rtype.to_u16().to_be_bytes());
+        data.extend(set.records[0].class.to_be_bytes());
+        data.extend(original_ttl.to_be_bytes());
+        data.extend((rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+    }
+
+    for key in keys.iter().filter(|k| k.name.eq_ignore_ascii_case(signer)) {
+        let (flags, protocol, key_algorithm, public_key) = match &key.rdata {
+            RData::DNSKEY { flags, protocol, algorithm, public_key } => (*flags, *protocol, *algorithm, public_key),
+            _ => continue,
+        };
+        if flags & FLAG_ZONE_KEY == 0 || protocol != 3 || key_algorithm != algorithm || key_tag(&key.rdata) != tag {
+            continue;
+        }
+        let valid = match algorithm {
+            ALGORITHM_RSASHA256 => rsa_sha256_verify(public_key, &data, signature),
+            ALGORITHM_ECDSAP256SHA256 => {
+                // RFC 6605 keys are x || y, which is the uncompressed point without its 04 prefix
+                let point = [&[0x04], public_key.as_slice()].concat();
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(&data, signature).is_ok()
+            }
+            _ => false,
+        };
+        if valid {
+            return Ok(());
+        }
+    }
+    Err(format!("no DNSKEY of {} validates the signature over {}", display_name(signer), what))
+}
+
+// RSA/SHA-256 with a key in the RFC 3110 layout: exponent length, exponent, modulus. 1024-bit
+// keys are still around in DNSSEC, so they are accepted.
+fn rsa_sha256_verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let (exponent_len, rest) = match public_key.split_first() {
+        Some((0, rest)) if rest.len() >= 2 => (u16::from_be_bytes([rest[0], rest[1]]) as usize, &rest[2..]),
+        Some((&len, rest)) => (len as usize, rest),
+        None => {
+            return false;
+        }
+    };
+    if exponent_len == 0 || rest.len() <= exponent_len {
+        return false;
+    }
+    let (e, n) = rest.split_at(exponent_len);
+    let strip = |bytes: &[u8]| -> Vec<u8> { bytes.iter().copied().skip_while(|&b| b == 0).collect() };
+    let key = RsaPublicKeyComponents { n: strip(n), e: strip(e) };
+    key.verify(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, message, signature).is_ok()
+}
+
+// SHA-1 is only used where the protocol fixes it: DS digest type 1 and NSEC3 hashes
+fn sha1(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, data).as_ref().to_vec()
+}
+
+// rdata with embedded names lowercased (RFC 4034 6.2, as amended by RFC 6840 5.1)
+fn canonical(rdata: &RData) -> RData {
+    let lower = |name: &String| name.to_ascii_lowercase();
+    match rdata {
+        RData::NS(name) => RData::NS(lower(name)),
+        RData::CNAME(name) => RData::CNAME(lower(name)),
+        RData::PTR(name) => RData::PTR(lower(name)),
+        RData::MX { preference, exchange } => RData::MX { preference: *preference, exchange: lower(exchange) },
+        RData::SRV { priority, weight, port, target } => RData::SRV {
+            priority: *priority,
+            weight: *weight,
+            port: *port,
+            target: lower(target),
+        },
+        RData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => RData::SOA {
+            mname: lower(mname),
+            rname: lower(rname),
+            serial: *serial,
+            refresh: *refresh,
+            retry: *retry,
+            expire: *expire,
+            minimum: *minimum,
+        },
+        other => other.clone(),
+    }
+}
+
+// RFC 1982 serial number arithmetic, so signature times survive 2106
+fn serial_le(a: u32, b: u32) -> bool {
+    a == b || (b.wrapping_sub(a) as i32) > 0
+}
+
+fn label_count(name: &str) -> usize {
+    if name.is_empty() { 0 } else { name.split('.').count() }
+}
+
+fn parent(name: &str) -> &str {
+    name.split_once('.').map(|(_, rest)| rest).unwrap_or("")
+}
+
+fn display_name(name: &str) -> String {
+    format!("{}.", name)
+}
+
+// labels of the wildcard an answer was synthesised from, if it was
+fn wildcard_labels(set: &RRset) -> Option<usize> {
+    set.signatures
+        .iter()
+        .filter_map(|s| match &s.rdata {
+            RData::RRSIG { labels, .. } => Some(*labels as usize),
+            _ => None,
+        })
+        .find(|&labels| labels < label_count(&set.name))
+}
+
+// DNSSEC ordering of names: label by label from the root, case-insensitively (RFC 4034 6.1)
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| -> Vec<Vec<u8>> {
+        if name.is_empty() {
+            return vec![];
+        }
+        name.split('.').rev().map(|l| l.to_ascii_lowercase().into_bytes()).collect()
+    };
+    labels(a).cmp(&labels(b))
+}
+
+// whether the NSEC from owner to next says name doesn't exist
+pub fn nsec_covers(owner: &str, next: &str, name: &str) -> bool {
+    let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+    if canonical_cmp(owner, next) == Ordering::Less {
+        after_owner && canonical_cmp(name, next) == Ordering::Less
+    } else {
+        // the last NSEC in the zone points back at the apex
+        is_subdomain(name, next) && after_owner
+    }
+}
+
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Option<Vec<u8>> {
+    let mut wire = vec![];
+    write_name(&mut wire, &name.to_ascii_lowercase()).ok()?;
+    let mut hash = sha1(&[wire.as_slice(), salt].concat());
+    for _ in 0..iterations {
+        hash = sha1(&[hash.as_slice(), salt].concat());
+    }
+    Some(hash)
+}
+
+pub fn nsec3_owner_hash(record: &ResourceRecord) -> Option<Vec<u8>> {
+    from_base32hex(record.name.split('.').next()?)
+}
+
+// whether hash falls strictly between owner and next, wrapping at the end of the chain
+pub fn hash_covers(owner: &[u8], next: &[u8], hash: &[u8]) -> bool {
+    if owner < next {
+        owner < hash && hash < next
+    } else {
+        hash > owner || hash < next
+    }
+}
+
+// the NSEC3 whose owner is the hash of name, and whether one covers it instead
+pub fn nsec3_match<'a>(records: &'a [ResourceRecord], name: &str, covering: bool) -> Option<&'a ResourceRecord> {
+    records.iter().find(|r| match &r.rdata {
+        RData::NSEC3 { salt, iterations, next_hashed, .. } => {
+            match (nsec3_owner_hash(r), nsec3_hash(name, salt, *iterations)) {
+                (Some(owner), Some(hash)) if covering => hash_covers(&owner, next_hashed, &hash),
+                (Some(owner), Some(hash)) => owner == hash,
+                _ => false,
+            }
+        }
+        _ => false,
+    })
+}
+
+fn nsec_records(records: &[ResourceRecord]) -> impl Iterator<Item = (&ResourceRecord, &String, &Vec<RecordType>)> {
+    records.iter().filter_map(|r| match &r.rdata {
+        RData::NSEC { next, types } => Some((r, next, types)),
+        _ => None,
+    })
+}
+
+fn nsec_covering<'a>(records: &'a [ResourceRecord], name: &str) -> Option<&'a ResourceRecord> {
+    nsec_records(records)
+        .find(|(r, next, _)| nsec_covers(&r.name, next, name))
+        .map(|(r, _, _)| r)
+}
+
+fn common_ancestor(a: &str, b: &str) -> String {
+    let a_labels: Vec<&str> = a.split('.').rev().collect();
+    let b_labels: Vec<&str> = b.split('.').rev().collect();
+    let shared = a_labels
+        .iter()
+        .zip(b_labels.iter())
+        .take_while(|(x, y)| x.eq_ignore_ascii_case(y))
+        .count();
+    let mut labels: Vec<&str> = a_labels[..shared].to_vec();
+    labels.reverse();
+    labels.join(".")
+}
+
+fn wildcard_of(encloser: &str) -> String {
+    if encloser.is_empty() { "*".to_string() } else { format!("*.{}", encloser) }
+}
+
+// the closest existing ancestor of name, proven with NSEC3 (RFC 5155 8.3)
+fn nsec3_closest_encloser(name: &str, records: &[ResourceRecord]) -> Option<String> {
+    let labels: Vec<&str> = name.split('.').collect();
+    for i in 1..=labels.len() {
+        let candidate = labels[i..].join(".");
+        if nsec3_match(records, &candidate, false).is_some() {
+            let next_closer = labels[i - 1..].join(".");
+            return nsec3_match(records, &next_closer, true).map(|_| candidate);
+        }
+    }
+    None
+}
+
+pub fn proves_nxdomain(name: &str, records: &[ResourceRecord]) -> bool {
+    if let Some(cover) = nsec_covering(records, name) {
+        let next = match &cover.rdata {
+            RData::NSEC { next, .. } => next,
+            _ => {
+                return false;
+            }
+        };
+        let (from_owner, from_next) = (common_ancestor(name, &cover.name), common_ancestor(name, next));
+        let encloser = if label_count(&from_next) > label_count(&from_owner) { from_next } else { from_owner };
+        return nsec_covering(records, &wildcard_of(&encloser)).is_some();
+    }
+    match nsec3_closest_encloser(name, records) {
+        Some(encloser) => nsec3_match(records, &wildcard_of(&encloser), true).is_some(),
+        None => false,
+    }
+}
+
+pub fn proves_nodata(name: &str, qtype: RecordType, records: &[ResourceRecord]) -> bool {
+    // a DS denial has to come from the parent side of the cut
+    let lacks = |types: &[RecordType]| {
+        !types.contains(&qtype) &&
+            !types.contains(&RecordType::CNAME) &&
+            (qtype != RecordType::DS || !types.contains(&RecordType::SOA))
+    };
+    for (record, next, types) in nsec_records(records) {
+        if record.name.eq_ignore_ascii_case(name) {
+            return lacks(types);
+        }
+        // empty non-terminal: the NSEC skips over name straight into its subtree
+        if nsec_covers(&record.name, next, name) && is_subdomain(next, name) {
+            return true;
+        }
+    }
+    match nsec3_match(records, name, false).map(|r| &r.rdata) {
+        Some(RData::NSEC3 { types, .. }) => lacks(types),
+        _ => false,
+    }
+}
+
+// the parent's denial of a DS at a delegation: the name has NS but neither SOA, which would make it
+// an apex seen from the child, nor DS (RFC 4035 5.2)
+fn proves_unsigned_delegation(name: &str, records: &[ResourceRecord]) -> bool {
+    let cut = |types: &[RecordType]| {
+        types.contains(&RecordType::NS) && !types.contains(&RecordType::SOA) && !types.contains(&RecordType::DS)
+    };
+    if let Some((_, _, types)) = nsec_records(records).find(|(r, _, _)| r.name.eq_ignore_ascii_case(name)) {
+        return cut(types);
+    }
+    match nsec3_match(records, name, false).map(|r| &r.rdata) {
+        Some(RData::NSEC3 { types, .. }) => cut(types),
+        _ => false,
+    }
+}
+
+// for wildcard answers: the exact name is covered by an NSEC, or its next closer name by an NSEC3
+fn denies_name(name: &str, wildcard_labels: usize, records: &[ResourceRecord]) -> bool {
+    if nsec_covering(records, name).is_some() {
+        return true;
+    }
+    let labels: Vec<&str> = name.split('.').collect();
+    let next_closer = labels[labels.len() - wildcard_labels - 1..].join(".");
+    nsec3_match(records, &next_closer, true).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Header;
+    use crate::server::Handler;
+    use crate::zone::{ parse_records, Authority, Zone };
+
+    const NOW: u32 = 1735689600; // 2025-01-01, inside the fixtures' signature window
+
+    struct Fixtures(Authority);
+
+    impl Lookup for Fixtures {
+        fn lookup(&mut self, name: &str, qtype: RecordType) -> Result<Message, String> {
+            Ok(self.0.handle(&query(name, qtype)))
+        }
+    }
+
+    fn query(name: &str, qtype: RecordType) -> Message {
+        Message {
+            header: Header { id: 1, flags: 0x0110, qdcount: 1, ancount: 0, nscount: 0, arcount: 1 },
+            questions: vec![Question { qname: name.to_string(), qtype, qclass: 1 }],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![dnssec_edns().to_record()],
+        }
+    }
+
+    fn authority() -> Authority {
+        let zones = [
+            include_str!("../tests/zones/test.signed.zone"),
+            include_str!("../tests/zones/secure.test.signed.zone"),
+            include_str!("../tests/zones/insecure.test.zone"),
+        ];
+        Authority { zones: zones.iter().map(|z| Zone::parse(z, "").unwrap()).collect() }
+    }
+
+    fn validator() -> Validator<Fixtures> {
+        let anchors = parse_records(include_str!("../tests/zones/test.anchor"), "").unwrap();
+        let mut validator = Validator::new(Fixtures(authority()), anchors);
+        validator.now = NOW;
+        validator
+    }
+
+    fn check(name: &str, qtype: RecordType) -> Security {
+        validator().validate(&authority().handle(&query(name, qtype)))
+    }
+
+    #[test]
+    fn test_secure_answers() {
+        assert_eq!(check("www.test", RecordType::A), Security::Secure); // RSA, KSK and ZSK
+        assert_eq!(check("www.secure.test", RecordType::AAAA), Security::Secure); // ECDSA via DS
+        assert_eq!(check("alias.secure.test", RecordType::A), Security::Secure);
+        assert_eq!(check("foo.wild.test", RecordType::TXT), Security::Secure);
+    }
+
+    #[test]
+    fn test_authenticated_denial() {
+        assert_eq!(check("nope.test", RecordType::A), Security::Secure); // NSEC
+        assert_eq!(check("www.test", RecordType::AAAA), Security::Secure);
+        assert_eq!(check("nope.secure.test", RecordType::A), Security::Secure); // NSEC3
+        assert_eq!(check("www.secure.test", RecordType::MX), Security::Secure);
+        assert_eq!(check("empty.secure.test", RecordType::A), Security::Secure);
+    }
+
+    #[test]
+    fn test_insecure_delegation() {
+        assert_eq!(check("www.insecure.test", RecordType::A), Security::Insecure);
+        assert_eq!(check("nope.insecure.test", RecordType::A), Security::Insecure);
+    }
+
+    #[test]
+    fn test_bogus_answers() {
+        let mut response = authority().handle(&query("www.secure.test", RecordType::A));
+        response.answers[0].rdata = RData::A([192, 0, 2, 66].into());
+        assert!(matches!(validator().validate(&response), Security::Bogus(_)));
+
+        let mut response = authority().handle(&query("nope.test", RecordType::A));
+        response.authorities.retain(|r| r.rtype != RecordType::NSEC && r.rtype != RecordType::RRSIG);
+        assert!(matches!(validator().validate(&response), Security::Bogus(_)));
+
+        // a forged answer signed as if www.secure.test were a zone of its own: the DS lookup for it
+        // gets an NSEC3 without NS, which is no proof of an unsigned delegation
+        let mut response = authority().handle(&query("www.secure.test", RecordType::A));
+        response.answers[0].rdata = RData::A([192, 0, 2, 66].into());
+        for record in response.answers.iter_mut() {
+            if let RData::RRSIG { signer, .. } = &mut record.rdata {
+                *signer = "www.secure.test".to_string();
+            }
+        }
+        assert!(matches!(validator().validate(&response), Security::Bogus(reason) if reason.contains("not by its zone")));
+        assert!(validator().trusted_keys("www.secure.test").is_err());
+
+        let response = authority().handle(&query("www.test", RecordType::A));
+        let mut expired = validator();
+        expired.now = 2050000000;
+        assert!(matches!(expired.validate(&response), Security::Bogus(reason) if reason.contains("expired")));
+    }
+
+    #[test]
+    fn test_wrong_anchor_is_bogus() {
+        let anchor = parse_records("test. 3600 IN DS 1 8 2 00", "").unwrap();
+        let mut validator = Validator::new(Fixtures(authority()), anchor);
+        validator.now = NOW;
+        let response = authority().handle(&query("www.test", RecordType::A));
+        assert!(matches!(validator.validate(&response), Security::Bogus(_)));
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let mut names = vec!["z.example", "a.example", "yljkjljk.a.example", "example", "*.z.example", "Z.a.example"];
+        names.sort_by(|a, b| canonical_cmp(a, b));
+        assert_eq!(names, vec!["example", "a.example", "yljkjljk.a.example", "Z.a.example", "z.example", "*.z.example"]);
+        assert!(nsec_covers("www.test", "test", "zzz.test"));
+        assert!(!nsec_covers("www.test", "test", "aaa.other"));
+    }
+}
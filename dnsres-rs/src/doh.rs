@@ -0,0 +1,217 @@
+use std::io::{ Error, Read, Write };
+
+use crate::encoding::to_base64url;
+use crate::message::malformed;
+
+pub const DOH_PATH: &str = "/dns-query";
+
+const MEDIA_TYPE: &str = "application/dns-message";
+
+// longer than any status line or header a DoH server has reason to send
+const MAX_LINE: usize = 8192;
+
+// a DNS message can't be any longer, so neither can a body worth reading
+const MAX_BODY: usize = 65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get, // the query base64url encoded in the dns parameter, friendlier to HTTP caches
+    Post,
+}
+
+// where on the server queries go and how they are sent (RFC 8484 4.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub path: String,
+    pub method: Method,
+}
+
+// one query and its reply over an HTTP/1.1 connection, which is left open for the next one.
+// authority goes in the Host header. The id is sent as it is rather than as 0 (RFC 8484 4.1),
+// so the reply is checked like one that came over udp.
+pub fn exchange<S: Read + Write>(
+    stream: &mut S,
+    authority: &str,
+    endpoint: &Endpoint,
+    message: &[u8]
+) -> Result<Vec<u8>, Error> {
+    let request = match endpoint.method {
+        Method::Get => {
+            let separator = if endpoint.path.contains('?') { '&' } else { '?' };
+            let target = format!("{}{}dns={}", endpoint.path, separator, to_base64url(message));
+            format!("GET {} HTTP/1.1\r\nHost: {}\r\nAccept: {}\r\n\r\n", target, authority, MEDIA_TYPE).into_bytes()
+        }
+        Method::Post => {
+            let head = format!(
+                "POST {} HTTP/1.1\r\nHost: {}\r\nAccept: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                endpoint.path,
+                authority,
+                MEDIA_TYPE,
+                MEDIA_TYPE,
+                message.len()
+            );
+            [head.as_bytes(), message].concat()
+        }
+    };
+    stream.write_all(&request)?;
+    stream.flush()?;
+
+    let status = read_line(stream)?;
+    let mut parts = status.splitn(3, ' ');
+    let (version, code, reason) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default(), parts.next());
+    if !version.starts_with("HTTP/1.") {
+        return Err(malformed(&format!("not an HTTP/1.x response: {}", status)));
+    }
+    let mut headers = vec![];
+    loop {
+        let line = read_line(stream)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+
+    // the body is read even for an error, so the connection can carry the next query
+    let body = match (header("transfer-encoding"), header("content-length")) {
+        (Some(encoding), _) if encoding.eq_ignore_ascii_case("chunked") => read_chunked(stream)?,
+        (_, Some(length)) => {
+            let length: usize = length.parse().map_err(|_| malformed(&format!("invalid Content-Length: {}", length)))?;
+            if length > MAX_BODY {
+                return Err(malformed(&format!("body of {} bytes is too long for a DNS message", length)));
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body)?;
+            body
+        }
+        _ => {
+            let mut body = vec![];
+            stream.take(MAX_BODY as u64 + 1).read_to_end(&mut body)?;
+            if body.len() > MAX_BODY {
+                return Err(malformed("body is too long for a DNS message"));
+            }
+            body
+        }
+    };
+    if code != "200" {
+        return Err(Error::other(format!("server answered HTTP {} {}", code, reason.unwrap_or_default())));
+    }
+    let media_type = header("content-type").and_then(|v| v.split(';').next()).map(str::trim);
+    if !media_type.is_some_and(|t| t.eq_ignore_ascii_case(MEDIA_TYPE)) {
+        return Err(malformed(&format!("reply is {}, not {}", media_type.unwrap_or("untyped"), MEDIA_TYPE)));
+    }
+    Ok(body)
+}
+
+fn read_line<R: Read>(stream: &mut R) -> Result<String, Error> {
+    let mut line = vec![];
+    let mut byte = [0];
+    while !line.ends_with(b"\r\n") {
+        if line.len() == MAX_LINE {
+            return Err(malformed("HTTP line too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    line.truncate(line.len() - 2);
+    String::from_utf8(line).map_err(|_| malformed("HTTP line is not UTF-8"))
+}
+
+// chunk sizes in hex, each chunk followed by CRLF, the last one empty and then optional trailers
+fn read_chunked<R: Read>(stream: &mut R) -> Result<Vec<u8>, Error> {
+    let mut body = vec![];
+    loop {
+        let line = read_line(stream)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| malformed(&format!("invalid chunk size: {}", line)))?;
+        if size == 0 {
+            while !read_line(stream)?.is_empty() {}
+            return Ok(body);
+        }
+        let start = body.len();
+        if size > MAX_BODY - start {
+            return Err(malformed("chunked body is too long for a DNS message"));
+        }
+        body.resize(start + size, 0);
+        stream.read_exact(&mut body[start..])?;
+        if !read_line(stream)?.is_empty() {
+            return Err(malformed("chunk longer than its size"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // a connection that replays a canned response and keeps what was written to it
+    struct Canned {
+        response: Cursor<Vec<u8>>,
+        request: Vec<u8>,
+    }
+
+    impl Read for Canned {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Canned {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.request.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn send(method: Method, path: &str, response: &str) -> (Result<Vec<u8>, Error>, Vec<u8>) {
+        let mut stream = Canned { response: Cursor::new(response.as_bytes().to_vec()), request: vec![] };
+        let endpoint = Endpoint { path: path.to_string(), method };
+        let result = exchange(&mut stream, "dns.example:8443", &endpoint, &[0xab, 0xcd, 0xef]);
+        (result, stream.request)
+    }
+
+    #[test]
+    fn test_requests() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: 3\r\n\r\nabc";
+        let (body, request) = send(Method::Get, "/dns-query", ok);
+        assert_eq!(body.unwrap(), b"abc");
+        assert_eq!(request, b"GET /dns-query?dns=q83v HTTP/1.1\r\nHost: dns.example:8443\r\nAccept: application/dns-message\r\n\r\n");
+        assert!(send(Method::Get, "/resolve?ct", ok).1.starts_with(b"GET /resolve?ct&dns=q83v "));
+
+        let (_, request) = send(Method::Post, "/dns-query", ok);
+        assert!(request.starts_with(b"POST /dns-query HTTP/1.1\r\n"));
+        assert!(request.ends_with(b"Content-Type: application/dns-message\r\nContent-Length: 3\r\n\r\n\xab\xcd\xef"));
+    }
+
+    #[test]
+    fn test_responses() {
+        let chunked = "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message; charset=binary\r\n\
+Transfer-Encoding: chunked\r\n\r\n2\r\nab\r\n1;note=x\r\nc\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        assert_eq!(send(Method::Post, "/dns-query", chunked).0.unwrap(), b"abc");
+
+        let error = "HTTP/1.1 415 Unsupported Media Type\r\nContent-Length: 0\r\n\r\n";
+        let message = send(Method::Post, "/dns-query", error).0.unwrap_err().to_string();
+        assert_eq!(message, "server answered HTTP 415 Unsupported Media Type");
+        let html = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 2\r\n\r\nhi";
+        assert!(send(Method::Get, "/", html).0.unwrap_err().to_string().contains("text/html"));
+        assert!(send(Method::Get, "/", "SSH-2.0-OpenSSH\r\n").0.is_err());
+    }
+
+    #[test]
+    fn test_oversized_bodies() {
+        let huge = "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: 18446744073709551615\r\n\r\n";
+        assert!(send(Method::Get, "/", huge).0.unwrap_err().to_string().contains("too long"));
+        let long = "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: 65536\r\n\r\n";
+        assert!(send(Method::Get, "/", long).0.unwrap_err().to_string().contains("too long"));
+        // the sizes add up past the limit, the last one on its own would overflow
+        let chunks = "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nTransfer-Encoding: chunked\r\n\r\n\
+2\r\nab\r\nffffffffffffffff\r\n";
+        assert!(send(Method::Get, "/", chunks).0.unwrap_err().to_string().contains("too long"));
+    }
+}
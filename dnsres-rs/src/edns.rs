@@ -0,0 +1,273 @@
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use std::fmt;
+use std::io::{ Cursor, Error };
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr };
+
+use crate::message::{ malformed, Message, ResourceRecord };
+use crate::rdata::{ RData, RecordType };
+
+pub const DEFAULT_UDP_SIZE: u16 = 1232; // DNS flag day 2020 recommendation
+const PADDING_BLOCK: usize = 128; // query block length from RFC 8467
+
+const OPTION_NSID: u16 = 3;
+const OPTION_CLIENT_SUBNET: u16 = 8;
+const OPTION_PADDING: u16 = 12;
+
+// the OPT pseudo-record (RFC 6891) in decoded form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    pub udp_size: u16, // carried in the record's class
+    pub extended_rcode: u8, // upper 8 bits of the 12-bit rcode
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EdnsOption {
+    Nsid(Vec<u8>), // empty in queries, the server's identifier in responses
+    ClientSubnet {
+        source_prefix: u8,
+        scope_prefix: u8,
+        address: IpAddr,
+    },
+    Padding(usize),
+    Unknown(u16, Vec<u8>),
+}
+
+impl Edns {
+    pub fn new(udp_size: u16) -> Edns {
+        Edns {
+            udp_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![],
+        }
+    }
+
+    pub fn to_record(&self) -> ResourceRecord {
+        let mut ttl = ((self.extended_rcode as u32) << 24) | ((self.version as u32) << 16);
+        if self.dnssec_ok {
+            ttl |= 0x8000;
+        }
+        ResourceRecord {
+            name: String::new(), // root
+            rtype: RecordType::OPT,
+            class: self.udp_size,
+            ttl,
+            rdata: RData::OPT(self.options.clone()),
+        }
+    }
+
+    pub fn from_record(record: &ResourceRecord) -> Option<Edns> {
+        let options = match &record.rdata {
+            RData::OPT(options) => options.clone(),
+            _ => {
+                return None;
+            }
+        };
+        Some(Edns {
+            udp_size: record.class,
+            extended_rcode: (record.ttl >> 24) as u8,
+            version: (record.ttl >> 16) as u8,
+            dnssec_ok: record.ttl & 0x8000 != 0,
+            options,
+        })
+    }
+
+    // swaps any padding option for one that brings the message to a multiple of the block size
+    pub fn pad(&mut self, unpadded_len: usize) {
+        self.options.retain(|o| !matches!(o, EdnsOption::Padding(_)));
+        // the padding option header itself takes 4 bytes
+        let len = unpadded_len + 4;
+        let padding = (PADDING_BLOCK - (len % PADDING_BLOCK)) % PADDING_BLOCK;
+        self.options.push(EdnsOption::Padding(padding));
+    }
+}
+
+impl Message {
+    pub fn edns(&self) -> Option<Edns> {
+        self.additionals
+            .iter()
+            .find(|r| r.rtype == RecordType::OPT)
+            .and_then(Edns::from_record)
+    }
+
+    // the full 12-bit rcode, combining the header and OPT record parts
+    pub fn rcode(&self) -> u16 {
+        let extended = self.edns().map(|e| e.extended_rcode).unwrap_or(0) as u16;
+        (extended << 4) | (self.header.rcode() as u16)
+    }
+}
+
+pub fn parse_options(bytes: &[u8]) -> Result<Vec<EdnsOption>, Error> {
+    let mut cursor = Cursor::new(bytes);
+    let mut options = vec![];
+    while (cursor.position() as usize) < bytes.len() {
+        let code = cursor.read_u16::<BigEndian>()?;
+        let len = cursor.read_u16::<BigEndian>()? as usize;
+        let start = cursor.position() as usize;
+        let data = bytes
+            .get(start..start + len)
+            .ok_or_else(|| malformed("EDNS option runs past OPT record data"))?;
+        cursor.set_position((start + len) as u64);
+
+        options.push(match code {
+            OPTION_NSID => EdnsOption::Nsid(data.to_vec()),
+            OPTION_CLIENT_SUBNET => parse_client_subnet(data)?,
+            OPTION_PADDING => EdnsOption::Padding(data.len()),
+            _ => EdnsOption::Unknown(code, data.to_vec()),
+        });
+    }
+    Ok(options)
+}
+
+pub fn write_options(bytes: &mut Vec<u8>, options: &[EdnsOption]) -> Result<(), Error> {
+    for option in options {
+        let (code, data) = match option {
+            EdnsOption::Nsid(id) => (OPTION_NSID, id.clone()),
+            EdnsOption::ClientSubnet { source_prefix, scope_prefix, address } => {
+                let (family, octets) = match address {
+                    IpAddr::V4(ip) => (1u16, ip.octets().to_vec()),
+                    IpAddr::V6(ip) => (2u16, ip.octets().to_vec()),
+                };
+                // only the bytes covered by the source prefix are sent, the bits past it zeroed (RFC 7871 6)
+                let keep = (*source_prefix as usize).div_ceil(8).min(octets.len());
+                let mut address = octets[..keep].to_vec();
+                if let Some(last) = address.last_mut() {
+                    let spare = keep * 8 - (*source_prefix as usize).min(keep * 8);
+                    *last &= 0xffu8 << spare;
+                }
+                let mut data = vec![];
+                data.write_u16::<BigEndian>(family)?;
+                data.push(*source_prefix);
+                data.push(*scope_prefix);
+                data.extend(address);
+                (OPTION_CLIENT_SUBNET, data)
+            }
+            EdnsOption::Padding(len) => (OPTION_PADDING, vec![0u8; *len]),
+            EdnsOption::Unknown(code, data) => (*code, data.clone()),
+        };
+        bytes.write_u16::<BigEndian>(code)?;
+        bytes.write_u16::<BigEndian>(data.len() as u16)?;
+        bytes.extend(data);
+    }
+    Ok(())
+}
+
+fn parse_client_subnet(data: &[u8]) -> Result<EdnsOption, Error> {
+    if data.len() < 4 {
+        return Err(malformed("client subnet option too short"));
+    }
+    let family = u16::from_be_bytes([data[0], data[1]]);
+    let address = &data[4..];
+    let address = match family {
+        1 if address.len() <= 4 => {
+            let mut octets = [0u8; 4];
+            octets[..address.len()].copy_from_slice(address);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        2 if address.len() <= 16 => {
+            let mut octets = [0u8; 16];
+            octets[..address.len()].copy_from_slice(address);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => {
+            return Err(malformed("client subnet option with bad family or address"));
+        }
+    };
+    Ok(EdnsOption::ClientSubnet { source_prefix: data[2], scope_prefix: data[3], address })
+}
+
+// parses the +subnet=<address>/<prefix> argument
+pub fn parse_subnet(value: &str) -> Result<EdnsOption, String> {
+    let (address, prefix) = match value.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (value, None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| format!("invalid subnet: {}", value))?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    let source_prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().map_err(|_| format!("invalid subnet: {}", value))?,
+        None => max,
+    };
+    if source_prefix > max {
+        return Err(format!("invalid subnet prefix: {}", value));
+    }
+    Ok(EdnsOption::ClientSubnet { source_prefix, scope_prefix: 0, address })
+}
+
+impl fmt::Display for EdnsOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EdnsOption::Nsid(id) => {
+                let hex: Vec<String> = id.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "NSID: {} (\"{}\")", hex.join(" "), String::from_utf8_lossy(id))
+            }
+            EdnsOption::ClientSubnet { source_prefix, scope_prefix, address } => {
+                write!(f, "CLIENT-SUBNET: {}/{}/{}", address, source_prefix, scope_prefix)
+            }
+            EdnsOption::Padding(len) => write!(f, "PADDING: {} bytes", len),
+            EdnsOption::Unknown(code, data) => {
+                let hex: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "OPT={}: {}", code, hex.join(" "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Edns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = if self.dnssec_ok { " do" } else { "" };
+        write!(f, "; EDNS: version: {}, flags:{}; udp: {}", self.version, flags, self.udp_size)?;
+        for option in self.options.iter() {
+            write!(f, "\n; {}", option)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut edns = Edns::new(4096);
+        edns.dnssec_ok = true;
+        edns.extended_rcode = 1;
+        edns.options = vec![
+            EdnsOption::Nsid(b"ns1".to_vec()),
+            parse_subnet("192.0.2.0/24").unwrap(),
+            EdnsOption::Padding(3),
+            EdnsOption::Unknown(65001, vec![1, 2]),
+        ];
+        let record = edns.to_record();
+        assert_eq!(record.ttl, 0x0100_8000);
+
+        let bytes = record.to_bytes().unwrap();
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let decoded = ResourceRecord::from_bytes(&mut cursor).unwrap();
+        assert_eq!(Edns::from_record(&decoded).unwrap(), edns);
+    }
+
+    #[test]
+    fn test_client_subnet_truncates_address() {
+        let mut bytes = vec![];
+        write_options(&mut bytes, &[parse_subnet("198.51.100.77/20").unwrap()]).unwrap();
+        // code, length, family, source, scope, then only 3 address bytes with the last 4 bits cleared
+        assert_eq!(bytes, vec![0, 8, 0, 7, 0, 1, 20, 0, 198, 51, 96]);
+        let mut bytes = vec![];
+        write_options(&mut bytes, &[parse_subnet("2001:db8:abcd::1/41").unwrap()]).unwrap();
+        assert_eq!(&bytes[8..], &[0x20, 0x01, 0x0d, 0xb8, 0xab, 0x80]);
+        assert!(parse_subnet("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn test_padding_reaches_block_size() {
+        let mut edns = Edns::new(DEFAULT_UDP_SIZE);
+        edns.pad(61);
+        assert_eq!(edns.options, vec![EdnsOption::Padding(63)]);
+    }
+}
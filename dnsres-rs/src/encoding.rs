@@ -0,0 +1,111 @@
+// text encodings used by presentation formats: hex (DS, NSEC3 salt),
+// base64 (keys, signatures) and base32hex (NSEC3 owner hashes, RFC 4648 7),
+// plus the unpadded base64url that DoH GET requests carry (RFC 4648 5)
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE32HEX: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+pub fn to_base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub fn to_base64url(bytes: &[u8]) -> String {
+    to_base64(bytes).trim_end_matches('=').replace('+', "-").replace('/', "_")
+}
+
+pub fn from_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = vec![];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        acc = (acc << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+pub fn to_base32hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for &b in bytes {
+        acc = (acc << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32HEX[((acc >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32HEX[((acc << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+pub fn from_base32hex(text: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let value = BASE32HEX.iter().position(|&b| b == c.to_ascii_lowercase())? as u32;
+        acc = (acc << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc4648_vectors() {
+        let cases = [("", "", ""), ("f", "Zg==", "co"), ("fo", "Zm8=", "cpng"), ("foobar", "Zm9vYmFy", "cpnmuoj1e8")];
+        for (plain, base64, base32hex) in cases {
+            assert_eq!(to_base64(plain.as_bytes()), base64);
+            assert_eq!(from_base64(base64).unwrap(), plain.as_bytes());
+            assert_eq!(to_base32hex(plain.as_bytes()), base32hex);
+            assert_eq!(from_base32hex(&base32hex.to_uppercase()).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(to_base64url(&[0xfb, 0xff, 0x00, 0x01]), "-_8AAQ");
+        assert_eq!(from_hex("00fF").unwrap(), vec![0, 255]);
+        assert!(from_hex("abc").is_none());
+        assert!(from_base64("a*b").is_none());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::io::{ Error, ErrorKind };
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::message::ResourceRecord;
+use crate::rdata::{ RData, RecordType };
+use crate::reverse::reverse_name;
+
+pub const HOSTS: &str = "/etc/hosts";
+
+// static host table in hosts(5) format: an address, its canonical name, then aliases
+#[derive(Debug, Clone, Default)]
+pub struct Hosts {
+    entries: Vec<(IpAddr, Vec<String>)>,
+}
+
+impl Hosts {
+    // a missing file is just an empty table
+    pub fn load(path: &Path) -> Result<Hosts, Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Hosts::parse(&text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Hosts::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(text: &str) -> Hosts {
+        let mut entries = vec![];
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let address = match words.next().and_then(|w| w.parse().ok()) {
+                Some(address) => address,
+                None => continue,
+            };
+            let names: Vec<String> = words.map(|w| w.trim_end_matches('.').to_ascii_lowercase()).collect();
+            if !names.is_empty() {
+                entries.push((address, names));
+            }
+        }
+        Hosts { entries }
+    }
+
+    // A, AAAA or PTR records for the name, with a zero TTL since they aren't ours to cache
+    pub fn lookup(&self, name: &str, qtype: RecordType) -> Vec<ResourceRecord> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut records = vec![];
+        for (address, names) in self.entries.iter() {
+            let rdata = match (qtype, address) {
+                (RecordType::A, IpAddr::V4(ip)) if names.contains(&name) => RData::A(*ip),
+                (RecordType::AAAA, IpAddr::V6(ip)) if names.contains(&name) => RData::AAAA(*ip),
+                (RecordType::PTR, _) if reverse_name(*address) == name => RData::PTR(names[0].clone()),
+                _ => continue,
+            };
+            if !records.iter().any(|r: &ResourceRecord| r.rdata == rdata) {
+                records.push(ResourceRecord { name: name.clone(), rtype: qtype, class: 1, ttl: 0, rdata });
+            }
+        }
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = "\
+127.0.0.1   localhost
+::1         localhost ip6-localhost
+192.0.2.7   build.lab.example build   # the ci box
+192.0.2.8   build.lab.example
+not-an-ip   ignored
+";
+
+    #[test]
+    fn test_lookup() {
+        let hosts = Hosts::parse(FIXTURE);
+        let addresses = |name, qtype| -> Vec<String> {
+            hosts.lookup(name, qtype).iter().map(|r| r.rdata.to_string()).collect()
+        };
+        assert_eq!(addresses("BUILD", RecordType::A), vec!["192.0.2.7"]);
+        assert_eq!(addresses("build.lab.example.", RecordType::A), vec!["192.0.2.7", "192.0.2.8"]);
+        assert_eq!(addresses("localhost", RecordType::AAAA), vec!["::1"]);
+        assert_eq!(addresses("7.2.0.192.in-addr.arpa", RecordType::PTR), vec!["build.lab.example."]);
+        assert!(addresses("ignored", RecordType::A).is_empty());
+        assert!(addresses("build", RecordType::MX).is_empty());
+    }
+}
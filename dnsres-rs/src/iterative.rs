@@ -0,0 +1,467 @@
+use std::io::Error;
+use std::net::{ IpAddr, Ipv4Addr, SocketAddr };
+use std::time::{ Duration, Instant };
+use crate::message::{ rcode_name, Message, Question, ResourceRecord };
+use crate::rdata::{ RData, RecordType };
+use crate::transport::{ self, Client };
+
+// a.root-servers.net through m.root-servers.net
+pub const ROOT_HINTS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+const MAX_CNAME_CHAIN: usize = 8;
+
+// walks the delegation chain itself instead of asking a recursive upstream
+pub struct Iterative {
+    pub roots: Vec<IpAddr>,
+    pub port: u16, // every authority is queried on this port
+    pub client: Client,
+    pub max_queries: usize, // budget for one lookup, including out-of-band NS lookups
+    pub steps: Vec<Step>, // every query the last resolve sent, in order
+}
+
+// one query made on the way down, kept so the delegation path can be shown afterwards
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub zone: String, // what the server was asked as an authority for, "" for the root
+    pub server: SocketAddr,
+    pub question: Question,
+    pub depth: usize, // 0 for the lookup itself, more inside the nameserver lookups it needed
+    pub elapsed: Duration,
+    pub response: Result<Message, String>,
+}
+
+// bookkeeping shared by a lookup and the NS lookups it triggers
+struct Walk {
+    queries: usize,
+    in_progress: Vec<(String, RecordType)>,
+}
+
+impl Iterative {
+    pub fn new(client: Client) -> Iterative {
+        Iterative {
+            roots: ROOT_HINTS.iter().map(|ip| IpAddr::V4(*ip)).collect(),
+            port: 53,
+            client,
+            max_queries: 64,
+            steps: vec![],
+        }
+    }
+
+    // returns the final response (with any CNAME chain prepended) and the server that gave it
+    pub fn resolve(
+        &mut self,
+        name: &str,
+        qtype: RecordType,
+        qclass: u16
+    ) -> Result<(Message, SocketAddr), Error> {
+        let mut walk = Walk { queries: 0, in_progress: vec![] };
+        self.steps.clear();
+        self.lookup(name, qtype, qclass, &mut walk)
+    }
+
+    fn lookup(
+        &mut self,
+        name: &str,
+        qtype: RecordType,
+        qclass: u16,
+        walk: &mut Walk
+    ) -> Result<(Message, SocketAddr), Error> {
+        let key = (name.to_ascii_lowercase(), qtype);
+        if walk.in_progress.contains(&key) {
+            return Err(failure(&format!("lookup loop on {} {}", name, qtype)));
+        }
+        walk.in_progress.push(key);
+        let result = self.follow_chain(name, qtype, qclass, walk);
+        walk.in_progress.pop();
+        result
+    }
+
+    fn follow_chain(
+        &mut self,
+        name: &str,
+        qtype: RecordType,
+        qclass: u16,
+        walk: &mut Walk
+    ) -> Result<(Message, SocketAddr), Error> {
+        let mut chain: Vec<ResourceRecord> = vec![];
+        let mut seen = vec![name.to_ascii_lowercase()];
+        let mut current = name.to_string();
+
+        loop {
+            let (mut response, server) = self.descend(&current, qtype, qclass, walk)?;
+
+            let cname = response.answers
+                .iter()
+                .find(|r| r.rtype == RecordType::CNAME && same_name(&r.name, &current))
+                .cloned();
+            let answered = response.answers
+                .iter()
+                .any(|r| r.rtype == qtype && same_name(&r.name, &current));
+
+            match cname {
+                Some(record) if !answered && qtype != RecordType::CNAME => {
+                    let target = match &record.rdata {
+                        RData::CNAME(target) => target.clone(),
+                        _ => return Err(failure("CNAME record without a target")),
+                    };
+                    if seen.contains(&target.to_ascii_lowercase()) {
+                        return Err(failure(&format!("CNAME loop at {}", target)));
+                    }
+                    if chain.len() >= MAX_CNAME_CHAIN {
+                        return Err(failure("CNAME chain too long"));
+                    }
+                    seen.push(target.to_ascii_lowercase());
+                    chain.push(record);
+                    current = target;
+                }
+                _ => {
+                    chain.append(&mut response.answers);
+                    response.answers = chain;
+                    response.header.ancount = response.answers.len() as u16;
+                    return Ok((response, server));
+                }
+            }
+        }
+    }
+
+    // starts at the roots and follows referrals until some server answers for the name
+    fn descend(
+        &mut self,
+        name: &str,
+        qtype: RecordType,
+        qclass: u16,
+        walk: &mut Walk
+    ) -> Result<(Message, SocketAddr), Error> {
+        let mut servers: Vec<IpAddr> = self.roots.clone();
+        let mut zone = String::new(); // root
+
+        loop {
+            let (response, server) = self.query_any(&servers, &zone, name, qtype, qclass, walk)?;
+
+            let child = match referral(&response) {
+                Some(child) => child.to_ascii_lowercase(),
+                None => return Ok((response, server)),
+            };
+            // a referral has to move us strictly closer to the name
+            if !is_subdomain(name, &child) || !is_subdomain(&child, &zone) || child == zone {
+                return Err(
+                    failure(&format!("bad referral from {} to '{}' while in '{}'", server, child, zone))
+                );
+            }
+            zone = child;
+
+            let ns_names: Vec<String> = response.authorities
+                .iter()
+                .filter_map(|r| match &r.rdata {
+                    RData::NS(ns) if same_name(&r.name, &zone) => Some(ns.clone()),
+                    _ => None,
+                })
+                .collect();
+            servers = glue(&response, &ns_names, &zone);
+
+            // missing glue: resolve the nameserver names out of band
+            if servers.is_empty() {
+                for ns in ns_names.iter() {
+                    if let Ok((answer, _)) = self.lookup(ns, RecordType::A, qclass, walk) {
+                        servers.extend(addresses(&answer));
+                    }
+                    if !servers.is_empty() {
+                        break;
+                    }
+                }
+            }
+            if servers.is_empty() {
+                return Err(failure(&format!("no reachable nameserver for zone '{}'", zone)));
+            }
+        }
+    }
+
+    // asks each server in turn; SERVFAIL and REFUSED count as lame and move on
+    fn query_any(
+        &mut self,
+        servers: &[IpAddr],
+        zone: &str,
+        name: &str,
+        qtype: RecordType,
+        qclass: u16,
+        walk: &mut Walk
+    ) -> Result<(Message, SocketAddr), Error> {
+        let mut last_error = failure("no servers to query");
+        for ip in servers {
+            walk.queries += 1;
+            if walk.queries > self.max_queries {
+                return Err(failure("query budget exhausted"));
+            }
+
+            let server = SocketAddr::new(*ip, self.port);
+            let question = Question { qname: name.to_string(), qtype, qclass };
+            let query = transport::build_query(question.clone(), false, None)?;
+            let started = Instant::now();
+            let result = self.client.send(&query, server);
+            self.steps.push(Step {
+                zone: zone.to_string(),
+                server,
+                question,
+                depth: walk.in_progress.len().saturating_sub(1),
+                elapsed: started.elapsed(),
+                response: result.as_ref().cloned().map_err(|e| e.to_string()),
+            });
+            match result {
+                Ok(response) if matches!(response.header.rcode(), 2 | 5) => {
+                    let rcode = rcode_name(response.header.rcode() as u16);
+                    last_error = failure(&format!("{} answered {}", server, rcode));
+                }
+                Ok(response) => {
+                    return Ok((response, server));
+                }
+                Err(e) => {
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+// the zone a non-authoritative, answerless NOERROR hands us down to, if that's what it is
+pub fn referral(response: &Message) -> Option<&str> {
+    if !response.answers.is_empty() || response.header.rcode() != 0 || response.header.flags & 0x0400 != 0 {
+        return None;
+    }
+    response.authorities.iter().find(|r| r.rtype == RecordType::NS).map(|r| r.name.as_str())
+}
+
+// addresses from the additional section belonging to the given nameservers. Only names inside
+// the zone being delegated count: the parent has no say over addresses anywhere else.
+fn glue(response: &Message, ns_names: &[String], zone: &str) -> Vec<IpAddr> {
+    ns_names
+        .iter()
+        .filter(|ns| is_subdomain(ns, zone))
+        .flat_map(|ns| {
+            response.additionals.iter().filter_map(move |r| {
+                if !same_name(&r.name, ns) {
+                    return None;
+                }
+                match r.rdata {
+                    RData::A(ip) => Some(IpAddr::V4(ip)),
+                    RData::AAAA(ip) => Some(IpAddr::V6(ip)),
+                    _ => None,
+                }
+            })
+        })
+        .collect()
+}
+
+// A records in an answer, regardless of the CNAME chain that led there
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response.answers
+        .iter()
+        .filter_map(|r| match r.rdata {
+            RData::A(ip) => Some(IpAddr::V4(ip)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+// true if name equals zone or sits below it; "" is the root
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    if zone.is_empty() {
+        return true;
+    }
+    let name = name.to_ascii_lowercase();
+    let zone = zone.to_ascii_lowercase();
+    name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+fn failure(reason: &str) -> Error {
+    Error::other(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Header;
+    use std::net::UdpSocket;
+    use std::thread;
+    use crate::transport::RetryPolicy;
+    use std::time::Duration;
+
+    fn record(name: &str, rdata: RData) -> ResourceRecord {
+        let rtype = match rdata {
+            RData::A(_) => RecordType::A,
+            RData::NS(_) => RecordType::NS,
+            RData::CNAME(_) => RecordType::CNAME,
+            _ => unreachable!(),
+        };
+        ResourceRecord { name: name.to_string(), rtype, class: 1, ttl: 300, rdata }
+    }
+
+    fn a(name: &str, ip: [u8; 4]) -> ResourceRecord {
+        record(name, RData::A(ip.into()))
+    }
+
+    fn ns(zone: &str, host: &str) -> ResourceRecord {
+        record(zone, RData::NS(host.to_string()))
+    }
+
+    // (answers, authorities, additionals, authoritative, rcode)
+    type Reply = (Vec<ResourceRecord>, Vec<ResourceRecord>, Vec<ResourceRecord>, bool, u16);
+
+    // stand-in authority answering on loopback with whatever the handler returns
+    fn spawn(ip: [u8; 4], port: u16, handler: fn(&Question) -> Reply) -> u16 {
+        let socket = UdpSocket::bind((Ipv4Addr::from(ip), port)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer) {
+                let query = Message::from_bytes(&buffer[..len]).unwrap();
+                let (answers, authorities, additionals, aa, rcode) = handler(&query.questions[0]);
+                let reply = Message {
+                    header: Header {
+                        flags: 0x8000 | (if aa { 0x0400 } else { 0 }) | rcode,
+                        ..query.header.clone()
+                    },
+                    questions: query.questions,
+                    answers,
+                    authorities,
+                    additionals,
+                };
+                socket.send_to(&reply.to_bytes().unwrap(), peer).unwrap();
+            }
+        });
+        port
+    }
+
+    fn root(q: &Question) -> Reply {
+        if is_subdomain(&q.qname, "loop.test") {
+            return (vec![], vec![ns("loop.test", "ns.loop.test")], vec![], false, 0);
+        }
+        if is_subdomain(&q.qname, "com") {
+            return (vec![], vec![ns("com", "a.nic.com")], vec![a("a.nic.com", [127, 0, 0, 2])], false, 0);
+        }
+        (vec![], vec![], vec![], true, 3)
+    }
+
+    fn tld(q: &Question) -> Reply {
+        if is_subdomain(&q.qname, "example.com") {
+            let glue = vec![a("ns1.example.com", [127, 0, 0, 3])];
+            return (vec![], vec![ns("example.com", "ns1.example.com")], glue, false, 0);
+        }
+        if is_subdomain(&q.qname, "noglue.com") {
+            // nameserver lives in another zone, no glue given
+            return (vec![], vec![ns("noglue.com", "ns2.example.com")], vec![], false, 0);
+        }
+        if is_subdomain(&q.qname, "poison.com") {
+            // glue for a name com has no authority over, pointing nowhere
+            let glue = vec![a("ns2.example.com", [127, 0, 0, 9])];
+            return (vec![], vec![ns("poison.com", "ns2.example.com")], glue, false, 0);
+        }
+        (vec![], vec![], vec![], true, 3)
+    }
+
+    fn example(q: &Question) -> Reply {
+        match q.qname.as_str() {
+            "www.example.com" => {
+                (vec![record("www.example.com", RData::CNAME("web.noglue.com".to_string()))], vec![], vec![], true, 0)
+            }
+            "ns1.example.com" => (vec![a("ns1.example.com", [127, 0, 0, 3])], vec![], vec![], true, 0),
+            "ns2.example.com" => (vec![a("ns2.example.com", [127, 0, 0, 4])], vec![], vec![], true, 0),
+            _ => (vec![], vec![], vec![], true, 3),
+        }
+    }
+
+    fn noglue(q: &Question) -> Reply {
+        match q.qname.as_str() {
+            "web.noglue.com" => (vec![a("web.noglue.com", [192, 0, 2, 80])], vec![], vec![], true, 0),
+            _ => (vec![], vec![], vec![], true, 3),
+        }
+    }
+
+    fn resolver() -> Iterative {
+        let port = spawn([127, 0, 0, 1], 0, root);
+        spawn([127, 0, 0, 2], port, tld);
+        spawn([127, 0, 0, 3], port, example);
+        spawn([127, 0, 0, 4], port, noglue);
+        Iterative {
+            roots: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            port,
+            client: Client::new(RetryPolicy::new(Duration::from_millis(500), 0)),
+            max_queries: 32,
+            steps: vec![],
+        }
+    }
+
+    #[test]
+    fn test_follows_referrals_cname_and_missing_glue() {
+        let mut resolver = resolver();
+        let (response, server) = resolver.resolve("www.example.com", RecordType::A, 1).unwrap();
+        assert_eq!(server.ip(), IpAddr::V4(Ipv4Addr::new(127, 0, 0, 4)));
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[0].rtype, RecordType::CNAME);
+        assert_eq!(response.answers[1].rdata, RData::A([192, 0, 2, 80].into()));
+
+        // the nameserver lookup for noglue.com shows up one level down
+        let path: Vec<(&str, usize)> = resolver.steps.iter().map(|s| (s.zone.as_str(), s.depth)).collect();
+        assert_eq!(path, vec![
+            ("", 0),
+            ("com", 0),
+            ("example.com", 0),
+            ("", 0),
+            ("com", 0),
+            ("", 1),
+            ("com", 1),
+            ("example.com", 1),
+            ("noglue.com", 0),
+        ]);
+        assert_eq!(referral(resolver.steps[0].response.as_ref().unwrap()), Some("com"));
+        assert_eq!(referral(resolver.steps[2].response.as_ref().unwrap()), None);
+    }
+
+    #[test]
+    fn test_out_of_zone_glue_is_ignored() {
+        let mut resolver = resolver();
+        let (response, server) = resolver.resolve("www.poison.com", RecordType::A, 1).unwrap();
+        assert_eq!(response.header.rcode(), 3);
+        assert_eq!(server.ip(), IpAddr::V4(Ipv4Addr::new(127, 0, 0, 4)));
+        assert!(resolver.steps.iter().all(|s| s.server.ip() != IpAddr::V4(Ipv4Addr::new(127, 0, 0, 9))));
+    }
+
+    #[test]
+    fn test_nxdomain_is_returned() {
+        let mut resolver = resolver();
+        let (response, _) = resolver.resolve("missing.org", RecordType::A, 1).unwrap();
+        assert_eq!(response.header.rcode(), 3);
+    }
+
+    #[test]
+    fn test_glueless_self_delegation_is_detected() {
+        // ns.loop.test can only be found by asking ns.loop.test
+        let mut resolver = resolver();
+        assert!(resolver.resolve("www.loop.test", RecordType::A, 1).is_err());
+    }
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("www.Example.com", "example.COM"));
+        assert!(is_subdomain("com", ""));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+    }
+}
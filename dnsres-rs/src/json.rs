@@ -0,0 +1,163 @@
+use crate::encoding::to_hex;
+use crate::message::{ class_name, Message, Question, ResourceRecord };
+use crate::rdata::RData;
+
+// a JSON string literal, escaped per RFC 8259 7
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// members are (name, already encoded value)
+fn object(members: &[(&str, String)]) -> String {
+    let members: Vec<String> = members.iter().map(|(name, value)| format!("{}:{}", json_string(name), value)).collect();
+    format!("{{{}}}", members.join(","))
+}
+
+fn array(values: Vec<String>) -> String {
+    format!("[{}]", values.join(","))
+}
+
+fn fqdn(name: &str) -> String {
+    json_string(&format!("{}.", name))
+}
+
+// the whole message as an RFC 8427 object
+pub fn message_to_json(message: &Message) -> String {
+    let header = &message.header;
+    let flag = |bit: u16| (header.flags & bit != 0).to_string();
+    let mut members = vec![
+        ("ID", header.id.to_string()),
+        ("QR", flag(0x8000)),
+        ("Opcode", header.opcode().to_string()),
+        ("AA", flag(0x0400)),
+        ("TC", flag(0x0200)),
+        ("RD", flag(0x0100)),
+        ("RA", flag(0x0080)),
+        ("AD", flag(0x0020)),
+        ("CD", flag(0x0010)),
+        ("RCODE", message.rcode().to_string()),
+        ("QDCOUNT", header.qdcount.to_string()),
+        ("ANCOUNT", header.ancount.to_string()),
+        ("NSCOUNT", header.nscount.to_string()),
+        ("ARCOUNT", header.arcount.to_string()),
+    ];
+    // the first question is repeated at the top level for convenience (RFC 8427 2.1)
+    if let Some(question) = message.questions.first() {
+        members.push(("QNAME", fqdn(&question.qname)));
+        members.push(("QTYPE", question.qtype.to_u16().to_string()));
+        members.push(("QTYPEname", json_string(&question.qtype.to_string())));
+        members.push(("QCLASS", question.qclass.to_string()));
+        members.push(("QCLASSname", json_string(&class_name(question.qclass))));
+    }
+    members.push(("questionRRs", array(message.questions.iter().map(question_to_json).collect())));
+    let sections = [
+        ("answerRRs", &message.answers),
+        ("authorityRRs", &message.authorities),
+        ("additionalRRs", &message.additionals),
+    ];
+    for (name, records) in sections {
+        members.push((name, array(records.iter().map(record_to_json).collect())));
+    }
+    object(&members)
+}
+
+fn question_to_json(question: &Question) -> String {
+    object(&[
+        ("NAME", fqdn(&question.qname)),
+        ("TYPE", question.qtype.to_u16().to_string()),
+        ("TYPEname", json_string(&question.qtype.to_string())),
+        ("CLASS", question.qclass.to_string()),
+        ("CLASSname", json_string(&class_name(question.qclass))),
+    ])
+}
+
+// RDATAHEX is always there so consumers can decode types we only know as presentation text
+pub fn record_to_json(record: &ResourceRecord) -> String {
+    let rdata = record.rdata.to_bytes().unwrap_or_default();
+    let mut members = vec![
+        ("NAME", fqdn(&record.name)),
+        ("TYPE", record.rtype.to_u16().to_string()),
+        ("TYPEname", json_string(&record.rtype.to_string())),
+        ("CLASS", record.class.to_string()),
+        ("CLASSname", json_string(&class_name(record.class))),
+        ("TTL", record.ttl.to_string()),
+        ("RDLENGTH", rdata.len().to_string()),
+        ("RDATAHEX", json_string(&to_hex(&rdata).to_uppercase())),
+    ];
+    let typed = format!("rdata{}", record.rtype);
+    match &record.rdata {
+        RData::A(ip) => members.push((&typed, json_string(&ip.to_string()))),
+        RData::AAAA(ip) => members.push((&typed, json_string(&ip.to_string()))),
+        RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => members.push((&typed, fqdn(name))),
+        RData::OPT(_) | RData::Unknown(_) => {} // nothing to add beyond RDATAHEX
+        other => members.push((&typed, json_string(&other.to_string()))),
+    }
+    object(&members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Header;
+    use crate::rdata::RecordType;
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}é"), "\"a\\\"b\\\\c\\n\\u0001é\"");
+    }
+
+    #[test]
+    fn test_rfc8427_message() {
+        let question = Question { qname: "example.com".to_string(), qtype: RecordType::MX, qclass: 1 };
+        let message = Message {
+            header: Header { id: 7, flags: 0x8180, qdcount: 1, ancount: 2, nscount: 0, arcount: 0 },
+            questions: vec![question],
+            answers: vec![
+                ResourceRecord {
+                    name: "example.com".to_string(),
+                    rtype: RecordType::MX,
+                    class: 1,
+                    ttl: 300,
+                    rdata: RData::MX { preference: 10, exchange: "mail.example.com".to_string() },
+                },
+                ResourceRecord {
+                    name: "mail.example.com".to_string(),
+                    rtype: RecordType::A,
+                    class: 1,
+                    ttl: 60,
+                    rdata: RData::A([192, 0, 2, 25].into()),
+                },
+            ],
+            authorities: vec![],
+            additionals: vec![],
+        };
+        assert_eq!(
+            message_to_json(&message),
+            concat!(
+                "{\"ID\":7,\"QR\":true,\"Opcode\":0,\"AA\":false,\"TC\":false,\"RD\":true,\"RA\":true,",
+                "\"AD\":false,\"CD\":false,\"RCODE\":0,\"QDCOUNT\":1,\"ANCOUNT\":2,\"NSCOUNT\":0,\"ARCOUNT\":0,",
+                "\"QNAME\":\"example.com.\",\"QTYPE\":15,\"QTYPEname\":\"MX\",\"QCLASS\":1,\"QCLASSname\":\"IN\",",
+                "\"questionRRs\":[{\"NAME\":\"example.com.\",\"TYPE\":15,\"TYPEname\":\"MX\",\"CLASS\":1,\"CLASSname\":\"IN\"}],",
+                "\"answerRRs\":[",
+                "{\"NAME\":\"example.com.\",\"TYPE\":15,\"TYPEname\":\"MX\",\"CLASS\":1,\"CLASSname\":\"IN\",\"TTL\":300,",
+                "\"RDLENGTH\":20,\"RDATAHEX\":\"000A046D61696C076578616D706C6503636F6D00\",\"rdataMX\":\"10 mail.example.com.\"},",
+                "{\"NAME\":\"mail.example.com.\",\"TYPE\":1,\"TYPEname\":\"A\",\"CLASS\":1,\"CLASSname\":\"IN\",\"TTL\":60,",
+                "\"RDLENGTH\":4,\"RDATAHEX\":\"C0000219\",\"rdataA\":\"192.0.2.25\"}",
+                "],\"authorityRRs\":[],\"additionalRRs\":[]}"
+            )
+        );
+    }
+}
@@ -0,0 +1,27 @@
+// DNS messages, transports, caching, zones and DNSSEC validation, plus a stub resolver
+// for embedding; the dnsres binary is a command line front end to these
+pub mod cache;
+pub mod decode;
+pub mod dnssec;
+pub mod doh;
+pub mod edns;
+pub mod encoding;
+pub mod hosts;
+pub mod iterative;
+pub mod json;
+pub mod mdns;
+pub mod message;
+pub mod name;
+pub mod rdata;
+pub mod resolvconf;
+pub mod resolver;
+pub mod reverse;
+pub mod server;
+pub mod tls;
+pub mod transport;
+pub mod tsig;
+pub mod update;
+pub mod xfr;
+pub mod zone;
+
+pub use resolver::{ Answer, ResolveError, Resolver, ResolverOptions };
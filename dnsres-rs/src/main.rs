@@ -1,109 +1,663 @@
-use byteorder::{ BigEndian, WriteBytesExt };
-use std::net::UdpSocket;
-use rand;
+mod bulk;
+mod cli;
+mod output;
 
-const DNS_ADDR: &str = "8.8.8.8";
-const DNS_PORT: u16 = 53;
-struct Header {
-    id: u16,
-    flags: u16,
-    qdcount: u16, // nb questions
-    ancount: u16, // nb answer resource records
-    nscount: u16, // nb authority resource records
-    arcount: u16, // nb additional resource records
-}
+use dnsres_rs::{ cache, decode, dnssec, doh, edns, hosts, iterative, json, mdns, message, name, rdata, resolvconf, reverse, server, tls, transport, tsig, update, xfr, zone };
+use message::{ Message, Question };
+use output::Source;
+use std::env;
+use std::fs::File;
+use std::io::{ self, BufRead, BufReader, Read };
+use std::net::{ SocketAddr, ToSocketAddrs };
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+const CACHE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let conf = load_resolv_conf(&cli::resolv_conf_path(&args));
+    match args.first().map(|a| a.as_str()) {
+        Some("serve") => return serve(&args[1..], &conf),
+        Some("bulk") => return bulk(&args[1..], &conf),
+        Some("mdns") => return multicast(&args[1..]),
+        Some("update") => return send_update(&args[1..], &conf),
+        Some("xfr") => return zone_transfer(&args[1..], &conf),
+        Some("decode") => return dissect(&args[1..]),
+        _ => {}
+    }
+
+    let options = match cli::parse_args(&args, &conf) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    // init
+    let question = Question {
+        qname: name::relative(&options.name).to_string(),
+        qtype: options.qtype,
+        qclass: options.qclass,
+    };
+    let names = if options.search {
+        conf.candidates(&options.name)
+    } else {
+        vec![question.qname.clone()]
+    };
+
+    // the hosts file is consulted before any server, like the system resolver does
+    if let Some(path) = &options.hosts_file {
+        let hosts = hosts::Hosts::load(path).unwrap_or_else(|e| {
+            eprintln!(";; Ignoring unreadable hosts file {}: {}", path.display(), e);
+            hosts::Hosts::default()
+        });
+        let records = hosts.lookup(&question.qname, question.qtype);
+        if question.qclass == 1 && !records.is_empty() {
+            let response = cache::synthesize(&question, 0, records, vec![]);
+            output::print_response(&response, &options, Source::Hosts, Duration::ZERO);
+            return;
+        }
+    }
+
+    let mut policy = transport::RetryPolicy::new(options.timeout, options.retries);
+    policy.backoff = options.backoff;
+
+    let mut client = transport::Client::new(policy);
+    client.force_tcp = options.tcp;
+    client.randomize_case = options.randomize_case;
+
+    if options.iterative {
+        let mut resolver = iterative::Iterative::new(client);
+        let started = Instant::now();
+        let mut result = resolver.resolve(&names[0], question.qtype, question.qclass);
+        if options.trace {
+            output::print_trace(&resolver.steps);
+        }
+        for name in names[1..].iter() {
+            if matches!(&result, Ok((response, _)) if has_answer(response)) {
+                break;
+            }
+            result = resolver.resolve(name, question.qtype, question.qclass);
+            if options.trace {
+                output::print_trace(&resolver.steps);
+            }
+        }
+        match result {
+            // the trace already ended with the answer
+            Ok(_) if options.trace => {}
+            Ok((response, server)) => {
+                output::print_response(&response, &options, Source::Server(server), started.elapsed());
+            }
+            Err(e) => {
+                eprintln!(";; Iterative resolution of {} failed: {}", question.qname, e);
+                std::process::exit(1);
+            }
+        }
+        // authorities are asked without DO, so there are no signatures to check
+        if options.validate {
+            output::print_not_validated("answers found with +iterative are not checked", &options);
+        }
+        return;
+    }
+
+    let mut cache = match &options.cache_file {
+        Some(path) =>
+            match cache::Cache::load(path) {
+                Ok(cache) => Some(cache),
+                Err(e) => {
+                    eprintln!(";; Ignoring unreadable cache {}: {}", path.display(), e);
+                    Some(cache::Cache::new())
+                }
+            }
+        None => None,
+    };
 
-struct Question {
-    qname: String, // name being looked up
-    qtype: u16, // record type
-    qclass: u16, // class
+    let named = named_servers(&options.servers, options.port);
+    let servers: Vec<SocketAddr> = named.iter().map(|(server, _)| *server).collect();
+    if servers.is_empty() {
+        eprintln!(";; No usable servers");
+        std::process::exit(1);
+    }
+    if options.tls {
+        client.encryption = Some(encryption(&options, &named));
+    }
+
+    let key = options.key_file.as_deref().map(load_key);
+
+    // walk the search list until a name has an answer, showing the last reply otherwise
+    let mut outcome = None;
+    for name in names.iter() {
+        let question = Question { qname: name.clone(), ..question.clone() };
+        let (response, source, elapsed) = match lookup(&question, &options, key.as_ref(), &mut client, &servers, cache.as_mut()) {
+            Ok(answer) => answer,
+            Err(e) => {
+                eprintln!(";; Query failed: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let answered = has_answer(&response);
+        outcome = Some((response, source, elapsed));
+        if answered {
+            break;
+        }
+    }
+    let (response, source, elapsed) = match outcome {
+        Some(outcome) => outcome,
+        None => std::process::exit(1),
+    };
+
+    output::print_response(&response, &options, source, elapsed);
+    if options.validate {
+        let anchors = load_anchors(&options);
+        let upstream = dnssec::Upstream { client: &mut client, servers: &servers };
+        let security = dnssec::Validator::new(upstream, anchors).validate(&response);
+        output::print_security(&security, &options);
+    }
+    if let (Some(cache), Some(path)) = (cache.as_ref(), options.cache_file.as_ref()) {
+        if let Err(e) = cache.save(path) {
+            eprintln!(";; Failed to save cache {}: {}", path.display(), e);
+        }
+    }
 }
 
-impl Header {
-    fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
-        let mut bytes = vec![];
-        bytes.write_u16::<BigEndian>(self.id)?;
-        bytes.write_u16::<BigEndian>(self.flags)?;
-        bytes.write_u16::<BigEndian>(self.qdcount)?;
-        bytes.write_u16::<BigEndian>(self.ancount)?;
-        bytes.write_u16::<BigEndian>(self.nscount)?;
-        bytes.write_u16::<BigEndian>(self.arcount)?;
-        Ok(bytes)
+// one question against the cache, then the servers; fresh answers go into the cache
+fn lookup(
+    question: &Question,
+    options: &cli::Options,
+    key: Option<&tsig::Key>,
+    client: &mut transport::Client,
+    servers: &[SocketAddr],
+    mut cache: Option<&mut cache::Cache>
+) -> Result<(Message, Source, Duration), String> {
+    // the cache keeps no signatures, so answers to be validated always come from a server
+    if let Some(response) = cache.as_mut().filter(|_| !options.validate).and_then(|c| c.answer(question)) {
+        return Ok((response, Source::Cache, Duration::ZERO));
+    }
+
+    // convert
+    let mut message = transport::build_query(question.clone(), options.recursion_desired, options.edns.as_ref())
+        .map_err(|e| e.to_string())?;
+    if options.validate {
+        // we check the signatures ourselves, so ask for the data even if the server thinks it's bogus
+        dnssec::set_checking_disabled(&mut message);
+    }
+    // signing comes last, the MAC covers everything before it
+    let mut request_mac = vec![];
+    if let Some(key) = key {
+        (message, request_mac) = tsig::sign(&message, key, tsig::now(), &[]).map_err(|e| e.to_string())?;
+    }
+    if options.verbosity == cli::Verbosity::Verbose {
+        output::print_query(&message);
     }
+
+    let mut attempts = vec![];
+    let result = client.exchange_wire(&message, servers, &mut attempts);
+    output::print_attempts(&attempts, options);
+    let (response, wire, server) = result.map_err(|e| e.to_string())?;
+    if let Some(key) = key {
+        tsig::verify(&wire, key, &request_mac, tsig::now()).map_err(|e| e.to_string())?;
+    }
+    if let Some(cache) = cache {
+        cache.insert(question, &response);
+    }
+    // query time of the try that produced the answer
+    let elapsed = attempts.last().map(|a| a.elapsed).unwrap_or_default();
+    Ok((response, Source::Server(server), elapsed))
+}
+
+// NOERROR with something in the answer section, which ends a search list walk
+fn has_answer(response: &Message) -> bool {
+    response.header.rcode() == 0 && !response.answers.is_empty()
 }
-impl Question {
-    fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
-        let mut bytes = vec![];
-        for part in self.qname.split('.') {
-            bytes.push(part.len() as u8);
-            for b in part.bytes() {
-                bytes.push(b);
+
+fn bulk(args: &[String], conf: &resolvconf::ResolvConf) {
+    let options = match cli::parse_bulk_args(args, conf) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::BULK_USAGE);
+            std::process::exit(1);
+        }
+    };
+    let servers = resolve_servers(&options.servers, options.port);
+    if servers.is_empty() {
+        eprintln!(";; No usable servers");
+        std::process::exit(1);
+    }
+
+    let input: Box<dyn BufRead + Send> = match &options.input {
+        Some(path) =>
+            match File::open(path) {
+                Ok(file) => Box::new(BufReader::new(file)),
+                Err(e) => {
+                    eprintln!(";; Failed to open {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
             }
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+    let started = Instant::now();
+    let mut output = io::LineWriter::new(io::stdout());
+    match bulk::run(&options, &servers, input, &mut output) {
+        Ok((total, failed)) => {
+            eprintln!(";; {} names in {:.1} s, {} failed", total, started.elapsed().as_secs_f64(), failed);
+        }
+        Err(e) => {
+            eprintln!(";; Bulk resolution stopped: {}", e);
+            std::process::exit(1);
         }
-        bytes.push(0); // End of name
-        bytes.write_u16::<BigEndian>(self.qtype)?;
-        bytes.write_u16::<BigEndian>(self.qclass)?;
-        Ok(bytes)
     }
 }
 
-fn main() {
-    // init
-    let header = Header {
-        id: rand::random(),
-        flags: 0x0100, // standard query
-        qdcount: 1, // questions
-        ancount: 0, // answers
-        nscount: 0, // authority records
-        arcount: 0, // additional records
+fn send_update(args: &[String], conf: &resolvconf::ResolvConf) {
+    let options = match cli::parse_update_args(args, conf) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::UPDATE_USAGE);
+            std::process::exit(1);
+        }
     };
-    let question = Question {
-        qname: "www.example.com".to_string(),
-        qtype: 1, // A record
-        qclass: 1, // internet class
+    let script = match &options.script {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e)),
+        None => io::read_to_string(io::stdin()).map_err(|e| e.to_string()),
+    };
+    let update = match script.and_then(|script| update::Update::parse(&script, options.zone.as_deref())) {
+        Ok(update) => update,
+        Err(e) => {
+            eprintln!(";; Bad update: {}", e);
+            std::process::exit(1);
+        }
     };
+    let key = options.key_file.as_deref().map(load_key);
+    let servers = resolve_servers(&options.servers, options.port);
+    if servers.is_empty() {
+        eprintln!(";; No usable servers");
+        std::process::exit(1);
+    }
 
-    // convert
-    let message = match construct(header, question) {
-        Ok(message) => message,
+    let signed = update.to_bytes().and_then(|message| match &key {
+        Some(key) => tsig::sign(&message, key, tsig::now(), &[]),
+        None => Ok((message, vec![])),
+    });
+    let (message, request_mac) = match signed {
+        Ok(signed) => signed,
+        Err(e) => {
+            eprintln!(";; Failed to build the update: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut client = transport::Client::new(transport::RetryPolicy::new(options.timeout, options.retries));
+    client.force_tcp = options.tcp;
+    let mut attempts = vec![];
+    let started = Instant::now();
+    let (response, wire, server) = match client.exchange_wire(&message, &servers, &mut attempts) {
+        Ok(reply) => reply,
         Err(e) => {
+            eprintln!(";; Update failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let display = cli::Options::new(&update.zone, "", options.port);
+    output::print_response(&response, &display, Source::Server(server), started.elapsed());
+    if let Some(key) = &key {
+        if let Err(e) = tsig::verify(&wire, key, &request_mac, tsig::now()) {
+            eprintln!(";; Reply failed verification: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if response.rcode() != 0 {
+        std::process::exit(1);
+    }
+}
+
+fn zone_transfer(args: &[String], conf: &resolvconf::ResolvConf) {
+    let options = match cli::parse_xfr_args(args, conf) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::XFR_USAGE);
+            std::process::exit(1);
+        }
+    };
+    let key = options.key_file.as_deref().map(load_key);
+    let servers = resolve_servers(&options.servers, options.port);
+    if servers.is_empty() {
+        eprintln!(";; No usable servers");
+        std::process::exit(1);
+    }
+
+    // the zone file we already have is what an IXFR brings up to date
+    let existing = match &options.output {
+        Some(path) if options.ixfr && path.exists() => match zone::Zone::load(path, &options.zone) {
+            Ok(zone) => Some(zone),
+            Err(e) => {
+                eprintln!(";; Failed to load zone {}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    let file_serial = existing.as_ref().and_then(serial_of);
+    let serial = match (options.ixfr, options.serial, file_serial) {
+        (false, _, _) => None,
+        (true, Some(ours), Some(file)) if ours != file => {
+            eprintln!(";; +ixfr={} does not match serial {} in the zone file", ours, file);
+            std::process::exit(1);
+        }
+        (true, Some(serial), _) | (true, None, Some(serial)) => Some(serial),
+        (true, None, None) => {
+            eprintln!(";; No zone file with an SOA to start the IXFR from");
+            std::process::exit(1);
+        }
+    };
+
+    let started = Instant::now();
+    let mut transferred = None;
+    for &server in servers.iter() {
+        match xfr::transfer(&options.zone, serial, server, key.as_ref(), options.timeout) {
+            Ok(transfer) => {
+                transferred = Some((transfer, server));
+                break;
+            }
+            Err(e) => eprintln!(";; Transfer from {} failed: {}", server, e),
+        }
+    }
+    let (transfer, server) = transferred.unwrap_or_else(|| std::process::exit(1));
+
+    let records = match transfer {
+        xfr::Transfer::Full(records) => records,
+        xfr::Transfer::Incremental(diffs) => match existing {
+            Some(zone) => {
+                let mut records = zone.records;
+                for diff in diffs.iter() {
+                    if let Err(e) = diff.apply(&mut records) {
+                        eprintln!(";; Can't apply the changes from {}: {}", server, e);
+                        std::process::exit(1);
+                    }
+                }
+                records
+            }
+            // nothing to apply them to, so show what changed
+            None => {
+                output::print_diffs(&diffs);
+                eprintln!(";; {} changes from {} in {} msec", diffs.len(), server, started.elapsed().as_millis());
+                return;
+            }
+        },
+        xfr::Transfer::UpToDate(_) => {
+            eprintln!(";; {} is up to date at serial {}", options.zone, serial.unwrap_or_default());
             return;
         }
     };
+    let zone = zone::Zone { origin: options.zone.clone(), records };
+    let text = format!(
+        "; {} transferred from {} at serial {}\n{}",
+        zone.origin,
+        server,
+        serial_of(&zone).unwrap_or_default(),
+        zone.to_text()
+    );
+    match &options.output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, text) {
+                eprintln!(";; Failed to write {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", text),
+    }
+    eprintln!(";; {} records from {} in {} msec", zone.records.len(), server, started.elapsed().as_millis());
+}
 
-    send(&message, DNS_ADDR, DNS_PORT);
+fn serial_of(zone: &zone::Zone) -> Option<u32> {
+    match zone.soa().map(|soa| &soa.rdata) {
+        Some(rdata::RData::SOA { serial, .. }) => Some(*serial),
+        _ => None,
+    }
 }
 
-fn construct(header: Header, question: Question) -> Result<Vec<u8>, std::io::Error> {
-    let header_bytes = match header.to_bytes() {
-        Ok(bytes) => bytes,
+// exits 1 when anything in the message was flagged, so scripts can check captures
+fn dissect(args: &[String]) {
+    let options = match cli::parse_decode_args(args) {
+        Ok(options) => options,
         Err(e) => {
-            eprintln!("Failed to convert header to bytes: {}", e);
-            return Err(e);
+            eprintln!("{}\n{}", e, cli::DECODE_USAGE);
+            std::process::exit(1);
+        }
+    };
+    let read = match &options.input {
+        Some(path) => std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e)),
+        None => {
+            let mut bytes = vec![];
+            io::stdin().read_to_end(&mut bytes).map(|_| bytes).map_err(|e| e.to_string())
         }
     };
-    let question_bytes = match question.to_bytes() {
+    let bytes = read.and_then(|contents| {
+        let text = std::str::from_utf8(&contents);
+        match options.format {
+            Some(cli::DumpFormat::Raw) => Ok(contents),
+            Some(cli::DumpFormat::Hex) => decode::parse_hex(text.map_err(|_| "hex dump is not text".to_string())?),
+            // a binary message is all but never valid hex, so anything that reads as hex is taken for it
+            None => Ok(text.ok().and_then(|t| decode::parse_hex(t).ok()).unwrap_or(contents)),
+        }
+    });
+    let bytes = match bytes {
         Ok(bytes) => bytes,
         Err(e) => {
-            eprintln!("Failed to convert question to bytes: {}", e);
-            return Err(e);
+            eprintln!(";; Failed to read message: {}", e);
+            std::process::exit(1);
         }
     };
-    let mut message = header_bytes;
-    message.extend(question_bytes);
-    Ok(message)
+    let entries = decode::dissect(&bytes, options.tcp);
+    output::print_dissection(&entries);
+    let problems = entries.iter().filter(|e| matches!(e, decode::Entry::Problem(..))).count();
+    if problems > 0 {
+        eprintln!(";; {} problems in {} bytes", problems, bytes.len());
+        std::process::exit(1);
+    }
 }
 
-fn send(message: &[u8], address: &str, port: u16) {
-    let socket = match UdpSocket::bind("0.0.0.0:0") {
-        Ok(socket) => socket,
+fn multicast(args: &[String]) {
+    let options = match cli::parse_mdns_args(args) {
+        Ok(options) => options,
         Err(e) => {
-            eprintln!("Failed to bind socket: {}", e);
-            return;
+            eprintln!("{}\n{}", e, cli::MDNS_USAGE);
+            std::process::exit(1);
+        }
+    };
+    let interface = match options.interface.as_deref().map(mdns::interface_index).transpose() {
+        Ok(interface) => interface.unwrap_or(0),
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::MDNS_USAGE);
+            std::process::exit(1);
+        }
+    };
+    let group = mdns::group(options.ipv6, interface);
+
+    if options.browse {
+        match mdns::browse(&options.name, group, options.window) {
+            Ok(instances) => {
+                output::print_instances(&instances, options.verbosity);
+                eprintln!(";; {} instances of {}.", instances.len(), options.name);
+            }
+            Err(e) => {
+                eprintln!(";; Browsing {} failed: {}", options.name, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let question = Question { qname: options.name.clone(), qtype: options.qtype, qclass: 1 };
+    let responses = match mdns::query(&[question], group, options.window) {
+        Ok(responses) => responses,
+        Err(e) => {
+            eprintln!(";; Query to {} failed: {}", group, e);
+            std::process::exit(1);
         }
     };
-    match socket.send_to(&message, &format!("{}:{}", address, port)) {
-        Ok(_) => println!("Message sent successfully"),
-        Err(e) => eprintln!("Failed to send message: {}", e),
+    if responses.is_empty() {
+        eprintln!(";; No responders answered within {} msec", options.window.as_millis());
+        std::process::exit(1);
+    }
+    // every responder gets its own dig-style block
+    let mut display = cli::Options::new(&options.name, "", mdns::MDNS_PORT);
+    display.verbosity = options.verbosity;
+    for (i, response) in responses.iter().enumerate() {
+        if i > 0 && options.verbosity != cli::Verbosity::Short {
+            println!();
+        }
+        output::print_response(&response.message, &display, Source::Server(response.from), response.elapsed);
+    }
+}
+
+fn serve(args: &[String], conf: &resolvconf::ResolvConf) {
+    let options = match cli::parse_serve_args(args, conf) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::SERVE_USAGE);
+            std::process::exit(1);
+        }
     };
+
+    if !options.zones.is_empty() {
+        serve_zones(&options);
+        return;
+    }
+
+    let upstreams = resolve_servers(&options.upstreams, options.port);
+    if upstreams.is_empty() {
+        eprintln!("No usable upstream servers");
+        std::process::exit(1);
+    }
+    let cache = match &options.cache_file {
+        Some(path) => cache::Cache::load(path).unwrap_or_else(|e| {
+            eprintln!("Ignoring unreadable cache {}: {}", path.display(), e);
+            cache::Cache::new()
+        }),
+        None => cache::Cache::new(),
+    };
+
+    let policy = transport::RetryPolicy::new(options.timeout, options.retries);
+    let forwarder = Arc::new(server::Forwarder::new(upstreams.clone(), policy, cache));
+
+    // write the cache out now and then so a restart picks up where we left off
+    if let Some(path) = options.cache_file.clone() {
+        let forwarder = forwarder.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(CACHE_SAVE_INTERVAL);
+                if let Ok(cache) = forwarder.cache().lock() {
+                    if let Err(e) = cache.save(&path) {
+                        eprintln!("Failed to save cache {}: {}", path.display(), e);
+                    }
+                }
+            }
+        });
+    }
+
+    let server = match server::Server::bind(options.listen, forwarder) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", options.listen, e);
+            std::process::exit(1);
+        }
+    };
+    let listen = server.local_addr().unwrap_or(options.listen);
+    println!("Forwarding queries on {} to {:?}", listen, upstreams);
+    if let Err(e) = server.run() {
+        eprintln!("Server stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn serve_zones(options: &cli::ServeOptions) {
+    let mut zones = vec![];
+    for path in options.zones.iter() {
+        match zone::Zone::load(path, "") {
+            Ok(zone) => zones.push(zone),
+            Err(e) => {
+                eprintln!("Failed to load zone {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let origins: Vec<String> = zones.iter().map(|z| format!("{}.", z.origin)).collect();
+
+    let authority = Arc::new(zone::Authority { zones });
+    let server = match server::Server::bind(options.listen, authority) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", options.listen, e);
+            std::process::exit(1);
+        }
+    };
+    let listen = server.local_addr().unwrap_or(options.listen);
+    println!("Serving {} authoritatively on {}", origins.join(", "), listen);
+    if let Err(e) = server.run() {
+        eprintln!("Server stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn load_resolv_conf(path: &Path) -> resolvconf::ResolvConf {
+    resolvconf::ResolvConf::load(path).unwrap_or_else(|e| {
+        eprintln!(";; Ignoring unreadable {}: {}", path.display(), e);
+        resolvconf::ResolvConf::default()
+    })
+}
+
+fn load_key(path: &Path) -> tsig::Key {
+    tsig::Key::load(path).unwrap_or_else(|e| {
+        eprintln!(";; Failed to load TSIG key {}", e);
+        std::process::exit(1);
+    })
+}
+
+fn load_anchors(options: &cli::Options) -> Vec<message::ResourceRecord> {
+    let parsed = match &options.trust_anchor {
+        Some(path) =>
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))
+                .and_then(|text| zone::parse_records(&text, "")),
+        None => zone::parse_records(dnssec::ROOT_ANCHORS, ""),
+    };
+    match parsed {
+        Ok(anchors) => anchors,
+        Err(e) => {
+            eprintln!(";; Failed to load trust anchor {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn resolve_servers(servers: &[(String, Option<u16>)], default_port: u16) -> Vec<SocketAddr> {
+    named_servers(servers, default_port).into_iter().map(|(server, _)| server).collect()
+}
+
+// each server's address along with the name it was given as
+fn named_servers(servers: &[(String, Option<u16>)], default_port: u16) -> Vec<(SocketAddr, String)> {
+    let mut resolved = vec![];
+    for (host, port) in servers.iter() {
+        match (host.as_str(), port.unwrap_or(default_port)).to_socket_addrs() {
+            Ok(mut addrs) => resolved.extend(addrs.next().map(|addr| (addr, host.clone()))),
+            Err(e) => eprintln!(";; Failed to resolve server {}: {}", host, e),
+        }
+    }
+    resolved
+}
+
+// certificates have to be issued to the name each server was given as, or to +tls-hostname
+fn encryption(options: &cli::Options, servers: &[(SocketAddr, String)]) -> tls::Encryption {
+    let mut encryption = tls::Encryption::new(options.tls_ca.as_deref(), options.https.clone()).unwrap_or_else(|e| {
+        eprintln!(";; Failed to set up TLS: {}", e);
+        std::process::exit(1);
+    });
+    for (server, host) in servers.iter() {
+        let name = options.tls_hostname.clone().unwrap_or_else(|| host.clone());
+        encryption.server_names.insert(*server, name);
+    }
+    encryption
 }
@@ -0,0 +1,406 @@
+use std::io::{ Error, ErrorKind };
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket };
+use std::time::{ Duration, Instant };
+
+use crate::message::{ Header, Message, MessageBuilder, Question, ResourceRecord };
+use crate::rdata::{ RData, RecordType };
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+// the top bit of a record's class tells caches to drop older data (RFC 6762 10.2)
+const CACHE_FLUSH: u16 = 0x8000;
+
+// mDNS lifts the 512 byte limit up to the largest Ethernet jumbo frame (RFC 6762 17)
+const MAX_PACKET: usize = 9000;
+
+// ff02::fb is link-local, so the scope id says which interface the query goes out on
+// (0 leaves it to the routing table). IPv4 always follows the multicast route.
+pub fn group(ipv6: bool, interface: u32) -> SocketAddr {
+    if ipv6 {
+        SocketAddr::V6(SocketAddrV6::new(MDNS_V6, MDNS_PORT, 0, interface))
+    } else {
+        SocketAddr::from((MDNS_V4, MDNS_PORT))
+    }
+}
+
+// takes an interface index or, on Linux, a name such as eth0
+pub fn interface_index(interface: &str) -> Result<u32, Error> {
+    if let Ok(index) = interface.parse() {
+        return Ok(index);
+    }
+    let invalid = || Error::new(ErrorKind::NotFound, format!("no such interface: {}", interface));
+    if interface.is_empty() || interface.contains('/') || interface.starts_with('.') {
+        return Err(invalid());
+    }
+    let index = std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", interface)).map_err(|_| invalid())?;
+    index.trim().parse().map_err(|_| invalid())
+}
+
+// one reply and the responder that sent it
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub message: Message,
+    pub from: SocketAddr,
+    pub elapsed: Duration,
+}
+
+// sends the questions to the group once and gathers every reply that arrives within the window.
+// The query goes out from an ephemeral port with id 0, which makes it a one-shot query that
+// responders answer by unicast (RFC 6762 5.1, 6.7), so we don't need to share port 5353.
+pub fn query(questions: &[Question], group: SocketAddr, window: Duration) -> Result<Vec<Response>, Error> {
+    let socket = match group.ip() {
+        IpAddr::V4(ip) => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            if ip.is_multicast() {
+                socket.set_multicast_ttl_v4(255)?;
+                socket.set_multicast_loop_v4(true)?;
+            }
+            socket
+        }
+        IpAddr::V6(ip) => {
+            let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?;
+            if ip.is_multicast() {
+                socket.set_multicast_loop_v6(true)?;
+            }
+            socket
+        }
+    };
+
+    let header = Header { id: 0, flags: 0, qdcount: 0, ancount: 0, nscount: 0, arcount: 0 };
+    let mut builder = MessageBuilder::new(&header);
+    for question in questions.iter() {
+        builder.question(question)?;
+    }
+    match socket.send_to(&builder.finish(), group) {
+        // without a scope id there may be no route to a link-local group at all
+        Err(e) if e.kind() == ErrorKind::NetworkUnreachable && group.is_ipv6() => {
+            return Err(Error::new(e.kind(), format!("{}, name the interface to send on", e)));
+        }
+        result => result?,
+    };
+
+    let started = Instant::now();
+    let mut responses = vec![];
+    let mut buf = vec![0; MAX_PACKET];
+    loop {
+        let remaining = window.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+        // other queriers' traffic and garbage are no reason to stop listening
+        let mut message = match Message::from_bytes(&buf[..len]) {
+            Ok(message) if message.header.is_response() => message,
+            _ => continue,
+        };
+        for record in message.answers.iter_mut().chain(message.authorities.iter_mut()).chain(message.additionals.iter_mut()) {
+            if record.rtype != RecordType::OPT {
+                record.class &= !CACHE_FLUSH;
+            }
+        }
+        responses.push(Response { message, from, elapsed: started.elapsed() });
+    }
+    Ok(responses)
+}
+
+// one DNS-SD service instance, put together from its SRV, TXT and address records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    pub name: String,
+    pub host: String, // empty when no responder gave us the SRV record
+    pub port: u16,
+    pub txt: Vec<String>,
+    pub addresses: Vec<IpAddr>,
+}
+
+// finds the instances of a service type such as _http._tcp.local (RFC 6763 4), then fills in
+// whatever the responders didn't volunteer as additional records with follow-up queries
+pub fn browse(service: &str, group: SocketAddr, window: Duration) -> Result<Vec<Instance>, Error> {
+    let mut records = vec![];
+    gather(&mut records, query(&[question(service, RecordType::PTR)], group, window)?);
+
+    let mut names: Vec<String> = records
+        .iter()
+        .filter(|r| same_name(&r.name, service))
+        .filter_map(|r| match &r.rdata {
+            RData::PTR(target) => Some(target.clone()),
+            _ => None,
+        })
+        .collect();
+    names.sort_by_key(|name| name.to_ascii_lowercase());
+    names.dedup_by(|a, b| same_name(a, b));
+
+    let missing: Vec<Question> = names
+        .iter()
+        .flat_map(|name| [RecordType::SRV, RecordType::TXT].map(|t| (name, t)))
+        .filter(|(name, rtype)| !has_record(&records, name, *rtype))
+        .map(|(name, rtype)| question(name, rtype))
+        .collect();
+    if !missing.is_empty() {
+        gather(&mut records, query(&missing, group, window)?);
+    }
+
+    let mut hosts: Vec<String> = names.iter().filter_map(|name| service_target(&records, name)).map(|(host, _)| host).collect();
+    hosts.sort_by_key(|host| host.to_ascii_lowercase());
+    hosts.dedup_by(|a, b| same_name(a, b));
+    let missing: Vec<Question> = hosts
+        .iter()
+        .filter(|host| !has_record(&records, host, RecordType::A) && !has_record(&records, host, RecordType::AAAA))
+        .flat_map(|host| [question(host, RecordType::A), question(host, RecordType::AAAA)])
+        .collect();
+    if !missing.is_empty() {
+        gather(&mut records, query(&missing, group, window)?);
+    }
+
+    Ok(names.iter().map(|name| instance(&records, name)).collect())
+}
+
+fn question(name: &str, qtype: RecordType) -> Question {
+    Question { qname: name.to_string(), qtype, qclass: 1 }
+}
+
+// mDNS names compare without regard to ASCII case (RFC 6762 16)
+fn same_name(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+// every record from every section, each one only once however many responders sent it
+fn gather(records: &mut Vec<ResourceRecord>, responses: Vec<Response>) {
+    for response in responses {
+        let message = response.message;
+        for record in message.answers.into_iter().chain(message.authorities).chain(message.additionals) {
+            let known = records
+                .iter()
+                .any(|r| r.rtype == record.rtype && same_name(&r.name, &record.name) && r.rdata == record.rdata);
+            if record.rtype != RecordType::OPT && !known {
+                records.push(record);
+            }
+        }
+    }
+}
+
+fn has_record(records: &[ResourceRecord], name: &str, rtype: RecordType) -> bool {
+    records.iter().any(|r| r.rtype == rtype && same_name(&r.name, name))
+}
+
+fn service_target(records: &[ResourceRecord], name: &str) -> Option<(String, u16)> {
+    records.iter().find_map(|r| match &r.rdata {
+        RData::SRV { target, port, .. } if same_name(&r.name, name) => Some((target.clone(), *port)),
+        _ => None,
+    })
+}
+
+fn owned<'a>(records: &'a [ResourceRecord], owner: &'a str) -> impl Iterator<Item = &'a RData> {
+    records.iter().filter(move |r| same_name(&r.name, owner)).map(|r| &r.rdata)
+}
+
+fn instance(records: &[ResourceRecord], name: &str) -> Instance {
+    let (host, port) = service_target(records, name).unwrap_or_default();
+    let txt = owned(records, name)
+        .filter_map(|rdata| match rdata {
+            RData::TXT(strings) => Some(strings.iter().map(|s| String::from_utf8_lossy(s).into_owned())),
+            _ => None,
+        })
+        .flatten()
+        .filter(|s| !s.is_empty()) // a lone empty string means no keys at all (RFC 6763 6.1)
+        .collect();
+    let addresses = if host.is_empty() {
+        vec![]
+    } else {
+        owned(records, &host)
+            .filter_map(|rdata| match rdata {
+                RData::A(ip) => Some(IpAddr::V4(*ip)),
+                RData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+                _ => None,
+            })
+            .collect()
+    };
+    Instance { name: name.to_string(), host, port, txt, addresses }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn record(name: &str, rtype: RecordType, rdata: RData) -> ResourceRecord {
+        ResourceRecord { name: name.to_string(), rtype, class: 1 | CACHE_FLUSH, ttl: 120, rdata }
+    }
+
+    fn srv(host: &str, port: u16) -> RData {
+        RData::SRV { priority: 0, weight: 0, port, target: host.to_string() }
+    }
+
+    // two devices answering from their own sockets whatever reaches the listener; only the
+    // printer volunteers its SRV, TXT and address along with the PTR, the camera has to be asked
+    fn respond(listener: UdpSocket) {
+        let local = match listener.local_addr().unwrap() {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let printer = UdpSocket::bind((local, 0)).unwrap();
+        let camera = UdpSocket::bind((local, 0)).unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; MAX_PACKET];
+            while let Ok((len, peer)) = listener.recv_from(&mut buf) {
+                let query = Message::from_bytes(&buf[..len]).unwrap();
+                for (socket, device) in [(&printer, "printer"), (&camera, "camera")] {
+                    let instance = format!("Lab\\032{}._http._tcp.local", device);
+                    let host = format!("{}.local", device);
+                    let mut reply = Message {
+                        header: Header { id: 0, flags: 0x8400, qdcount: 0, ancount: 0, nscount: 0, arcount: 0 },
+                        questions: vec![],
+                        answers: vec![],
+                        authorities: vec![],
+                        additionals: vec![],
+                    };
+                    for question in query.questions.iter() {
+                        let name = question.qname.as_str();
+                        match question.qtype {
+                            RecordType::PTR => {
+                                let ptr = RData::PTR(instance.clone());
+                                reply.answers.push(ResourceRecord { class: 1, ..record(name, RecordType::PTR, ptr) });
+                                if device == "printer" {
+                                    reply.additionals.push(record(&instance, RecordType::SRV, srv(&host, 631)));
+                                    reply.additionals.push(record(&instance, RecordType::TXT, RData::TXT(vec![b"rp=ipp".to_vec()])));
+                                    reply.additionals.push(record(&host, RecordType::A, RData::A([192, 0, 2, 7].into())));
+                                }
+                            }
+                            RecordType::SRV if same_name(name, &instance) => {
+                                reply.answers.push(record(name, RecordType::SRV, srv(&host, 8080)));
+                            }
+                            RecordType::TXT if same_name(name, &instance) => {
+                                reply.answers.push(record(name, RecordType::TXT, RData::TXT(vec![vec![]])));
+                            }
+                            RecordType::AAAA if same_name(name, &host) => {
+                                reply.answers.push(record(name, RecordType::AAAA, RData::AAAA("2001:db8::9".parse().unwrap())));
+                            }
+                            _ => {}
+                        }
+                    }
+                    if !reply.answers.is_empty() {
+                        socket.send_to(&reply.to_bytes().unwrap(), peer).unwrap();
+                    }
+                }
+            }
+        });
+    }
+
+    fn responders() -> SocketAddr {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let group = listener.local_addr().unwrap();
+        respond(listener);
+        group
+    }
+
+    // an interface that is up and does multicast (Linux only), for the tests that go through
+    // the real groups; the loopback interface does neither
+    fn multicast_interface() -> Option<u32> {
+        let mut interfaces: Vec<_> = std::fs::read_dir("/sys/class/net").ok()?.flatten().map(|e| e.path()).collect();
+        interfaces.sort();
+        interfaces.into_iter().find_map(|path| {
+            let flags = std::fs::read_to_string(path.join("flags")).ok()?;
+            let flags = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok()?;
+            // IFF_UP, IFF_LOOPBACK and IFF_MULTICAST
+            if flags & 0x1 == 0 || flags & 0x8 != 0 || flags & 0x1000 == 0 {
+                return None;
+            }
+            std::fs::read_to_string(path.join("ifindex")).ok()?.trim().parse().ok()
+        })
+    }
+
+    // responders that are members of the group itself, on an ephemeral port so that they
+    // don't get in the way of a real responder on 5353
+    fn group_members(ipv6: bool) -> Option<SocketAddr> {
+        let interface = multicast_interface()?;
+        let (listener, group) = if ipv6 {
+            let listener = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).unwrap();
+            listener.join_multicast_v6(&MDNS_V6, interface).ok()?;
+            let port = listener.local_addr().unwrap().port();
+            (listener, SocketAddr::V6(SocketAddrV6::new(MDNS_V6, port, 0, interface)))
+        } else {
+            let listener = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+            listener.join_multicast_v4(&MDNS_V4, &Ipv4Addr::UNSPECIFIED).ok()?;
+            let port = listener.local_addr().unwrap().port();
+            (listener, SocketAddr::from((MDNS_V4, port)))
+        };
+        respond(listener);
+        Some(group)
+    }
+
+    #[test]
+    fn test_collects_every_responder() {
+        let group = responders();
+        let responses = query(&[question("_http._tcp.local", RecordType::PTR)], group, Duration::from_millis(300)).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_ne!(responses[0].from, responses[1].from);
+        assert!(responses.iter().all(|r| r.message.additionals.iter().all(|a| a.class == 1)));
+    }
+
+    #[test]
+    fn test_browse() {
+        let group = responders();
+        let instances = browse("_http._tcp.local", group, Duration::from_millis(200)).unwrap();
+        assert_eq!(instances, vec![
+            Instance {
+                name: r"Lab\032camera._http._tcp.local".to_string(),
+                host: "camera.local".to_string(),
+                port: 8080,
+                txt: vec![],
+                addresses: vec!["2001:db8::9".parse().unwrap()],
+            },
+            Instance {
+                name: r"Lab\032printer._http._tcp.local".to_string(),
+                host: "printer.local".to_string(),
+                port: 631,
+                txt: vec!["rp=ipp".to_string()],
+                addresses: vec!["192.0.2.7".parse().unwrap()],
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_group_v4() {
+        let group = match group_members(false) {
+            Some(group) => group,
+            None => return eprintln!("no multicast interface, skipping"),
+        };
+        // the query reaches the members through multicast loopback
+        let instances = browse("_http._tcp.local", group, Duration::from_millis(300)).unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[1].addresses, vec!["192.0.2.7".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn test_group_v6() {
+        let group = match group_members(true) {
+            Some(group) => group,
+            None => return eprintln!("no multicast interface, skipping"),
+        };
+        let responses = match query(&[question("_http._tcp.local", RecordType::PTR)], group, Duration::from_millis(300)) {
+            Ok(responses) => responses,
+            // an interface that can't do IPv6
+            Err(e) if e.kind() == ErrorKind::AddrNotAvailable => return,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|r| r.from.is_ipv6()));
+    }
+
+    #[test]
+    fn test_interface_index() {
+        assert_eq!(interface_index("3").unwrap(), 3);
+        assert!(interface_index("../lo").is_err());
+        assert!(interface_index("no-such-interface0").is_err());
+        if let Ok(index) = interface_index("lo") {
+            assert_ne!(index, 0);
+        }
+    }
+}
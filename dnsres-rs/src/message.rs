@@ -0,0 +1,597 @@
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use std::collections::HashMap;
+use std::io::{ Cursor, Error, ErrorKind };
+
+use crate::name::Name;
+use crate::rdata::{ RData, RecordType };
+
+// the meta classes (RFC 2136 1.3, RFC 8945 4.2)
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_ANY: u16 = 255;
+
+// a name can't point back more often than it has labels (max 127 in 255 bytes)
+const MAX_POINTERS: usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    pub qdcount: u16, // nb questions
+    pub ancount: u16, // nb answer resource records
+    pub nscount: u16, // nb authority resource records
+    pub arcount: u16, // nb additional resource records
+}
+
+#[derive(Debug, Clone)]
+pub struct Question {
+    pub qname: String, // name being looked up
+    pub qtype: RecordType, // record type
+    pub qclass: u16, // class
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceRecord {
+    pub name: String, // owner name
+    pub rtype: RecordType, // record type
+    pub class: u16, // class
+    pub ttl: u32, // seconds the record may be cached
+    pub rdata: RData, // record payload
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<ResourceRecord>,
+    pub authorities: Vec<ResourceRecord>,
+    pub additionals: Vec<ResourceRecord>,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = vec![];
+        bytes.write_u16::<BigEndian>(self.id)?;
+        bytes.write_u16::<BigEndian>(self.flags)?;
+        bytes.write_u16::<BigEndian>(self.qdcount)?;
+        bytes.write_u16::<BigEndian>(self.ancount)?;
+        bytes.write_u16::<BigEndian>(self.nscount)?;
+        bytes.write_u16::<BigEndian>(self.arcount)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Header, Error> {
+        Ok(Header {
+            id: cursor.read_u16::<BigEndian>()?,
+            flags: cursor.read_u16::<BigEndian>()?,
+            qdcount: cursor.read_u16::<BigEndian>()?,
+            ancount: cursor.read_u16::<BigEndian>()?,
+            nscount: cursor.read_u16::<BigEndian>()?,
+            arcount: cursor.read_u16::<BigEndian>()?,
+        })
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & 0x8000 != 0
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.flags & 0x0200 != 0
+    }
+
+    pub fn opcode(&self) -> u8 {
+        ((self.flags >> 11) & 0x000f) as u8
+    }
+
+    pub fn rcode(&self) -> u8 {
+        (self.flags & 0x000f) as u8
+    }
+
+    // lowercase flag mnemonics as printed by dig
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        let bits = [
+            (0x8000, "qr"),
+            (0x0400, "aa"),
+            (0x0200, "tc"),
+            (0x0100, "rd"),
+            (0x0080, "ra"),
+            (0x0020, "ad"),
+            (0x0010, "cd"),
+        ];
+        bits.iter()
+            .filter(|(bit, _)| self.flags & bit != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl Question {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = vec![];
+        write_name(&mut bytes, &self.qname)?;
+        bytes.write_u16::<BigEndian>(self.qtype.to_u16())?;
+        bytes.write_u16::<BigEndian>(self.qclass)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Question, Error> {
+        Ok(Question {
+            qname: read_name(cursor)?,
+            qtype: RecordType::from(cursor.read_u16::<BigEndian>()?),
+            qclass: cursor.read_u16::<BigEndian>()?,
+        })
+    }
+}
+
+impl ResourceRecord {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let rdata = self.rdata.to_bytes()?;
+        let mut bytes = vec![];
+        write_name(&mut bytes, &self.name)?;
+        bytes.write_u16::<BigEndian>(self.rtype.to_u16())?;
+        bytes.write_u16::<BigEndian>(self.class)?;
+        bytes.write_u32::<BigEndian>(self.ttl)?;
+        if rdata.len() > u16::MAX as usize {
+            return Err(malformed("rdata too long"));
+        }
+        bytes.write_u16::<BigEndian>(rdata.len() as u16)?;
+        bytes.extend(rdata);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(cursor: &mut Cursor<&[u8]>) -> Result<ResourceRecord, Error> {
+        let name = read_name(cursor)?;
+        let rtype = RecordType::from(cursor.read_u16::<BigEndian>()?);
+        let class = cursor.read_u16::<BigEndian>()?;
+        let ttl = cursor.read_u32::<BigEndian>()?;
+        let rdlength = cursor.read_u16::<BigEndian>()? as usize;
+        // prerequisites and deletions in an UPDATE carry no rdata, only class ANY or NONE (RFC 2136 2.4, 2.5)
+        let rdata = if rdlength == 0 && matches!(class, CLASS_NONE | CLASS_ANY) {
+            RData::Unknown(vec![])
+        } else {
+            RData::from_bytes(cursor, rtype, rdlength)?
+        };
+
+        Ok(ResourceRecord { name, rtype, class, ttl, rdata })
+    }
+}
+
+impl Message {
+    // serializes every section with names compressed, counts taken from the vectors
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut builder = MessageBuilder::new(&self.header);
+        for question in self.questions.iter() {
+            builder.question(question)?;
+        }
+        for record in self.answers.iter() {
+            builder.answer(record)?;
+        }
+        for record in self.authorities.iter() {
+            builder.authority(record)?;
+        }
+        for record in self.additionals.iter() {
+            builder.additional(record)?;
+        }
+        Ok(builder.finish())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Message, Error> {
+        let mut cursor = Cursor::new(bytes);
+        let header = Header::from_bytes(&mut cursor)?;
+
+        let mut questions = vec![];
+        for _ in 0..header.qdcount {
+            questions.push(Question::from_bytes(&mut cursor)?);
+        }
+        let answers = read_records(&mut cursor, header.ancount)?;
+        let authorities = read_records(&mut cursor, header.nscount)?;
+        let additionals = read_records(&mut cursor, header.arcount)?;
+
+        Ok(Message { header, questions, answers, authorities, additionals })
+    }
+}
+
+// where a builder currently is; sections have to be filled in wire order
+const QUESTION: usize = 0;
+const ANSWER: usize = 1;
+const AUTHORITY: usize = 2;
+const ADDITIONAL: usize = 3;
+
+// pointers only have 14 bits of offset
+const MAX_POINTER_OFFSET: usize = 0x3fff;
+
+// writes a message section by section, pointing repeated name suffixes back at the first
+// copy (RFC 1035 4.1.4). The header counts come from what was added.
+pub struct MessageBuilder {
+    bytes: Vec<u8>,
+    counts: [u16; 4],
+    section: usize,
+    // every name tail written so far and its offset, matched byte for byte so names
+    // with randomized case (draft-vixie-dnsext-dns0x20) come back exactly as sent
+    suffixes: HashMap<Vec<Vec<u8>>, u16>,
+}
+
+impl MessageBuilder {
+    pub fn new(header: &Header) -> MessageBuilder {
+        let mut bytes = Vec::with_capacity(512);
+        bytes.extend(header.id.to_be_bytes());
+        bytes.extend(header.flags.to_be_bytes());
+        bytes.extend([0; 8]); // counts, filled in by finish
+        MessageBuilder { bytes, counts: [0; 4], section: QUESTION, suffixes: HashMap::new() }
+    }
+
+    pub fn question(&mut self, question: &Question) -> Result<(), Error> {
+        self.add(QUESTION, |builder| {
+            builder.name(&question.qname)?;
+            builder.bytes.write_u16::<BigEndian>(question.qtype.to_u16())?;
+            builder.bytes.write_u16::<BigEndian>(question.qclass)
+        })
+    }
+
+    pub fn answer(&mut self, record: &ResourceRecord) -> Result<(), Error> {
+        self.add(ANSWER, |builder| builder.record(record))
+    }
+
+    pub fn authority(&mut self, record: &ResourceRecord) -> Result<(), Error> {
+        self.add(AUTHORITY, |builder| builder.record(record))
+    }
+
+    pub fn additional(&mut self, record: &ResourceRecord) -> Result<(), Error> {
+        self.add(ADDITIONAL, |builder| builder.record(record))
+    }
+
+    // how many bytes the message takes so far
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        for (i, count) in self.counts.iter().enumerate() {
+            self.bytes[4 + 2 * i..6 + 2 * i].copy_from_slice(&count.to_be_bytes());
+        }
+        self.bytes
+    }
+
+    // a failed write is rolled back, so the builder is still good for whatever comes next
+    fn add(&mut self, section: usize, write: impl FnOnce(&mut MessageBuilder) -> Result<(), Error>) -> Result<(), Error> {
+        if section < self.section {
+            return Err(Error::new(ErrorKind::InvalidInput, "sections must be added in order"));
+        }
+        if self.counts[section] == u16::MAX {
+            return Err(Error::new(ErrorKind::InvalidInput, "too many entries in one section"));
+        }
+        let mark = self.bytes.len();
+        if let Err(e) = write(self) {
+            self.bytes.truncate(mark);
+            self.suffixes.retain(|_, offset| (*offset as usize) < mark);
+            return Err(e);
+        }
+        self.section = section;
+        self.counts[section] += 1;
+        Ok(())
+    }
+
+    fn name(&mut self, name: &str) -> Result<(), Error> {
+        let name = Name::parse_opaque(name).map_err(|e| malformed(&e))?;
+        let labels = name.labels();
+        for i in 0..labels.len() {
+            if let Some(&offset) = self.suffixes.get(&labels[i..]) {
+                return self.bytes.write_u16::<BigEndian>(0xc000 | offset);
+            }
+            if self.bytes.len() <= MAX_POINTER_OFFSET {
+                self.suffixes.insert(labels[i..].to_vec(), self.bytes.len() as u16);
+            }
+            self.bytes.push(labels[i].len() as u8);
+            self.bytes.extend(&labels[i]);
+        }
+        self.bytes.push(0);
+        Ok(())
+    }
+
+    fn record(&mut self, record: &ResourceRecord) -> Result<(), Error> {
+        self.name(&record.name)?;
+        self.bytes.write_u16::<BigEndian>(record.rtype.to_u16())?;
+        self.bytes.write_u16::<BigEndian>(record.class)?;
+        self.bytes.write_u32::<BigEndian>(record.ttl)?;
+        let start = self.bytes.len() + 2;
+        self.bytes.write_u16::<BigEndian>(0)?; // rdlength, patched below
+        match &record.rdata {
+            // only the RFC 1035 types may be compressed inside rdata (RFC 3597 4), everything
+            // else goes out as is so servers that don't know the type can still read it
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => self.name(name)?,
+            RData::MX { preference, exchange } => {
+                self.bytes.write_u16::<BigEndian>(*preference)?;
+                self.name(exchange)?;
+            }
+            RData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                self.name(mname)?;
+                self.name(rname)?;
+                for value in [serial, refresh, retry, expire, minimum] {
+                    self.bytes.write_u32::<BigEndian>(*value)?;
+                }
+            }
+            rdata => self.bytes.extend(rdata.to_bytes()?),
+        }
+        let length = self.bytes.len() - start;
+        if length > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "rdata too long"));
+        }
+        self.bytes[start - 2..start].copy_from_slice(&(length as u16).to_be_bytes());
+        Ok(())
+    }
+}
+
+fn read_records(cursor: &mut Cursor<&[u8]>, count: u16) -> Result<Vec<ResourceRecord>, Error> {
+    let mut records = vec![];
+    for _ in 0..count {
+        records.push(ResourceRecord::from_bytes(cursor)?);
+    }
+    Ok(records)
+}
+
+// writes a name as uncompressed labels, "" being the root; names over the length limits are refused
+pub fn write_name(bytes: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+    let name = Name::parse_opaque(name).map_err(|e| malformed(&e))?;
+    name.write(bytes);
+    Ok(())
+}
+
+// reads a (possibly compressed) domain name, leaving the cursor after it
+pub fn read_name(cursor: &mut Cursor<&[u8]>) -> Result<String, Error> {
+    let bytes = *cursor.get_ref();
+    let mut labels: Vec<Vec<u8>> = vec![];
+    let mut pos = cursor.position() as usize;
+    let mut resume_at = None; // where to continue once we followed a pointer
+    let mut jumps = 0;
+
+    loop {
+        let len = *bytes.get(pos).ok_or_else(|| malformed("name runs past end of message"))?;
+        match len & 0xc0 {
+            0x00 => {
+                pos += 1;
+                if len == 0 {
+                    break;
+                }
+                let end = pos + (len as usize);
+                let label = bytes
+                    .get(pos..end)
+                    .ok_or_else(|| malformed("label runs past end of message"))?;
+                labels.push(label.to_vec());
+                pos = end;
+            }
+            0xc0 => {
+                let low = *bytes.get(pos + 1).ok_or_else(|| malformed("truncated compression pointer"))?;
+                let target = (((len & 0x3f) as usize) << 8) | (low as usize);
+                if target >= pos {
+                    return Err(malformed("compression pointer does not point backwards"));
+                }
+                jumps += 1;
+                if jumps > MAX_POINTERS {
+                    return Err(malformed("too many compression pointers"));
+                }
+                if resume_at.is_none() {
+                    resume_at = Some(pos + 2);
+                }
+                pos = target;
+            }
+            _ => {
+                return Err(malformed("unsupported label type"));
+            }
+        }
+    }
+
+    cursor.set_position(resume_at.unwrap_or(pos) as u64);
+    let name = Name::from_labels(labels).map_err(|e| malformed(&e))?;
+    Ok(name.to_string())
+}
+
+pub fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        6 => "YXDOMAIN".to_string(),
+        7 => "YXRRSET".to_string(),
+        8 => "NXRRSET".to_string(),
+        9 => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        16 => "BADVERS".to_string(),
+        17 => "BADKEY".to_string(),
+        18 => "BADTIME".to_string(),
+        22 => "BADTRUNC".to_string(),
+        _ => format!("RCODE{}", rcode),
+    }
+}
+
+pub fn opcode_name(opcode: u8) -> String {
+    match opcode {
+        0 => "QUERY".to_string(),
+        1 => "IQUERY".to_string(),
+        2 => "STATUS".to_string(),
+        4 => "NOTIFY".to_string(),
+        5 => "UPDATE".to_string(),
+        _ => format!("OPCODE{}", opcode),
+    }
+}
+
+pub fn class_name(class: u16) -> String {
+    match class {
+        1 => "IN".to_string(),
+        3 => "CH".to_string(),
+        4 => "HS".to_string(),
+        254 => "NONE".to_string(),
+        255 => "ANY".to_string(),
+        _ => format!("CLASS{}", class),
+    }
+}
+
+pub fn parse_class(value: &str) -> Result<u16, String> {
+    let upper = value.to_ascii_uppercase();
+    match upper.as_str() {
+        "IN" => Ok(1),
+        "CH" => Ok(3),
+        "HS" => Ok(4),
+        "NONE" => Ok(254),
+        "ANY" => Ok(255),
+        _ => upper
+            .strip_prefix("CLASS")
+            .and_then(|n| n.parse::<u16>().ok())
+            .ok_or_else(|| format!("unknown class: {}", value)),
+    }
+}
+
+pub fn malformed(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // response for www.example.com A with the answer name compressed to offset 12
+    const RESPONSE: [u8; 49] = [
+        0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x03, b'w', b'w', b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+        0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04,
+        93, 184, 216, 34,
+    ];
+
+    #[test]
+    fn test_decode_response() {
+        let message = Message::from_bytes(&RESPONSE).unwrap();
+        assert_eq!(message.header.id, 0x1234);
+        assert!(message.header.is_response());
+        assert_eq!(message.header.rcode(), 0);
+        assert_eq!(message.questions[0].qname, "www.example.com");
+        assert_eq!(message.answers.len(), 1);
+        assert_eq!(message.answers[0].name, "www.example.com");
+        assert_eq!(message.answers[0].ttl, 3600);
+        assert_eq!(message.answers[0].rdata, RData::A([93, 184, 216, 34].into()));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let message = Message::from_bytes(&RESPONSE).unwrap();
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(bytes, RESPONSE);
+    }
+
+    fn record(name: &str, rdata: RData) -> ResourceRecord {
+        let rtype = match rdata {
+            RData::NS(_) => RecordType::NS,
+            RData::MX { .. } => RecordType::MX,
+            RData::SOA { .. } => RecordType::SOA,
+            RData::SRV { .. } => RecordType::SRV,
+            _ => RecordType::A,
+        };
+        ResourceRecord { name: name.to_string(), rtype, class: 1, ttl: 300, rdata }
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut builder = MessageBuilder::new(&Header { id: 1, flags: 0x8400, qdcount: 0, ancount: 0, nscount: 0, arcount: 0 });
+        builder.question(&Question { qname: "Example.COM".to_string(), qtype: RecordType::MX, qclass: 1 }).unwrap();
+        builder.answer(&record("Example.COM", RData::MX { preference: 10, exchange: "mail.Example.COM".to_string() })).unwrap();
+        builder.authority(&record("Example.COM", RData::SOA {
+            mname: "ns1.Example.COM".to_string(),
+            rname: "hostmaster.Example.COM".to_string(),
+            serial: 1,
+            refresh: 2,
+            retry: 3,
+            expire: 4,
+            minimum: 5,
+        })).unwrap();
+        // SRV targets must not be compressed (RFC 2782)
+        let srv = RData::SRV { priority: 0, weight: 0, port: 53, target: "ns1.Example.COM".to_string() };
+        builder.additional(&record("_dns._udp.Example.COM", srv)).unwrap();
+        builder.additional(&record("mail.example.com", RData::A([192, 0, 2, 25].into()))).unwrap();
+        assert!(builder.question(&Question { qname: "late".to_string(), qtype: RecordType::A, qclass: 1 }).is_err());
+        assert!(builder.additional(&record("a..b", RData::A([192, 0, 2, 1].into()))).is_err());
+        let bytes = builder.finish();
+
+        // question name at 12; the answer owner is a bare pointer and the exchange reuses it
+        assert_eq!(bytes[4..12], [0, 1, 0, 1, 0, 1, 0, 2]);
+        assert_eq!(bytes[29..31], [0xc0, 0x0c]);
+        assert_eq!(bytes[41..49], [0x00, 0x0a, 0x04, b'm', b'a', b'i', b'l', 0xc0]);
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(message.answers[0].rdata, RData::MX { preference: 10, exchange: "mail.Example.COM".to_string() });
+        assert_eq!(message.authorities[0].to_bytes().unwrap(), record("Example.COM", RData::SOA {
+            mname: "ns1.Example.COM".to_string(),
+            rname: "hostmaster.Example.COM".to_string(),
+            serial: 1,
+            refresh: 2,
+            retry: 3,
+            expire: 4,
+            minimum: 5,
+        }).to_bytes().unwrap());
+        let srv = &message.additionals[0];
+        assert_eq!(srv.rdata.to_bytes().unwrap().len(), 6 + 17);
+        // differently cased names are not merged
+        assert_eq!(message.additionals[1].name, "mail.example.com");
+        assert_eq!(message.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_header_flags() {
+        let message = Message::from_bytes(&RESPONSE).unwrap();
+        assert_eq!(message.header.flag_names(), vec!["qr", "rd", "ra"]);
+        assert_eq!(opcode_name(message.header.opcode()), "QUERY");
+        assert_eq!(rcode_name(3), "NXDOMAIN");
+        assert_eq!(parse_class("ch").unwrap(), 3);
+        assert_eq!(class_name(parse_class("CLASS42").unwrap()), "CLASS42");
+    }
+
+    #[test]
+    fn test_pointer_loop_rejected() {
+        let mut bytes = RESPONSE.to_vec();
+        bytes[33] = 0xc0;
+        bytes[34] = 0x21; // answer name points at itself
+        assert!(Message::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_truncated_rdata_rejected() {
+        assert!(Message::from_bytes(&RESPONSE[..47]).is_err());
+    }
+
+    #[test]
+    fn test_bad_names_refused() {
+        for qname in ["x".repeat(64), "a..b".to_string(), vec!["a"; 128].join(".")] {
+            let question = Question { qname, qtype: RecordType::A, qclass: 1 };
+            assert!(question.to_bytes().is_err());
+        }
+
+        // odd bytes in a label survive the trip as escapes
+        let mut bytes = RESPONSE.to_vec();
+        bytes[14] = b'.';
+        bytes[15] = b' ';
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(message.questions[0].qname, r"w\.\032.example.com");
+        assert_eq!(message.to_bytes().unwrap()[12..33], bytes[12..33]);
+    }
+
+    #[test]
+    fn test_rdata_too_long() {
+        let rdata = RData::TXT(vec![vec![b'x'; 255]; 257]);
+        let record = ResourceRecord { name: "txt.example.com".to_string(), rtype: RecordType::TXT, class: 1, ttl: 300, rdata };
+        assert_eq!(record.to_bytes().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_high_bytes_round_trip() {
+        // a label that isn't UTF-8, one that is, and one that only looks like punycode: all
+        // have to go back out exactly as they came in
+        for label in [&b"a\xffb"[..], &b"caf\xc3\xa9"[..], &b"xn--a!"[..]] {
+            let mut bytes = RESPONSE[..12].to_vec();
+            bytes[7] = 0; // the question only
+            bytes.push(label.len() as u8);
+            bytes.extend(label);
+            bytes.extend(b"\x07example\x00\x00\x01\x00\x01");
+            let message = Message::from_bytes(&bytes).unwrap();
+            assert_eq!(message.to_bytes().unwrap(), bytes);
+        }
+    }
+}
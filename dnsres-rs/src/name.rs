@@ -0,0 +1,463 @@
+use std::fmt;
+use std::str::FromStr;
+
+pub const MAX_LABEL: usize = 63;
+pub const MAX_NAME: usize = 255; // wire length, including the length bytes and the root label
+
+// punycode parameters (RFC 3492 5)
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+const ACE_PREFIX: &str = "xn--";
+
+// ideographic and full-width full stops separate labels too (UTS 46 / RFC 3490 3.1)
+const DOTS: [char; 4] = ['.', '\u{3002}', '\u{ff0e}', '\u{ff61}'];
+
+// a validated domain name as wire-format labels, the root having none
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    // checks the RFC 1035 limits on already-decoded labels
+    pub fn from_labels(labels: Vec<Vec<u8>>) -> Result<Name, String> {
+        let mut length = 1;
+        for label in labels.iter() {
+            if label.is_empty() {
+                return Err("empty label".to_string());
+            }
+            if label.len() > MAX_LABEL {
+                return Err(format!("label longer than {} bytes", MAX_LABEL));
+            }
+            length += label.len() + 1;
+        }
+        if length > MAX_NAME {
+            return Err(format!("name longer than {} bytes", MAX_NAME));
+        }
+        Ok(Name { labels })
+    }
+
+    // presentation format: \. and \DDD escapes, an optional trailing dot, "." or "" for the root.
+    // Labels typed in Unicode are converted to their xn-- form; escaped bytes are taken as they are.
+    pub fn parse(text: &str) -> Result<Name, String> {
+        Name::parse_labels(text, true)
+    }
+
+    // the same, for names that may have been read off the wire and so hold labels such as
+    // xn--a! that aren't valid IDNA but still have to be written back unchanged
+    pub fn parse_opaque(text: &str) -> Result<Name, String> {
+        Name::parse_labels(text, false)
+    }
+
+    fn parse_labels(text: &str, check_ace: bool) -> Result<Name, String> {
+        let invalid = |reason: &str| format!("invalid name {}: {}", text, reason);
+        if text.is_empty() || text == "." {
+            return Ok(Name::default());
+        }
+
+        let mut labels = vec![];
+        let mut label = Label::default();
+        let mut chars = text.chars().peekable();
+        let mut ended_with_dot = false;
+        while let Some(c) = chars.next() {
+            ended_with_dot = false;
+            if DOTS.contains(&c) {
+                labels.push(label.finish(check_ace).map_err(|e| invalid(&e))?);
+                label = Label::default();
+                ended_with_dot = true;
+                continue;
+            }
+            if c != '\\' {
+                label.push_char(c);
+                continue;
+            }
+            match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    let digits: String = [Some(d), chars.next(), chars.next()].into_iter().flatten().collect();
+                    let byte = match digits.parse::<u16>() {
+                        Ok(value) if digits.len() == 3 && value <= 255 => value as u8,
+                        _ => return Err(invalid("bad \\DDD escape")),
+                    };
+                    label.push_escaped(byte);
+                }
+                Some(other) => label.push_char(other),
+                None => return Err(invalid("trailing backslash")),
+            }
+        }
+        if !ended_with_dot {
+            labels.push(label.finish(check_ace).map_err(|e| invalid(&e))?);
+        }
+        Name::from_labels(labels).map_err(|e| invalid(&e))
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    // uncompressed wire format
+    pub fn write(&self, bytes: &mut Vec<u8>) {
+        for label in self.labels.iter() {
+            bytes.push(label.len() as u8);
+            bytes.extend(label);
+        }
+        bytes.push(0);
+    }
+}
+
+impl FromStr for Name {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Name, String> {
+        Name::parse(text)
+    }
+}
+
+// the form used throughout: escaped where needed, no trailing dot, "" for the root
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, label) in self.labels.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            for &b in label.iter() {
+                match b {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => write!(f, "\\{}", b as char)?,
+                    0x21..=0x7e => write!(f, "{}", b as char)?,
+                    _ => write!(f, "\\{:03}", b)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// whether presentation text ends in an unescaped dot, i.e. is already fully qualified
+pub fn is_absolute(text: &str) -> bool {
+    let body = match text.strip_suffix('.') {
+        Some(body) => body,
+        None => return false,
+    };
+    let backslashes = body.len() - body.trim_end_matches('\\').len();
+    backslashes % 2 == 0
+}
+
+// the text without the trailing dot that makes it absolute
+pub fn relative(text: &str) -> &str {
+    match is_absolute(text) {
+        true => &text[..text.len() - 1],
+        false => text,
+    }
+}
+
+// one label as it is being read from presentation format
+#[derive(Default)]
+struct Label {
+    bytes: Vec<u8>,
+    unicode: bool, // holds characters outside ASCII, as typed rather than escaped
+    escaped: bool, // holds \DDD bytes above 127
+}
+
+impl Label {
+    fn push_char(&mut self, c: char) {
+        let mut utf8 = [0; 4];
+        self.bytes.extend(c.encode_utf8(&mut utf8).bytes());
+        self.unicode |= !c.is_ascii();
+    }
+
+    fn push_escaped(&mut self, byte: u8) {
+        self.bytes.push(byte);
+        self.escaped |= !byte.is_ascii();
+    }
+
+    fn finish(self, check_ace: bool) -> Result<Vec<u8>, String> {
+        match (self.unicode, self.escaped) {
+            (true, true) => Err("label mixes Unicode characters with escaped bytes".to_string()),
+            (true, false) => to_ascii_label(&self.bytes),
+            (false, _) => {
+                let ace = self.bytes.len() > ACE_PREFIX.len() &&
+                    self.bytes[..ACE_PREFIX.len()].eq_ignore_ascii_case(ACE_PREFIX.as_bytes());
+                if check_ace && ace && self.bytes.is_ascii() {
+                    check_ace_label(&String::from_utf8_lossy(&self.bytes))?;
+                }
+                Ok(self.bytes)
+            }
+        }
+    }
+}
+
+// IDNA 2008 ToASCII for a label typed in Unicode (RFC 5891 4 and 5). Without the Unicode
+// tables we can't apply NFC or the full derived-property list, so this lowercases, rejects
+// what is clearly disallowed (controls, spaces, symbols and punctuation blocks, non-LDH ASCII)
+// and checks the hyphen rules. ASCII and escaped labels never get here so binary labels and
+// case are preserved.
+fn to_ascii_label(label: &[u8]) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(label).map_err(|_| "label is not valid UTF-8".to_string())?;
+    let chars: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    for &c in chars.iter() {
+        if !allowed_in_idn(c) {
+            return Err(format!("{:?} is not allowed in an internationalized label", c));
+        }
+    }
+    check_hyphens(&chars)?;
+    let encoded = format!("{}{}", ACE_PREFIX, punycode_encode(&chars).ok_or("label too long to encode")?);
+    Ok(encoded.into_bytes())
+}
+
+// an xn-- label has to decode to something that encodes back to the same label
+fn check_ace_label(label: &str) -> Result<(), String> {
+    let lower = label.to_ascii_lowercase();
+    let decoded = punycode_decode(&lower[ACE_PREFIX.len()..]).ok_or_else(|| format!("bad punycode in {}", label))?;
+    if decoded.iter().all(char::is_ascii) || decoded.iter().any(|&c| !allowed_in_idn(c) || c.is_uppercase()) {
+        return Err(format!("{} does not decode to a valid label", label));
+    }
+    check_hyphens(&decoded)?;
+    match punycode_encode(&decoded) {
+        Some(encoded) if encoded == lower[ACE_PREFIX.len()..] => Ok(()),
+        _ => Err(format!("{} is not in canonical punycode", label)),
+    }
+}
+
+fn check_hyphens(chars: &[char]) -> Result<(), String> {
+    if chars.first() == Some(&'-') || chars.last() == Some(&'-') {
+        return Err("label starts or ends with a hyphen".to_string());
+    }
+    if chars.len() >= 4 && chars[2] == '-' && chars[3] == '-' {
+        return Err("hyphens in the third and fourth positions".to_string());
+    }
+    Ok(())
+}
+
+fn allowed_in_idn(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+    }
+    let disallowed = c.is_control() ||
+        c.is_whitespace() ||
+        matches!(c as u32,
+            0x00a0..=0x00bf | // latin-1 punctuation and symbols
+            0x2000..=0x2bff | // general punctuation through miscellaneous symbols and arrows
+            0x3000..=0x3003 | // CJK spaces and punctuation
+            0xe000..=0xf8ff | // private use
+            0xfe00..=0xfe0f | // variation selectors
+            0xff01..=0xff5e | // full-width ASCII
+            0xfff0..=0xffff | // specials
+            0x1f000..=0x1faff // emoji and other pictographs
+        );
+    !disallowed
+}
+
+fn adapt(mut delta: u32, points: u32, first: bool) -> u32 {
+    delta /= if first { DAMP } else { 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (((BASE - T_MIN + 1) * delta) / (delta + SKEW))
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        T_MIN
+    } else if k >= bias + T_MAX {
+        T_MAX
+    } else {
+        k - bias
+    }
+}
+
+fn encode_digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    }
+}
+
+fn decode_digit(c: char) -> Option<u32> {
+    match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+        _ => None,
+    }
+}
+
+// RFC 3492 6.3, None on overflow
+pub fn punycode_encode(input: &[char]) -> Option<String> {
+    let mut output: String = input.iter().filter(|c| c.is_ascii()).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+    while (handled as usize) < input.len() {
+        let m = input.iter().map(|&c| c as u32).filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in input.iter() {
+            let c = c as u32;
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Some(output)
+}
+
+// RFC 3492 6.2, None for malformed input
+pub fn punycode_decode(input: &str) -> Option<Vec<char>> {
+    let (basic, extended) = match input.rfind('-') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.chars().peekable();
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let length = output.len() as u32 + 1;
+        bias = adapt(i - old_i, length, old_i == 0);
+        n = n.checked_add(i / length)?;
+        i %= length;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punycode_vectors() {
+        let cases = [
+            ("bücher", "bcher-kva"),
+            ("münchen", "mnchen-3ya"),
+            ("ドメイン名例", "eckwd4c7cu47r2wf"),
+            // RFC 3492 7.1 (L), mixed case kept as given
+            ("3年B組金八先生", "3B-ww4c5e180e575a65lsy2b"),
+        ];
+        for (unicode, encoded) in cases {
+            let chars: Vec<char> = unicode.chars().collect();
+            assert_eq!(punycode_encode(&chars).unwrap(), encoded);
+            assert_eq!(punycode_decode(encoded).unwrap(), chars);
+        }
+        assert!(punycode_decode("a-b!").is_none());
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let name = Name::parse("WWW.Example.com.").unwrap();
+        assert_eq!(name.to_string(), "WWW.Example.com");
+        assert_eq!(name.labels.len(), 3);
+        assert!(Name::parse(".").unwrap().is_root());
+        assert_eq!(Name::parse("").unwrap().to_string(), "");
+
+        let escaped = Name::parse(r"a\.b.c\032d.\065").unwrap();
+        assert_eq!(escaped.labels[0], b"a.b");
+        assert_eq!(escaped.labels[1], b"c d");
+        assert_eq!(escaped.to_string(), r"a\.b.c\032d.A");
+
+        let mut wire = vec![];
+        Name::parse("example.com").unwrap().write(&mut wire);
+        assert_eq!(wire, b"\x07example\x03com\x00");
+        assert!(is_absolute("example.com."));
+        assert!(!is_absolute(r"example\."));
+        assert!(is_absolute(r"example\\."));
+        assert_eq!(relative("example.com."), "example.com");
+        assert_eq!(relative(r"example\."), r"example\.");
+    }
+
+    #[test]
+    fn test_limits() {
+        assert!(Name::parse("a..b").is_err());
+        assert!(Name::parse(".a").is_err());
+        assert!(Name::parse("a.").is_ok());
+        assert!(Name::parse(&"a".repeat(63)).is_ok());
+        assert!(Name::parse(&"a".repeat(64)).is_err());
+        let long = vec!["a".repeat(63); 4].join(".");
+        assert!(Name::parse(&long).is_err());
+        assert!(Name::parse(&long[2..]).is_ok());
+        assert!(Name::parse(r"a\256").is_err());
+        assert!(Name::parse(r"a\").is_err());
+    }
+
+    #[test]
+    fn test_idna() {
+        assert_eq!(Name::parse("Bücher.example").unwrap().to_string(), "xn--bcher-kva.example");
+        assert_eq!(Name::parse("例え。テスト").unwrap().to_string(), "xn--r8jz45g.xn--zckzah");
+        assert!(Name::parse("xn--bcher-kva.example").is_ok());
+        assert!(Name::parse("xn--bcher-kvb!.example").is_err());
+        assert!(Name::parse("xn--ls8h.example").is_err()); // a pictograph
+        assert!(Name::parse("☃.example").is_err());
+        assert!(Name::parse("-bücher.example").is_err());
+        assert!(Name::parse("bü_cher.example").is_err());
+        assert!(Name::parse("under_score.example").is_ok()); // ASCII labels aren't IDNA's business
+        assert!(Name::parse_opaque("xn--bcher-kvb!.example").is_ok());
+        assert!(Name::parse(r"b9588cher.example").is_ok());
+        assert!(Name::parse(r"bü9588.example").is_err());
+    }
+
+    #[test]
+    fn test_escaped_bytes_are_opaque() {
+        // neither UTF-8 nor, for the second, something to punycode: both stay the bytes given
+        for (text, label) in [(r"a\255b", &b"a\xffb"[..]), (r"caf\195\169", &b"caf\xc3\xa9"[..])] {
+            let name = Name::parse(&format!("{}.example", text)).unwrap();
+            assert_eq!(name.labels[0], label);
+            assert_eq!(name.to_string(), format!("{}.example", text));
+            assert_eq!(Name::parse(&name.to_string()).unwrap(), name);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::cli::{ Options, Verbosity };
+use crate::decode::Entry;
+use crate::dnssec::Security;
+use crate::iterative::{ referral, Step };
+use crate::json::message_to_json;
+use crate::mdns::Instance;
+use crate::message::{ class_name, opcode_name, rcode_name, Message, ResourceRecord };
+use crate::rdata::RecordType;
+use crate::transport::{ Attempt, Outcome };
+use crate::xfr::Diff;
+
+// where a response came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Server(SocketAddr),
+    Cache,
+    Hosts,
+}
+
+// prints a response the way dig lays it out
+pub fn print_response(
+    response: &Message,
+    options: &Options,
+    source: Source,
+    elapsed: Duration
+) {
+    if options.json {
+        println!("{}", message_to_json(response));
+        return;
+    }
+    if options.verbosity == Verbosity::Short {
+        for record in response.answers.iter() {
+            println!("{}", record.rdata);
+        }
+        return;
+    }
+
+    let header = &response.header;
+    println!(
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        opcode_name(header.opcode()),
+        rcode_name(response.rcode()),
+        header.id
+    );
+    println!(
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        header.flag_names().join(" "),
+        header.qdcount,
+        header.ancount,
+        header.nscount,
+        header.arcount
+    );
+
+    if let Some(edns) = response.edns() {
+        println!("\n;; OPT PSEUDOSECTION:\n{}", edns);
+    }
+
+    // an UPDATE puts its zone, prerequisites and changes where a query has its sections (RFC 2136 2)
+    let update = header.opcode() == 5;
+    println!("\n;; {} SECTION:", if update { "ZONE" } else { "QUESTION" });
+    for question in response.questions.iter() {
+        println!(";{}.\t\t{}\t{}", question.qname, class_name(question.qclass), question.qtype);
+    }
+
+    let sections = [
+        (if update { "PREREQUISITE" } else { "ANSWER" }, &response.answers),
+        (if update { "UPDATE" } else { "AUTHORITY" }, &response.authorities),
+        ("ADDITIONAL", &response.additionals),
+    ];
+    for (title, records) in sections {
+        // the OPT record is shown in its own pseudosection above
+        let records: Vec<&ResourceRecord> = records
+            .iter()
+            .filter(|r| r.rtype != RecordType::OPT)
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+        println!("\n;; {} SECTION:", title);
+        for record in records {
+            println!("{}", format_record(record));
+        }
+    }
+
+    println!("\n;; Query time: {} msec", elapsed.as_millis());
+    match source {
+        Source::Server(server) => println!(";; SERVER: {}#{}", server.ip(), server.port()),
+        Source::Cache => println!(";; SERVER: (cache)"),
+        Source::Hosts => println!(";; SERVER: (hosts)"),
+    }
+}
+
+// one line per DNS-SD instance: its name, where it listens and its TXT keys
+pub fn print_instances(instances: &[Instance], verbosity: Verbosity) {
+    for instance in instances.iter() {
+        if verbosity == Verbosity::Short {
+            println!("{}.", instance.name);
+            continue;
+        }
+        let target = if instance.host.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{}.:{}", instance.host, instance.port)
+        };
+        let addresses: Vec<String> = instance.addresses.iter().map(|a| a.to_string()).collect();
+        let txt: Vec<String> = instance.txt.iter().map(|t| format!("\"{}\"", t.replace('"', "\\\""))).collect();
+        println!("{}.\t{}\t{}\t{}", instance.name, target, addresses.join(","), txt.join(" "));
+    }
+}
+
+// the changes an IXFR brought, oldest first, marked the way diff marks lines
+pub fn print_diffs(diffs: &[Diff]) {
+    for diff in diffs.iter() {
+        println!("; serial {} to {}", diff.from, diff.to);
+        for record in diff.deleted.iter() {
+            println!("-{}", format_record(record));
+        }
+        for record in diff.added.iter() {
+            println!("+{}", format_record(record));
+        }
+    }
+}
+
+pub fn format_record(record: &ResourceRecord) -> String {
+    format!(
+        "{}.\t{}\t{}\t{}\t{}",
+        record.name,
+        record.ttl,
+        class_name(record.class),
+        record.rtype,
+        record.rdata
+    )
+}
+
+// every query of an iterative resolution the way dig +trace shows them: the records that came
+// back, then who sent them and how long it took. Nameserver lookups made along the way are indented.
+pub fn print_trace(steps: &[Step]) {
+    for step in steps.iter() {
+        let indent = "  ".repeat(step.depth);
+        let asked = format!(
+            "{}. {} from {}#{} ({}. server)",
+            step.question.qname,
+            step.question.qtype,
+            step.server.ip(),
+            step.server.port(),
+            step.zone
+        );
+        let elapsed = step.elapsed.as_millis();
+        match &step.response {
+            Ok(response) => {
+                for record in response.answers.iter().chain(response.authorities.iter()) {
+                    println!("{}{}", indent, format_record(record));
+                }
+                let verdict = match (referral(response), response.rcode()) {
+                    (Some(zone), _) => format!("referral to {}.", zone),
+                    (None, rcode @ (2 | 5)) => format!("{}, lame", rcode_name(rcode)),
+                    (None, rcode) => rcode_name(rcode),
+                };
+                println!("{};; Received {} for {} in {} ms\n", indent, verdict, asked, elapsed);
+            }
+            Err(e) => println!("{};; No reply for {} after {} ms: {}\n", indent, asked, elapsed, e),
+        }
+    }
+}
+
+// the validation verdict, kept off stdout in +short mode like other diagnostics
+pub fn print_security(security: &Security, options: &Options) {
+    if quiet(options) {
+        eprintln!(";; DNSSEC: {}", security);
+    } else {
+        println!(";; DNSSEC: {}", security);
+    }
+}
+
+pub fn print_not_validated(reason: &str, options: &Options) {
+    if quiet(options) {
+        eprintln!(";; DNSSEC: not validated, {}", reason);
+    } else {
+        println!(";; DNSSEC: not validated, {}", reason);
+    }
+}
+
+// lists the servers that timed out or failed before one answered
+pub fn print_attempts(attempts: &[Attempt], options: &Options) {
+    for attempt in attempts.iter() {
+        if matches!(attempt.outcome, Outcome::Answered(_)) {
+            continue;
+        }
+        if quiet(options) {
+            eprintln!(";; {}", attempt);
+        } else {
+            println!(";; {}", attempt);
+        }
+    }
+}
+
+// +short and +json keep stdout for the answer itself, diagnostics go to stderr
+fn quiet(options: &Options) -> bool {
+    options.verbosity == Verbosity::Short || options.json
+}
+
+// a decoded message, one field per line under its offset, problems marked where they were found
+pub fn print_dissection(entries: &[Entry]) {
+    for entry in entries.iter() {
+        match entry {
+            Entry::Section(at, title) => println!("{:04x}  {}", at, title),
+            Entry::Field(at, name, value) => println!("{:04x}    {:<10} {}", at, name, value),
+            Entry::Problem(at, reason) => println!("{:04x}  !! {}", at, reason),
+        }
+    }
+}
+
+// hex dump of the outgoing query for -v
+pub fn print_query(query: &[u8]) {
+    println!(";; QUERY ({} bytes):", query.len());
+    for (i, chunk) in query.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        println!(";; {:04x}  {}", i * 16, hex.join(" "));
+    }
+    println!();
+}
+
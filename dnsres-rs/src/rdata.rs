@@ -0,0 +1,862 @@
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use std::fmt;
+use std::io::{ Cursor, Error };
+use std::net::{ Ipv4Addr, Ipv6Addr };
+use std::str::FromStr;
+
+use crate::edns::{ self, EdnsOption };
+use crate::encoding::{ to_base32hex, to_base64, to_hex };
+use crate::message::{ malformed, rcode_name, read_name, write_name };
+
+#[allow(clippy::upper_case_acronyms)] // keep the RFC mnemonics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+    OPT,
+    DS,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    TSIG,
+    IXFR, // IXFR, AXFR and ANY are only ever asked for, never stored
+    AXFR,
+    ANY,
+    CAA,
+    Unknown(u16),
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    CNAME(String),
+    NS(String),
+    PTR(String),
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    TXT(Vec<Vec<u8>>), // one entry per character-string
+    SOA {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    CAA {
+        flags: u8,
+        tag: String,
+        value: Vec<u8>,
+    },
+    OPT(Vec<EdnsOption>),
+    DS {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    RRSIG {
+        type_covered: RecordType,
+        algorithm: u8,
+        labels: u8, // owner labels, excluding a leading wildcard
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: String,
+        signature: Vec<u8>,
+    },
+    NSEC {
+        next: String,
+        types: Vec<RecordType>,
+    },
+    DNSKEY {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    NSEC3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed: Vec<u8>,
+        types: Vec<RecordType>,
+    },
+    TSIG {
+        algorithm: String,
+        time_signed: u64, // 48 bits of seconds since the epoch
+        fudge: u16, // seconds of clock skew allowed either way
+        mac: Vec<u8>,
+        original_id: u16, // the message id before any forwarder changed it
+        error: u16,
+        other: Vec<u8>, // the server's time when error is BADTIME
+    },
+    Unknown(Vec<u8>),
+}
+
+impl RecordType {
+    pub fn to_u16(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::NS => 2,
+            RecordType::CNAME => 5,
+            RecordType::SOA => 6,
+            RecordType::PTR => 12,
+            RecordType::MX => 15,
+            RecordType::TXT => 16,
+            RecordType::AAAA => 28,
+            RecordType::SRV => 33,
+            RecordType::OPT => 41,
+            RecordType::DS => 43,
+            RecordType::RRSIG => 46,
+            RecordType::NSEC => 47,
+            RecordType::DNSKEY => 48,
+            RecordType::NSEC3 => 50,
+            RecordType::TSIG => 250,
+            RecordType::IXFR => 251,
+            RecordType::AXFR => 252,
+            RecordType::ANY => 255,
+            RecordType::CAA => 257,
+            RecordType::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<u16> for RecordType {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => RecordType::A,
+            2 => RecordType::NS,
+            5 => RecordType::CNAME,
+            6 => RecordType::SOA,
+            12 => RecordType::PTR,
+            15 => RecordType::MX,
+            16 => RecordType::TXT,
+            28 => RecordType::AAAA,
+            33 => RecordType::SRV,
+            41 => RecordType::OPT,
+            43 => RecordType::DS,
+            46 => RecordType::RRSIG,
+            47 => RecordType::NSEC,
+            48 => RecordType::DNSKEY,
+            50 => RecordType::NSEC3,
+            250 => RecordType::TSIG,
+            251 => RecordType::IXFR,
+            252 => RecordType::AXFR,
+            255 => RecordType::ANY,
+            257 => RecordType::CAA,
+            _ => RecordType::Unknown(value),
+        }
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        match upper.as_str() {
+            "A" => Ok(RecordType::A),
+            "NS" => Ok(RecordType::NS),
+            "CNAME" => Ok(RecordType::CNAME),
+            "SOA" => Ok(RecordType::SOA),
+            "PTR" => Ok(RecordType::PTR),
+            "MX" => Ok(RecordType::MX),
+            "TXT" => Ok(RecordType::TXT),
+            "AAAA" => Ok(RecordType::AAAA),
+            "SRV" => Ok(RecordType::SRV),
+            "OPT" => Ok(RecordType::OPT),
+            "DS" => Ok(RecordType::DS),
+            "RRSIG" => Ok(RecordType::RRSIG),
+            "NSEC" => Ok(RecordType::NSEC),
+            "DNSKEY" => Ok(RecordType::DNSKEY),
+            "NSEC3" => Ok(RecordType::NSEC3),
+            "TSIG" => Ok(RecordType::TSIG),
+            "IXFR" => Ok(RecordType::IXFR),
+            "AXFR" => Ok(RecordType::AXFR),
+            "ANY" => Ok(RecordType::ANY),
+            "CAA" => Ok(RecordType::CAA),
+            // RFC 3597 generic form, e.g. TYPE65
+            _ => upper
+                .strip_prefix("TYPE")
+                .and_then(|n| n.parse::<u16>().ok())
+                .map(RecordType::from)
+                .ok_or_else(|| format!("unknown record type: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordType::Unknown(value) => write!(f, "TYPE{}", value),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl RData {
+    pub fn from_bytes(
+        cursor: &mut Cursor<&[u8]>,
+        rtype: RecordType,
+        rdlength: usize
+    ) -> Result<RData, Error> {
+        let start = cursor.position() as usize;
+        let end = start + rdlength;
+        let bytes = *cursor.get_ref();
+        if end > bytes.len() {
+            return Err(malformed("resource record data runs past end of message"));
+        }
+
+        let rdata = match rtype {
+            RecordType::A => {
+                let octets: [u8; 4] = bytes[start..end]
+                    .try_into()
+                    .map_err(|_| malformed("A record must be 4 bytes"))?;
+                RData::A(Ipv4Addr::from(octets))
+            }
+            RecordType::AAAA => {
+                let octets: [u8; 16] = bytes[start..end]
+                    .try_into()
+                    .map_err(|_| malformed("AAAA record must be 16 bytes"))?;
+                RData::AAAA(Ipv6Addr::from(octets))
+            }
+            RecordType::CNAME => RData::CNAME(read_name(cursor)?),
+            RecordType::NS => RData::NS(read_name(cursor)?),
+            RecordType::PTR => RData::PTR(read_name(cursor)?),
+            RecordType::MX => RData::MX {
+                preference: cursor.read_u16::<BigEndian>()?,
+                exchange: read_name(cursor)?,
+            },
+            RecordType::TXT => {
+                let mut strings = vec![];
+                let mut pos = start;
+                while pos < end {
+                    let len = bytes[pos] as usize;
+                    if pos + 1 + len > end {
+                        return Err(malformed("TXT character-string runs past record data"));
+                    }
+                    strings.push(bytes[pos + 1..pos + 1 + len].to_vec());
+                    pos += 1 + len;
+                }
+                cursor.set_position(end as u64);
+                RData::TXT(strings)
+            }
+            RecordType::SOA => RData::SOA {
+                mname: read_name(cursor)?,
+                rname: read_name(cursor)?,
+                serial: cursor.read_u32::<BigEndian>()?,
+                refresh: cursor.read_u32::<BigEndian>()?,
+                retry: cursor.read_u32::<BigEndian>()?,
+                expire: cursor.read_u32::<BigEndian>()?,
+                minimum: cursor.read_u32::<BigEndian>()?,
+            },
+            RecordType::SRV => RData::SRV {
+                priority: cursor.read_u16::<BigEndian>()?,
+                weight: cursor.read_u16::<BigEndian>()?,
+                port: cursor.read_u16::<BigEndian>()?,
+                target: read_name(cursor)?,
+            },
+            RecordType::CAA => {
+                let flags = cursor.read_u8()?;
+                let tag_len = cursor.read_u8()? as usize;
+                let tag_start = start + 2;
+                if tag_start + tag_len > end {
+                    return Err(malformed("CAA tag runs past record data"));
+                }
+                RData::CAA {
+                    flags,
+                    tag: String::from_utf8_lossy(&bytes[tag_start..tag_start + tag_len]).into_owned(),
+                    value: bytes[tag_start + tag_len..end].to_vec(),
+                }
+            }
+            RecordType::OPT => RData::OPT(edns::parse_options(&bytes[start..end])?),
+            RecordType::DS => {
+                if rdlength < 4 {
+                    return Err(malformed("DS record too short"));
+                }
+                RData::DS {
+                    key_tag: cursor.read_u16::<BigEndian>()?,
+                    algorithm: cursor.read_u8()?,
+                    digest_type: cursor.read_u8()?,
+                    digest: bytes[start + 4..end].to_vec(),
+                }
+            }
+            RecordType::RRSIG => {
+                if rdlength < 18 {
+                    return Err(malformed("RRSIG record too short"));
+                }
+                let type_covered = RecordType::from(cursor.read_u16::<BigEndian>()?);
+                let algorithm = cursor.read_u8()?;
+                let labels = cursor.read_u8()?;
+                let original_ttl = cursor.read_u32::<BigEndian>()?;
+                let expiration = cursor.read_u32::<BigEndian>()?;
+                let inception = cursor.read_u32::<BigEndian>()?;
+                let key_tag = cursor.read_u16::<BigEndian>()?;
+                let signer = read_name(cursor)?;
+                let signature = bytes.get(cursor.position() as usize..end).unwrap_or_default().to_vec();
+                RData::RRSIG {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature,
+                }
+            }
+            RecordType::NSEC => {
+                let next = read_name(cursor)?;
+                let bitmap = bytes.get(cursor.position() as usize..end).unwrap_or_default();
+                RData::NSEC { next, types: read_type_bitmap(bitmap)? }
+            }
+            RecordType::DNSKEY => {
+                if rdlength < 4 {
+                    return Err(malformed("DNSKEY record too short"));
+                }
+                RData::DNSKEY {
+                    flags: cursor.read_u16::<BigEndian>()?,
+                    protocol: cursor.read_u8()?,
+                    algorithm: cursor.read_u8()?,
+                    public_key: bytes[start + 4..end].to_vec(),
+                }
+            }
+            RecordType::NSEC3 => {
+                // algorithm, flags, iterations, then length-prefixed salt and hash
+                let field = |pos: usize| bytes.get(pos).filter(|_| pos < end).copied().map(|b| b as usize);
+                let salt_len = field(start + 4).ok_or_else(|| malformed("NSEC3 record too short"))?;
+                let hash_at = start + 5 + salt_len;
+                let hash_len = field(hash_at).ok_or_else(|| malformed("NSEC3 salt runs past record data"))?;
+                let bitmap_at = hash_at + 1 + hash_len;
+                if bitmap_at > end {
+                    return Err(malformed("NSEC3 hash runs past record data"));
+                }
+                RData::NSEC3 {
+                    hash_algorithm: bytes[start],
+                    flags: bytes[start + 1],
+                    iterations: u16::from_be_bytes([bytes[start + 2], bytes[start + 3]]),
+                    salt: bytes[start + 5..hash_at].to_vec(),
+                    next_hashed: bytes[hash_at + 1..bitmap_at].to_vec(),
+                    types: read_type_bitmap(&bytes[bitmap_at..end])?,
+                }
+            }
+            RecordType::TSIG => {
+                let algorithm = read_name(cursor)?;
+                let time_signed = ((cursor.read_u16::<BigEndian>()? as u64) << 32) | cursor.read_u32::<BigEndian>()? as u64;
+                let fudge = cursor.read_u16::<BigEndian>()?;
+                let mac_len = cursor.read_u16::<BigEndian>()? as usize;
+                let mac_at = cursor.position() as usize;
+                let mac = bytes.get(mac_at..mac_at + mac_len).filter(|_| mac_at + mac_len <= end);
+                let mac = mac.ok_or_else(|| malformed("TSIG MAC runs past record data"))?.to_vec();
+                cursor.set_position((mac_at + mac_len) as u64);
+                let original_id = cursor.read_u16::<BigEndian>()?;
+                let error = cursor.read_u16::<BigEndian>()?;
+                let other_len = cursor.read_u16::<BigEndian>()? as usize;
+                let other_at = cursor.position() as usize;
+                let other = bytes.get(other_at..other_at + other_len).filter(|_| other_at + other_len <= end);
+                let other = other.ok_or_else(|| malformed("TSIG other data runs past record data"))?.to_vec();
+                RData::TSIG { algorithm, time_signed, fudge, mac, original_id, error, other }
+            }
+            RecordType::IXFR | RecordType::AXFR | RecordType::ANY | RecordType::Unknown(_) => {
+                RData::Unknown(bytes[start..end].to_vec())
+            }
+        };
+
+        // names inside rdata may not spill over the declared length
+        if cursor.position() as usize > end {
+            return Err(malformed("record data longer than its declared length"));
+        }
+        cursor.set_position(end as u64);
+        Ok(rdata)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = vec![];
+        match self {
+            RData::A(address) => bytes.extend(address.octets()),
+            RData::AAAA(address) => bytes.extend(address.octets()),
+            RData::CNAME(name) | RData::NS(name) | RData::PTR(name) => {
+                write_name(&mut bytes, name)?;
+            }
+            RData::MX { preference, exchange } => {
+                bytes.write_u16::<BigEndian>(*preference)?;
+                write_name(&mut bytes, exchange)?;
+            }
+            RData::TXT(strings) => {
+                for string in strings {
+                    if string.len() > 255 {
+                        return Err(malformed("TXT character-string longer than 255 bytes"));
+                    }
+                    bytes.push(string.len() as u8);
+                    bytes.extend(string);
+                }
+            }
+            RData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                write_name(&mut bytes, mname)?;
+                write_name(&mut bytes, rname)?;
+                bytes.write_u32::<BigEndian>(*serial)?;
+                bytes.write_u32::<BigEndian>(*refresh)?;
+                bytes.write_u32::<BigEndian>(*retry)?;
+                bytes.write_u32::<BigEndian>(*expire)?;
+                bytes.write_u32::<BigEndian>(*minimum)?;
+            }
+            RData::SRV { priority, weight, port, target } => {
+                bytes.write_u16::<BigEndian>(*priority)?;
+                bytes.write_u16::<BigEndian>(*weight)?;
+                bytes.write_u16::<BigEndian>(*port)?;
+                write_name(&mut bytes, target)?;
+            }
+            RData::CAA { flags, tag, value } => {
+                bytes.push(*flags);
+                if tag.len() > 255 {
+                    return Err(malformed("CAA tag longer than 255 bytes"));
+                }
+                bytes.push(tag.len() as u8);
+                bytes.extend(tag.as_bytes());
+                bytes.extend(value);
+            }
+            RData::OPT(options) => edns::write_options(&mut bytes, options)?,
+            RData::DS { key_tag, algorithm, digest_type, digest } => {
+                bytes.write_u16::<BigEndian>(*key_tag)?;
+                bytes.push(*algorithm);
+                bytes.push(*digest_type);
+                bytes.extend(digest);
+            }
+            RData::RRSIG { signature, .. } => {
+                bytes.extend(self.rrsig_header()?);
+                bytes.extend(signature);
+            }
+            RData::NSEC { next, types } => {
+                write_name(&mut bytes, next)?;
+                write_type_bitmap(&mut bytes, types);
+            }
+            RData::DNSKEY { flags, protocol, algorithm, public_key } => {
+                bytes.write_u16::<BigEndian>(*flags)?;
+                bytes.push(*protocol);
+                bytes.push(*algorithm);
+                bytes.extend(public_key);
+            }
+            RData::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed, types } => {
+                bytes.push(*hash_algorithm);
+                bytes.push(*flags);
+                bytes.write_u16::<BigEndian>(*iterations)?;
+                if salt.len() > 255 || next_hashed.len() > 255 {
+                    return Err(malformed("NSEC3 salt or hash longer than 255 bytes"));
+                }
+                bytes.push(salt.len() as u8);
+                bytes.extend(salt);
+                bytes.push(next_hashed.len() as u8);
+                bytes.extend(next_hashed);
+                write_type_bitmap(&mut bytes, types);
+            }
+            RData::TSIG { algorithm, time_signed, fudge, mac, original_id, error, other } => {
+                write_name(&mut bytes, algorithm)?;
+                bytes.write_u16::<BigEndian>((time_signed >> 32) as u16)?;
+                bytes.write_u32::<BigEndian>(*time_signed as u32)?;
+                bytes.write_u16::<BigEndian>(*fudge)?;
+                bytes.write_u16::<BigEndian>(mac.len() as u16)?;
+                bytes.extend(mac);
+                bytes.write_u16::<BigEndian>(*original_id)?;
+                bytes.write_u16::<BigEndian>(*error)?;
+                bytes.write_u16::<BigEndian>(other.len() as u16)?;
+                bytes.extend(other);
+            }
+            RData::Unknown(data) => bytes.extend(data),
+        }
+        Ok(bytes)
+    }
+
+    // RRSIG rdata up to the signature, which is what the signature itself covers (RFC 4034 3.1.8.1)
+    pub fn rrsig_header(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = vec![];
+        if let RData::RRSIG {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer,
+            ..
+        } = self
+        {
+            bytes.write_u16::<BigEndian>(type_covered.to_u16())?;
+            bytes.push(*algorithm);
+            bytes.push(*labels);
+            bytes.write_u32::<BigEndian>(*original_ttl)?;
+            bytes.write_u32::<BigEndian>(*expiration)?;
+            bytes.write_u32::<BigEndian>(*inception)?;
+            bytes.write_u16::<BigEndian>(*key_tag)?;
+            write_name(&mut bytes, signer)?;
+        }
+        Ok(bytes)
+    }
+}
+
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RData::A(address) => write!(f, "{}", address),
+            RData::AAAA(address) => write!(f, "{}", address),
+            RData::CNAME(name) | RData::NS(name) | RData::PTR(name) => write!(f, "{}.", name),
+            RData::MX { preference, exchange } => write!(f, "{} {}.", preference, exchange),
+            RData::TXT(strings) => {
+                let quoted: Vec<String> = strings.iter().map(|s| quote(s)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                write!(
+                    f,
+                    "{}. {}. {} {} {} {} {}",
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum
+                )
+            }
+            RData::SRV { priority, weight, port, target } => {
+                write!(f, "{} {} {} {}.", priority, weight, port, target)
+            }
+            RData::CAA { flags, tag, value } => write!(f, "{} {} {}", flags, tag, quote(value)),
+            RData::OPT(options) => {
+                let options: Vec<String> = options.iter().map(|o| o.to_string()).collect();
+                write!(f, "{}", options.join("; "))
+            }
+            RData::DS { key_tag, algorithm, digest_type, digest } => {
+                write!(f, "{} {} {} {}", key_tag, algorithm, digest_type, to_hex(digest).to_uppercase())
+            }
+            RData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => {
+                write!(
+                    f,
+                    "{} {} {} {} {} {} {} {}. {}",
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    format_time(*expiration),
+                    format_time(*inception),
+                    key_tag,
+                    signer,
+                    to_base64(signature)
+                )
+            }
+            RData::NSEC { next, types } => {
+                write!(f, "{}.", next)?;
+                for rtype in types {
+                    write!(f, " {}", rtype)?;
+                }
+                Ok(())
+            }
+            RData::DNSKEY { flags, protocol, algorithm, public_key } => {
+                write!(f, "{} {} {} {}", flags, protocol, algorithm, to_base64(public_key))
+            }
+            RData::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed, types } => {
+                let salt = if salt.is_empty() { "-".to_string() } else { to_hex(salt).to_uppercase() };
+                write!(f, "{} {} {} {} {}", hash_algorithm, flags, iterations, salt, to_base32hex(next_hashed).to_uppercase())?;
+                for rtype in types {
+                    write!(f, " {}", rtype)?;
+                }
+                Ok(())
+            }
+            // laid out the way dig shows it
+            RData::TSIG { algorithm, time_signed, fudge, mac, original_id, error, other } => {
+                write!(
+                    f,
+                    "{}. {} {} {} {} {} {} {}",
+                    algorithm,
+                    time_signed,
+                    fudge,
+                    mac.len(),
+                    to_base64(mac),
+                    original_id,
+                    tsig_error_name(*error),
+                    other.len()
+                )
+            }
+            // RFC 3597 unknown rdata format
+            RData::Unknown(data) => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                }
+                for b in data {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// TSIG errors share numbers with the extended rcodes, except that 16 means BADSIG here (RFC 8945 3)
+pub fn tsig_error_name(error: u16) -> String {
+    match error {
+        16 => "BADSIG".to_string(),
+        _ => rcode_name(error),
+    }
+}
+
+// NSEC/NSEC3 type bitmaps: windows of 256 types, each a block of up to 32 bytes (RFC 4034 4.1.2)
+fn read_type_bitmap(bytes: &[u8]) -> Result<Vec<RecordType>, Error> {
+    let mut types = vec![];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let window = bytes[pos] as u16;
+        let len = *bytes.get(pos + 1).ok_or_else(|| malformed("truncated type bitmap"))? as usize;
+        let block = bytes
+            .get(pos + 2..pos + 2 + len)
+            .filter(|_| (1..=32).contains(&len))
+            .ok_or_else(|| malformed("bad type bitmap block"))?;
+        for (i, byte) in block.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(RecordType::from((window << 8) | (i as u16 * 8 + bit)));
+                }
+            }
+        }
+        pos += 2 + len;
+    }
+    Ok(types)
+}
+
+fn write_type_bitmap(bytes: &mut Vec<u8>, types: &[RecordType]) {
+    let mut values: Vec<u16> = types.iter().map(|t| t.to_u16()).collect();
+    values.sort_unstable();
+    values.dedup();
+    let mut i = 0;
+    while i < values.len() {
+        let window = values[i] >> 8;
+        let mut block = [0u8; 32];
+        let mut len = 0;
+        while i < values.len() && values[i] >> 8 == window {
+            let low = (values[i] & 0xff) as usize;
+            block[low / 8] |= 0x80 >> (low % 8);
+            len = low / 8 + 1;
+            i += 1;
+        }
+        bytes.push(window as u8);
+        bytes.push(len as u8);
+        bytes.extend(&block[..len]);
+    }
+}
+
+// RRSIG timestamps are shown as YYYYMMDDHHmmSS in UTC
+pub fn format_time(timestamp: u32) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // civil_from_days, shifting the epoch to 0000-03-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+// accepts YYYYMMDDHHmmSS or plain seconds since the epoch
+pub fn parse_time(value: &str) -> Option<u32> {
+    if value.len() != 14 {
+        return value.parse().ok();
+    }
+    let field = |range: std::ops::Range<usize>| value.get(range).and_then(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    // days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u32::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+// character-string in presentation format, escaping quotes and non-printables
+fn quote(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(rtype: RecordType, rdata: RData) {
+        let bytes = rdata.to_bytes().unwrap();
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let decoded = RData::from_bytes(&mut cursor, rtype, bytes.len()).unwrap();
+        assert_eq!(decoded, rdata);
+    }
+
+    #[test]
+    fn test_round_trip_common_types() {
+        round_trip(RecordType::A, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        round_trip(RecordType::AAAA, RData::AAAA("2001:db8::1".parse().unwrap()));
+        round_trip(RecordType::MX, RData::MX {
+            preference: 10,
+            exchange: "mail.example.com".to_string(),
+        });
+        round_trip(RecordType::TXT, RData::TXT(vec![b"v=spf1 -all".to_vec(), vec![]]));
+        round_trip(RecordType::SOA, RData::SOA {
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        });
+        round_trip(RecordType::SRV, RData::SRV {
+            priority: 0,
+            weight: 5,
+            port: 5060,
+            target: "sip.example.com".to_string(),
+        });
+        round_trip(RecordType::CAA, RData::CAA {
+            flags: 0,
+            tag: "issue".to_string(),
+            value: b"letsencrypt.org".to_vec(),
+        });
+    }
+
+    #[test]
+    fn test_round_trip_dnssec_types() {
+        round_trip(RecordType::DS, RData::DS { key_tag: 20326, algorithm: 8, digest_type: 2, digest: vec![0xe0, 0x6d] });
+        round_trip(RecordType::RRSIG, RData::RRSIG {
+            type_covered: RecordType::A,
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 1700000000,
+            inception: 1690000000,
+            key_tag: 12345,
+            signer: "example.com".to_string(),
+            signature: vec![1, 2, 3],
+        });
+        round_trip(RecordType::NSEC, RData::NSEC {
+            next: "www.example.com".to_string(),
+            types: vec![RecordType::A, RecordType::RRSIG, RecordType::NSEC, RecordType::CAA],
+        });
+        round_trip(RecordType::NSEC3, RData::NSEC3 {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 1,
+            salt: vec![0xaa, 0xbb],
+            next_hashed: vec![7; 20],
+            types: vec![],
+        });
+    }
+
+    #[test]
+    fn test_length_bytes_do_not_wrap() {
+        let caa = RData::CAA { flags: 0, tag: "t".repeat(256), value: vec![] };
+        assert!(caa.to_bytes().is_err());
+        let nsec3 = |salt: usize, hash: usize| RData::NSEC3 {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 0,
+            salt: vec![0; salt],
+            next_hashed: vec![0; hash],
+            types: vec![],
+        };
+        assert!(nsec3(255, 255).to_bytes().is_ok());
+        assert!(nsec3(256, 20).to_bytes().is_err());
+        assert!(nsec3(0, 256).to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_type_bitmap_layout() {
+        let mut bytes = vec![];
+        write_type_bitmap(&mut bytes, &[RecordType::MX, RecordType::A, RecordType::CAA]);
+        assert_eq!(bytes, vec![0, 2, 0x40, 0x01, 1, 1, 0x40]);
+        assert!(read_type_bitmap(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_signature_times() {
+        assert_eq!(format_time(0), "19700101000000");
+        assert_eq!(format_time(1709251199), "20240229235959");
+        assert_eq!(parse_time("20240229235959"), Some(1709251199));
+        assert_eq!(parse_time("1709251199"), Some(1709251199));
+        assert_eq!(parse_time("20241301000000"), None);
+    }
+
+    #[test]
+    fn test_record_type_names() {
+        assert_eq!("mx".parse::<RecordType>().unwrap(), RecordType::MX);
+        assert_eq!("TYPE65".parse::<RecordType>().unwrap(), RecordType::Unknown(65));
+        assert_eq!(RecordType::from(257), RecordType::CAA);
+        assert_eq!(RecordType::Unknown(65).to_string(), "TYPE65");
+        assert!("BOGUS".parse::<RecordType>().is_err());
+    }
+
+    #[test]
+    fn test_txt_presentation() {
+        let txt = RData::TXT(vec![b"say \"hi\"".to_vec(), vec![0x01]]);
+        assert_eq!(txt.to_string(), "\"say \\\"hi\\\"\" \"\\001\"");
+    }
+}
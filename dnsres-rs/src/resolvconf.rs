@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::{ Error, ErrorKind };
+use std::iter;
+use std::net::{ IpAddr, Ipv4Addr };
+use std::path::Path;
+use std::time::Duration;
+
+use crate::name::{ is_absolute, relative, Name };
+
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+pub const DNS_PORT: u16 = 53;
+
+// limits the system resolver applies (resolv.h MAXNS, RES_MAXNDOTS and friends)
+const MAX_NAMESERVERS: usize = 3;
+const MAX_NDOTS: usize = 15;
+const MAX_TIMEOUT: u64 = 30;
+const MAX_ATTEMPTS: u32 = 5;
+
+// the parts of resolv.conf(5) we act on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<IpAddr>,
+    pub search: Vec<String>,
+    pub ndots: usize, // names with fewer dots try the search list first
+    pub timeout: Duration,
+    pub attempts: u32,
+}
+
+impl Default for ResolvConf {
+    // what the system resolver assumes without a config file
+    fn default() -> ResolvConf {
+        ResolvConf {
+            nameservers: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            search: vec![],
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+        }
+    }
+}
+
+impl ResolvConf {
+    // a missing file gives the defaults, like it does for libc
+    pub fn load(path: &Path) -> Result<ResolvConf, Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(ResolvConf::parse(&text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(ResolvConf::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // unknown keywords and malformed values are skipped rather than rejected
+    pub fn parse(text: &str) -> ResolvConf {
+        let mut conf = ResolvConf { nameservers: vec![], ..ResolvConf::default() };
+        for line in text.lines() {
+            let line = line.split(['#', ';']).next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    // link-local addresses may carry a %scope we can't use
+                    let address = words.next().and_then(|w| w.split('%').next()?.parse().ok());
+                    if let Some(address) = address {
+                        if conf.nameservers.len() < MAX_NAMESERVERS {
+                            conf.nameservers.push(address);
+                        }
+                    }
+                }
+                // domain and search override each other, the last one wins
+                Some("domain") => {
+                    conf.search = words.next().map(domain).into_iter().collect();
+                }
+                Some("search") => {
+                    conf.search = words.map(domain).collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        conf.set_option(option);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if conf.nameservers.is_empty() {
+            conf.nameservers = ResolvConf::default().nameservers;
+        }
+        conf
+    }
+
+    fn set_option(&mut self, option: &str) {
+        let (key, value) = match option.split_once(':') {
+            Some((key, value)) => (key, value.parse::<u64>().ok()),
+            None => (option, None),
+        };
+        match (key, value) {
+            ("ndots", Some(n)) => self.ndots = (n as usize).min(MAX_NDOTS),
+            ("timeout", Some(n)) => self.timeout = Duration::from_secs(n.clamp(1, MAX_TIMEOUT)),
+            ("attempts", Some(n)) => self.attempts = (n as u32).clamp(1, MAX_ATTEMPTS),
+            _ => {}
+        }
+    }
+
+    // the names to try for a lookup, in order; a trailing dot turns the search list off.
+    // Expansions that would be too long to send are dropped.
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        if is_absolute(name) {
+            return vec![relative(name).to_string()];
+        }
+        let expanded = self.search
+            .iter()
+            .map(|d| format!("{}.{}", name, d))
+            .filter(|n| Name::parse(n).is_ok());
+        if name.matches('.').count() >= self.ndots {
+            iter::once(name.to_string()).chain(expanded).collect()
+        } else {
+            expanded.chain(iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+fn domain(word: &str) -> String {
+    word.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = "\
+# generated by NetworkManager
+domain corp.example
+search Lab.Example. example.net
+nameserver 192.0.2.53
+nameserver fe80::1%eth0
+nameserver not-an-address
+nameserver 192.0.2.54
+nameserver 192.0.2.55
+options ndots:2 timeout:3 attempts:9 rotate
+";
+
+    #[test]
+    fn test_parse_fixture() {
+        let conf = ResolvConf::parse(FIXTURE);
+        assert_eq!(conf.nameservers, vec![
+            "192.0.2.53".parse::<IpAddr>().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "192.0.2.54".parse().unwrap(),
+        ]);
+        assert_eq!(conf.search, vec!["lab.example", "example.net"]);
+        assert_eq!(conf.ndots, 2);
+        assert_eq!(conf.timeout, Duration::from_secs(3));
+        assert_eq!(conf.attempts, MAX_ATTEMPTS);
+
+        let missing = ResolvConf::load(Path::new("/nonexistent/resolv.conf")).unwrap();
+        assert_eq!(missing, ResolvConf::default());
+        assert_eq!(ResolvConf::parse("search a.example\n").nameservers, ResolvConf::default().nameservers);
+    }
+
+    #[test]
+    fn test_search_list_order() {
+        let conf = ResolvConf::parse(FIXTURE);
+        assert_eq!(conf.candidates("www"), vec!["www.lab.example", "www.example.net", "www"]);
+        assert_eq!(conf.candidates("www.example.com"), vec![
+            "www.example.com",
+            "www.example.com.lab.example",
+            "www.example.com.example.net",
+        ]);
+        assert_eq!(conf.candidates("www."), vec!["www"]);
+        assert_eq!(conf.candidates("."), vec![""]);
+        let long = format!("{}.{}", vec!["a".repeat(63); 3].join("."), "b".repeat(57));
+        let conf = ResolvConf::parse("search x longer.example\n");
+        assert_eq!(conf.candidates(&long), vec![long.clone(), format!("{}.x", long)]);
+    }
+}
@@ -0,0 +1,341 @@
+use std::error;
+use std::fmt;
+use std::io::{ self, ErrorKind };
+use std::net::{ IpAddr, SocketAddr };
+use std::path::Path;
+use std::time::Duration;
+
+use crate::cache::{ follow_cnames, synthesize, Cache };
+use crate::edns::{ self, Edns };
+use crate::hosts::{ Hosts, HOSTS };
+use crate::message::{ Message, Question, ResourceRecord };
+use crate::name::{ is_absolute, relative, Name };
+use crate::rdata::{ RData, RecordType };
+use crate::resolvconf::{ ResolvConf, DNS_PORT, RESOLV_CONF };
+use crate::reverse::reverse_name;
+use crate::transport::{ self, Client, RetryPolicy };
+
+#[derive(Debug)]
+pub enum ResolveError {
+    InvalidName(String),
+    Timeout, // no server answered at all
+    NxDomain,
+    ServFail,
+    Refused,
+    Rcode(u16), // any other error rcode
+    Malformed(String), // replies came back but couldn't be decoded
+    Io(io::Error),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolveError::InvalidName(reason) => write!(f, "{}", reason),
+            ResolveError::Timeout => write!(f, "timed out"),
+            ResolveError::NxDomain => write!(f, "no such domain"),
+            ResolveError::ServFail => write!(f, "server failure"),
+            ResolveError::Refused => write!(f, "query refused"),
+            ResolveError::Rcode(rcode) => write!(f, "server returned rcode {}", rcode),
+            ResolveError::Malformed(reason) => write!(f, "malformed response: {}", reason),
+            ResolveError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ResolveError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ResolveError {
+    fn from(e: io::Error) -> ResolveError {
+        match e.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => ResolveError::Timeout,
+            ErrorKind::InvalidData => ResolveError::Malformed(e.to_string()),
+            _ => ResolveError::Io(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolverOptions {
+    pub servers: Vec<SocketAddr>, // tried in order
+    pub search: Vec<String>,
+    pub ndots: usize,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub tcp: bool,
+    pub randomize_case: bool,
+    pub edns: Option<Edns>,
+    pub hosts: Option<Hosts>, // consulted before the servers for A, AAAA and PTR
+    pub cache: bool, // keep answers in memory for their TTL
+}
+
+impl ResolverOptions {
+    // servers, search list and timing from a parsed resolv.conf
+    pub fn from_resolv_conf(conf: &ResolvConf) -> ResolverOptions {
+        ResolverOptions {
+            servers: conf.nameservers.iter().map(|ip| SocketAddr::new(*ip, DNS_PORT)).collect(),
+            search: conf.search.clone(),
+            ndots: conf.ndots,
+            timeout: conf.timeout,
+            retries: conf.attempts.saturating_sub(1),
+            tcp: false,
+            randomize_case: false,
+            edns: Some(Edns::new(edns::DEFAULT_UDP_SIZE)),
+            hosts: None,
+            cache: true,
+        }
+    }
+}
+
+impl Default for ResolverOptions {
+    fn default() -> ResolverOptions {
+        ResolverOptions::from_resolv_conf(&ResolvConf::default())
+    }
+}
+
+// the outcome of a successful lookup; records is empty when the name exists without that type
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub name: String, // the name that answered, after search list expansion
+    pub canonical_name: String, // where the CNAME chain, if any, ended
+    pub qtype: RecordType,
+    pub records: Vec<ResourceRecord>,
+    pub response: Message,
+    pub server: Option<SocketAddr>, // None when it came from the hosts file or the cache
+}
+
+impl Answer {
+    pub fn rdata(&self) -> impl Iterator<Item = &RData> {
+        self.records.iter().map(|r| &r.rdata)
+    }
+
+    // the lowest TTL in the answer, how long it may be reused for
+    pub fn ttl(&self) -> u32 {
+        self.records.iter().map(|r| r.ttl).min().unwrap_or(0)
+    }
+}
+
+// a stub resolver: asks recursive servers, walking the search list like the system resolver
+pub struct Resolver {
+    options: ResolverOptions,
+    client: Client,
+    cache: Option<Cache>,
+}
+
+impl Resolver {
+    pub fn new(options: ResolverOptions) -> Resolver {
+        let mut client = Client::new(RetryPolicy::new(options.timeout, options.retries));
+        client.force_tcp = options.tcp;
+        client.randomize_case = options.randomize_case;
+        let cache = if options.cache { Some(Cache::new()) } else { None };
+        Resolver { options, client, cache }
+    }
+
+    // configured from /etc/resolv.conf and /etc/hosts
+    pub fn system() -> Result<Resolver, ResolveError> {
+        let conf = ResolvConf::load(Path::new(RESOLV_CONF))?;
+        let mut options = ResolverOptions::from_resolv_conf(&conf);
+        options.hosts = Some(Hosts::load(Path::new(HOSTS))?);
+        Ok(Resolver::new(options))
+    }
+
+    pub fn options(&self) -> &ResolverOptions {
+        &self.options
+    }
+
+    // the names a lookup tries, in order (resolv.conf(5) search rules)
+    fn candidates(&self, name: &str) -> Vec<String> {
+        let conf = ResolvConf {
+            nameservers: vec![],
+            search: self.options.search.clone(),
+            ndots: self.options.ndots,
+            timeout: self.options.timeout,
+            attempts: self.options.retries + 1,
+        };
+        conf.candidates(name)
+    }
+
+    pub fn lookup(&mut self, name: &str, qtype: RecordType) -> Result<Answer, ResolveError> {
+        let parsed = Name::parse(name).map_err(ResolveError::InvalidName)?;
+        let name = if is_absolute(name) { format!("{}.", parsed) } else { parsed.to_string() };
+
+        if let Some(hosts) = &self.options.hosts {
+            let records = hosts.lookup(relative(&name), qtype);
+            if !records.is_empty() {
+                let question = Question { qname: relative(&name).to_string(), qtype, qclass: 1 };
+                return Ok(answer(&question, synthesize(&question, 0, records, vec![]), None));
+            }
+        }
+
+        // the first name with an answer wins. Like libc, a failure for one candidate doesn't
+        // stop the walk, but it is what gets reported if nothing else turns up.
+        let mut nodata = None;
+        let mut failure = None;
+        for candidate in self.candidates(&name) {
+            let question = Question { qname: candidate, qtype, qclass: 1 };
+            let (response, server) = self.query(&question)?;
+            let error = match response.rcode() {
+                0 => {
+                    let answer = answer(&question, response, server);
+                    if !answer.records.is_empty() {
+                        return Ok(answer);
+                    }
+                    nodata.get_or_insert(answer);
+                    continue;
+                }
+                3 => continue,
+                2 => ResolveError::ServFail,
+                5 => ResolveError::Refused,
+                rcode => ResolveError::Rcode(rcode),
+            };
+            failure.get_or_insert(error);
+        }
+        match (nodata, failure) {
+            (Some(answer), _) => Ok(answer),
+            (None, Some(error)) => Err(error),
+            (None, None) => Err(ResolveError::NxDomain),
+        }
+    }
+
+    // IPv4 then IPv6 addresses; only fails if both lookups do
+    pub fn lookup_ip(&mut self, name: &str) -> Result<Vec<IpAddr>, ResolveError> {
+        let (v4, v6) = match (self.lookup(name, RecordType::A), self.lookup(name, RecordType::AAAA)) {
+            (Err(e), Err(_)) => return Err(e),
+            answers => answers,
+        };
+        let mut addresses = vec![];
+        for answer in [v4, v6].into_iter().flatten() {
+            for rdata in answer.rdata() {
+                match rdata {
+                    RData::A(ip) => addresses.push(IpAddr::V4(*ip)),
+                    RData::AAAA(ip) => addresses.push(IpAddr::V6(*ip)),
+                    _ => {}
+                }
+            }
+        }
+        Ok(addresses)
+    }
+
+    // the PTR names for an address, without trailing dots
+    pub fn reverse(&mut self, address: IpAddr) -> Result<Vec<String>, ResolveError> {
+        let answer = self.lookup(&format!("{}.", reverse_name(address)), RecordType::PTR)?;
+        Ok(answer
+            .rdata()
+            .filter_map(|rdata| match rdata {
+                RData::PTR(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // one question to the servers, or the cache, with no interpretation of the rcode
+    pub fn query(&mut self, question: &Question) -> Result<(Message, Option<SocketAddr>), ResolveError> {
+        if let Some(response) = self.cache.as_mut().and_then(|c| c.answer(question)) {
+            return Ok((response, None));
+        }
+        let query = transport::build_query(question.clone(), true, self.options.edns.as_ref())?;
+        let (response, server) = self.client.exchange(&query, &self.options.servers, &mut vec![])?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.insert(question, &response);
+        }
+        Ok((response, Some(server)))
+    }
+}
+
+fn answer(question: &Question, response: Message, server: Option<SocketAddr>) -> Answer {
+    let canonical_name = follow_cnames(&question.qname, &response.answers);
+    let records = response.answers
+        .iter()
+        .filter(|r| r.rtype == question.qtype && r.name.eq_ignore_ascii_case(&canonical_name))
+        .cloned()
+        .collect();
+    Answer {
+        name: question.qname.clone(),
+        canonical_name,
+        qtype: question.qtype,
+        records,
+        response,
+        server,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::zone::{ Authority, Zone };
+    use std::net::UdpSocket;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn resolver() -> Resolver {
+        let zone = Zone::parse(include_str!("../tests/zones/example.com.zone"), "").unwrap();
+        let server = Server::bind("127.0.0.1:0".parse().unwrap(), Arc::new(Authority { zones: vec![zone] })).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let conf = ResolvConf::parse("search nowhere.test example.com\noptions timeout:1 attempts:1\n");
+        let mut options = ResolverOptions::from_resolv_conf(&conf);
+        options.servers = vec![addr];
+        options.hosts = Some(Hosts::parse("192.0.2.1 gateway\n"));
+        Resolver::new(options)
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut resolver = resolver();
+        let answer = resolver.lookup("ftp", RecordType::A).unwrap();
+        assert_eq!(answer.name, "ftp.example.com");
+        assert_eq!(answer.canonical_name, "www.example.com");
+        assert_eq!(answer.rdata().collect::<Vec<_>>(), vec![&RData::A([192, 0, 2, 80].into())]);
+        assert_eq!(answer.ttl(), 3600);
+
+        let nodata = resolver.lookup("www.example.com.", RecordType::MX).unwrap();
+        assert!(nodata.records.is_empty());
+        assert!(matches!(resolver.lookup("nope.example.com.", RecordType::A), Err(ResolveError::NxDomain)));
+        assert!(matches!(resolver.lookup("a..b", RecordType::A), Err(ResolveError::InvalidName(_))));
+        // outside the served zone the server refuses
+        assert!(matches!(resolver.lookup("example.org.", RecordType::A), Err(ResolveError::Refused)));
+    }
+
+    #[test]
+    fn test_lookup_ip_and_reverse() {
+        let mut resolver = resolver();
+        let addresses = resolver.lookup_ip("www.example.com").unwrap();
+        assert_eq!(addresses, vec!["192.0.2.80".parse::<IpAddr>().unwrap(), "2001:db8::80".parse().unwrap()]);
+        assert_eq!(resolver.lookup_ip("gateway").unwrap(), vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(resolver.reverse("192.0.2.1".parse().unwrap()).unwrap(), vec!["gateway"]);
+    }
+
+    #[test]
+    fn test_timeout_and_garbage() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut options = ResolverOptions {
+            servers: vec![silent.local_addr().unwrap()],
+            timeout: Duration::from_millis(100),
+            retries: 0,
+            ..ResolverOptions::default()
+        };
+        assert!(matches!(Resolver::new(options.clone()).lookup("example.com.", RecordType::A), Err(ResolveError::Timeout)));
+
+        // replies with a bad header echo back as malformed
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, peer)) = silent.recv_from(&mut buffer) {
+                let mut reply = buffer[..len].to_vec();
+                reply[2] |= 0x80;
+                reply.truncate(len - 2);
+                let _ = silent.send_to(&reply, peer);
+            }
+        });
+        options.timeout = Duration::from_secs(1);
+        let result = Resolver::new(options).lookup("example.com.", RecordType::A);
+        assert!(matches!(result, Err(ResolveError::Malformed(_))), "{:?}", result);
+    }
+}
@@ -0,0 +1,38 @@
+use std::net::IpAddr;
+
+// name to ask PTR for: 4.3.2.1.in-addr.arpa, or nibbles of the address under ip6.arpa
+pub fn reverse_name(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", octets[3], octets[2], octets[1], octets[0])
+        }
+        IpAddr::V6(ip) => {
+            let mut labels = vec![];
+            for byte in ip.octets().iter().rev() {
+                labels.push(format!("{:x}", byte & 0x0f));
+                labels.push(format!("{:x}", byte >> 4));
+            }
+            labels.push("ip6.arpa".to_string());
+            labels.join(".")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4_reverse_name() {
+        assert_eq!(reverse_name("192.0.2.10".parse().unwrap()), "10.2.0.192.in-addr.arpa");
+    }
+
+    #[test]
+    fn test_ipv6_reverse_name() {
+        assert_eq!(
+            reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::io::Error;
+use std::net::{ SocketAddr, TcpListener, TcpStream, UdpSocket };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Duration;
+use threadpool::ThreadPool;
+
+use crate::cache::Cache;
+use crate::dnssec::set_checking_disabled;
+use crate::edns::{ self, Edns };
+use crate::message::{ Header, Message };
+use crate::rdata::RecordType;
+use crate::transport::{ self, Client, RetryPolicy };
+
+const MAX_UDP_QUERY: usize = 4096;
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// anything that can turn a decoded query into a response
+pub trait Handler: Send + Sync {
+    fn handle(&self, query: &Message) -> Message;
+}
+
+// answers udp and tcp queries on one address, handing each to the handler
+pub struct Server {
+    udp: UdpSocket,
+    tcp: TcpListener,
+    handler: Arc<dyn Handler>,
+    threads: usize,
+}
+
+impl Server {
+    // binding port 0 picks a free port and uses it for both protocols
+    pub fn bind(listen: SocketAddr, handler: Arc<dyn Handler>) -> Result<Server, Error> {
+        let udp = UdpSocket::bind(listen)?;
+        let tcp = TcpListener::bind(udp.local_addr()?)?;
+        Ok(Server { udp, tcp, handler, threads: 16 })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.udp.local_addr()
+    }
+
+    // blocks forever; tcp connections get their own thread, udp queries go to a pool
+    pub fn run(self) -> Result<(), Error> {
+        let handler = self.handler.clone();
+        let tcp = self.tcp;
+        thread::spawn(move || {
+            for stream in tcp.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let handler = handler.clone();
+                        thread::spawn(move || serve_tcp(stream, handler.as_ref()));
+                    }
+                    Err(e) => eprintln!("Failed to accept tcp connection: {}", e),
+                }
+            }
+        });
+
+        let pool = ThreadPool::new(self.threads);
+        let mut buffer = [0u8; MAX_UDP_QUERY];
+        loop {
+            let (len, peer) = self.udp.recv_from(&mut buffer)?;
+            let packet = buffer[..len].to_vec();
+            let socket = self.udp.try_clone()?;
+            let handler = self.handler.clone();
+            pool.execute(move || {
+                if let Some(reply) = respond(&packet, handler.as_ref(), true) {
+                    if let Err(e) = socket.send_to(&reply, peer) {
+                        eprintln!("Failed to send reply to {}: {}", peer, e);
+                    }
+                }
+            });
+        }
+    }
+}
+
+fn serve_tcp(mut stream: TcpStream, handler: &dyn Handler) {
+    if stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err() {
+        return;
+    }
+    // several queries may arrive on one connection (RFC 7766 6.2.1)
+    while let Ok(packet) = transport::read_frame(&mut stream) {
+        if let Some(reply) = respond(&packet, handler, false) {
+            if transport::write_frame(&mut stream, &reply).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// decodes, hands off and encodes; udp replies that don't fit are truncated
+fn respond(packet: &[u8], handler: &dyn Handler, udp: bool) -> Option<Vec<u8>> {
+    let query = match Message::from_bytes(packet) {
+        Ok(query) => query,
+        // echo the id back with FORMERR if we got at least a header
+        Err(_) if packet.len() >= 12 => {
+            let id = u16::from_be_bytes([packet[0], packet[1]]);
+            return error_reply(id, 0, 1).to_bytes().ok();
+        }
+        Err(_) => {
+            return None;
+        }
+    };
+    if query.header.is_response() {
+        return None;
+    }
+
+    let response = handler.handle(&query);
+    let bytes = response.to_bytes().ok()?;
+
+    let limit = match query.edns() {
+        Some(edns) => edns.udp_size.max(512) as usize,
+        None => 512,
+    };
+    if udp && bytes.len() > limit {
+        // the OPT record stays, it holds the upper rcode bits and DO (RFC 6891 7)
+        let truncated = Message {
+            header: Header { flags: response.header.flags | 0x0200, ..response.header.clone() },
+            questions: response.questions.clone(),
+            answers: vec![],
+            authorities: vec![],
+            additionals: response.additionals.iter().filter(|r| r.rtype == RecordType::OPT).cloned().collect(),
+        };
+        return truncated.to_bytes().ok();
+    }
+    Some(bytes)
+}
+
+// a reply with no records, echoing the question when there is one
+pub fn error_reply(id: u16, query_flags: u16, rcode: u16) -> Message {
+    Message {
+        header: Header {
+            id,
+            flags: 0x8000 | (query_flags & 0x7900) | 0x0080 | rcode, // qr, opcode + rd, ra
+            qdcount: 0,
+            ancount: 0,
+            nscount: 0,
+            arcount: 0,
+        },
+        questions: vec![],
+        answers: vec![],
+        authorities: vec![],
+        additionals: vec![],
+    }
+}
+
+// caching forwarder: answers from the cache or asks the upstream servers
+pub struct Forwarder {
+    upstreams: Vec<SocketAddr>,
+    policy: RetryPolicy,
+    cache: Mutex<Cache>,
+}
+
+impl Forwarder {
+    pub fn new(upstreams: Vec<SocketAddr>, policy: RetryPolicy, cache: Cache) -> Forwarder {
+        Forwarder { upstreams, policy, cache: Mutex::new(cache) }
+    }
+
+    pub fn cache(&self) -> &Mutex<Cache> {
+        &self.cache
+    }
+
+    // the client's DO and CD bits go upstream with the question
+    fn forward(&self, query: &Message) -> Option<Message> {
+        let question = query.questions[0].clone();
+        let mut edns = Edns::new(edns::DEFAULT_UDP_SIZE);
+        edns.dnssec_ok = query.edns().is_some_and(|e| e.dnssec_ok);
+        let mut message = transport::build_query(question.clone(), true, Some(&edns)).ok()?;
+        if checking_disabled(query) {
+            set_checking_disabled(&mut message);
+        }
+
+        // a client per query so slow upstream answers don't hold up other queries
+        let mut client = Client::new(self.policy);
+        let (response, _) = client.exchange(&message, &self.upstreams, &mut vec![]).ok()?;
+        if cacheable(query) {
+            if let Ok(mut cache) = self.cache.lock() {
+                cache.insert(&question, &response);
+            }
+        }
+        Some(response)
+    }
+}
+
+impl Handler for Forwarder {
+    fn handle(&self, query: &Message) -> Message {
+        if query.header.opcode() != 0 {
+            return with_question(error_reply(query.header.id, query.header.flags, 4), query); // NOTIMP
+        }
+        if query.questions.len() != 1 {
+            return error_reply(query.header.id, query.header.flags, 1); // FORMERR
+        }
+
+        let cached = if cacheable(query) {
+            self.cache.lock().ok().and_then(|mut cache| cache.answer(&query.questions[0]))
+        } else {
+            None
+        };
+        let mut response = match cached.or_else(|| self.forward(query)) {
+            Some(response) => response,
+            None => {
+                return with_question(error_reply(query.header.id, query.header.flags, 2), query); // SERVFAIL
+            }
+        };
+
+        // hand the client our id-free view of the answer: its id, its question, its flags
+        response.header.id = query.header.id;
+        response.header.flags = (response.header.flags & !0x0100) | (query.header.flags & 0x0100);
+        response.header.flags &= !0x0400; // we are not authoritative for forwarded data
+        response.questions = query.questions.clone();
+        // the upstream OPT carries the extended rcode and DO bit, only the size on offer is ours
+        let upstream = response.additionals.iter().position(|r| r.rtype == RecordType::OPT);
+        let mut opt = upstream.map(|i| response.additionals.remove(i));
+        if query.edns().is_some() {
+            let opt = opt.get_or_insert_with(|| Edns::new(edns::DEFAULT_UDP_SIZE).to_record());
+            opt.class = edns::DEFAULT_UDP_SIZE;
+            response.additionals.push(opt.clone());
+        }
+        response
+    }
+}
+
+fn checking_disabled(query: &Message) -> bool {
+    query.header.flags & 0x0010 != 0
+}
+
+// the cache keeps neither signatures nor data a validating upstream would have refused, so
+// queries asking for those always go upstream
+fn cacheable(query: &Message) -> bool {
+    !checking_disabled(query) && !query.edns().is_some_and(|e| e.dnssec_ok)
+}
+
+fn with_question(mut reply: Message, query: &Message) -> Message {
+    reply.questions = query.questions.clone();
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{ Question, ResourceRecord };
+    use crate::rdata::RData;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    // upstream answering 192.0.2.1 for every A query, counting what it receives
+    fn spawn_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut reply = Message::from_bytes(&buffer[..len]).unwrap();
+                reply.header.flags |= 0x8080;
+                reply.answers.push(ResourceRecord {
+                    name: reply.questions[0].qname.clone(),
+                    rtype: RecordType::A,
+                    class: 1,
+                    ttl: 300,
+                    rdata: RData::A([192, 0, 2, 1].into()),
+                });
+                socket.send_to(&reply.to_bytes().unwrap(), peer).unwrap();
+            }
+        });
+        (addr, queries)
+    }
+
+    fn spawn_forwarder(upstream: SocketAddr) -> SocketAddr {
+        let policy = RetryPolicy::new(Duration::from_millis(500), 0);
+        let forwarder = Arc::new(Forwarder::new(vec![upstream], policy, Cache::new()));
+        let server = Server::bind("127.0.0.1:0".parse().unwrap(), forwarder).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    fn query() -> Vec<u8> {
+        let question = Question { qname: "www.example.com".to_string(), qtype: RecordType::A, qclass: 1 };
+        transport::build_query(question, true, None).unwrap()
+    }
+
+    #[test]
+    fn test_forwards_then_answers_from_cache() {
+        let (upstream, queries) = spawn_upstream();
+        let server = spawn_forwarder(upstream);
+        let mut client = Client::new(RetryPolicy::new(Duration::from_secs(1), 0));
+
+        for _ in 0..2 {
+            let response = client.send(&query(), server).unwrap();
+            assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 1].into()));
+            assert!(response.header.flag_names().contains(&"rd"));
+        }
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+        client.force_tcp = true;
+        let response = client.send(&query(), server).unwrap();
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_forwards_dnssec_bits_and_extended_rcode() {
+        // upstream answering BADVERS, which only fits in the rcode with the OPT record's upper bits
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream = socket.local_addr().unwrap();
+        let (seen, seen_by_upstream) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer) {
+                let mut reply = Message::from_bytes(&buffer[..len]).unwrap();
+                let edns = reply.edns().unwrap();
+                seen.send((edns.dnssec_ok, reply.header.flags & 0x0010 != 0)).unwrap();
+                reply.header.flags |= 0x8080;
+                reply.additionals[0].ttl |= 1 << 24;
+                socket.send_to(&reply.to_bytes().unwrap(), peer).unwrap();
+            }
+        });
+        let server = spawn_forwarder(upstream);
+        let mut client = Client::new(RetryPolicy::new(Duration::from_secs(1), 0));
+
+        let question = Question { qname: "www.example.com".to_string(), qtype: RecordType::A, qclass: 1 };
+        let mut edns = Edns::new(edns::DEFAULT_UDP_SIZE);
+        edns.dnssec_ok = true;
+        let mut message = transport::build_query(question.clone(), true, Some(&edns)).unwrap();
+        set_checking_disabled(&mut message);
+        for _ in 0..2 {
+            let response = client.send(&message, server).unwrap();
+            assert_eq!(response.rcode(), 16);
+            assert!(response.edns().unwrap().dnssec_ok);
+            assert_eq!(seen_by_upstream.recv().unwrap(), (true, true));
+        }
+
+        let response = client.send(&transport::build_query(question, true, Some(&Edns::new(512))).unwrap(), server).unwrap();
+        assert_eq!(response.rcode(), 16);
+        assert_eq!(seen_by_upstream.recv().unwrap(), (false, false));
+    }
+
+    #[test]
+    fn test_servfail_when_upstream_is_down() {
+        let dead = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = spawn_forwarder(dead.local_addr().unwrap());
+        let mut client = Client::new(RetryPolicy::new(Duration::from_secs(2), 0));
+        let response = client.send(&query(), server).unwrap();
+        assert_eq!(response.header.rcode(), 2);
+    }
+
+    #[test]
+    fn test_garbage_gets_formerr() {
+        let handler: Arc<dyn Handler> = Arc::new(Forwarder::new(vec![], RetryPolicy::new(Duration::ZERO, 0), Cache::new()));
+        let mut packet = query();
+        packet.truncate(14);
+        let reply = Message::from_bytes(&respond(&packet, handler.as_ref(), true).unwrap()).unwrap();
+        assert_eq!(reply.header.rcode(), 1);
+        assert_eq!(reply.header.id, u16::from_be_bytes([packet[0], packet[1]]));
+    }
+
+    // answers every query with more TXT data than fits in 512 bytes, BADVERS and DO set
+    struct Oversized;
+
+    impl Handler for Oversized {
+        fn handle(&self, query: &Message) -> Message {
+            let mut reply = with_question(error_reply(query.header.id, query.header.flags, 0), query);
+            let rdata = RData::TXT(vec![vec![b'x'; 255]; 4]);
+            let name = query.questions[0].qname.clone();
+            reply.answers.push(ResourceRecord { name, rtype: RecordType::TXT, class: 1, ttl: 300, rdata });
+            let mut edns = Edns::new(edns::DEFAULT_UDP_SIZE);
+            edns.extended_rcode = 1;
+            edns.dnssec_ok = true;
+            reply.additionals.push(edns.to_record());
+            reply
+        }
+    }
+
+    #[test]
+    fn test_truncation_keeps_opt() {
+        let question = Question { qname: "www.example.com".to_string(), qtype: RecordType::TXT, qclass: 1 };
+        let packet = transport::build_query(question, true, Some(&Edns::new(512))).unwrap();
+        let reply = Message::from_bytes(&respond(&packet, &Oversized, true).unwrap()).unwrap();
+        assert!(reply.header.flag_names().contains(&"tc"));
+        assert!(reply.answers.is_empty());
+        assert_eq!(reply.rcode(), 16);
+        assert!(reply.edns().unwrap().dnssec_ok);
+    }
+}
@@ -0,0 +1,251 @@
+use rustls::pki_types::ServerName;
+use rustls::{ ClientConfig, ClientConnection, RootCertStore, StreamOwned };
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ BufRead, BufReader, Error, ErrorKind };
+use std::net::{ SocketAddr, TcpStream };
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::doh::Endpoint;
+
+pub const DOT_PORT: u16 = 853;
+pub const DOH_PORT: u16 = 443;
+
+// where distributions keep the system CA bundle
+pub const CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/cert.pem",
+];
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// DNS over TLS (RFC 7858), or over HTTPS (RFC 8484) when doh is set, in place of plain udp and tcp
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    pub config: Arc<ClientConfig>,
+    pub doh: Option<Endpoint>,
+    pub server_names: HashMap<SocketAddr, String>, // who each certificate has to be issued to, the address if missing
+}
+
+impl Encryption {
+    // trusts the CAs in ca_file, or those in the system bundle
+    pub fn new(ca_file: Option<&Path>, doh: Option<Endpoint>) -> Result<Encryption, Error> {
+        Encryption::with_roots(roots(ca_file)?, doh)
+    }
+
+    fn with_roots(roots: RootCertStore, doh: Option<Endpoint>) -> Result<Encryption, Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(Error::other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        // we speak HTTP/1.1 only, which RFC 8484 allows even though it recommends HTTP/2; RFC 7858
+        // asks for no ALPN at all
+        if doh.is_some() {
+            config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        }
+        Ok(Encryption { config: Arc::new(config), doh, server_names: HashMap::new() })
+    }
+
+    pub fn server_name(&self, server: SocketAddr) -> String {
+        self.server_names.get(&server).cloned().unwrap_or_else(|| server.ip().to_string())
+    }
+
+    // the handshake is finished before returning, so a bad certificate fails here rather than
+    // looking like a broken reply later on
+    pub fn connect(&self, server: SocketAddr, timeout: Duration) -> Result<TlsStream, Error> {
+        let name = ServerName::try_from(self.server_name(server))
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid TLS server name: {}", e)))?;
+        let connection = ClientConnection::new(self.config.clone(), name).map_err(Error::other)?;
+        let socket = TcpStream::connect_timeout(&server, timeout)?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        let mut stream = StreamOwned::new(connection, socket);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        Ok(stream)
+    }
+}
+
+fn roots(ca_file: Option<&Path>) -> Result<RootCertStore, Error> {
+    let path = match ca_file {
+        Some(path) => path,
+        None => CA_BUNDLES
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no system CA bundle found"))?,
+    };
+    let file = File::open(path).map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    parse_roots(&mut BufReader::new(file), &path.display().to_string())
+}
+
+fn parse_roots(pem: &mut dyn BufRead, source: &str) -> Result<RootCertStore, Error> {
+    let certificates = rustls_pemfile::certs(pem).collect::<Result<Vec<_>, Error>>()?;
+    // system bundles can hold a few certificates webpki won't parse, which are no loss
+    let mut store = RootCertStore::empty();
+    store.add_parsable_certificates(certificates);
+    if store.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{}: no CA certificates", source)));
+    }
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doh::Method;
+    use crate::encoding::from_base64;
+    use crate::message::{ Message, Question };
+    use crate::rdata::RecordType;
+    use crate::transport::{ build_query, read_frame, write_frame, Client, Protocol, RetryPolicy };
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::{ ServerConfig, ServerConnection };
+    use std::io::{ Read, Write };
+    use std::net::TcpListener;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::thread;
+
+    const CA: &str = include_str!("../tests/tls/ca.pem");
+    const SERVER_CERT: &[u8] = include_bytes!("../tests/tls/server.pem");
+    const SERVER_KEY: &[u8] = include_bytes!("../tests/tls/server.key");
+
+    // trusts the certificates in pem, straight from memory
+    fn trusting(pem: &[u8], doh: Option<Endpoint>) -> Result<Encryption, Error> {
+        Encryption::with_roots(parse_roots(&mut &pem[..], "test bundle")?, doh)
+    }
+
+    fn server_config() -> Arc<ServerConfig> {
+        let certificates = rustls_pemfile::certs(&mut &SERVER_CERT[..]).collect::<Result<Vec<_>, Error>>().unwrap();
+        let key: PrivateKeyDer = rustls_pemfile::private_key(&mut &SERVER_KEY[..]).unwrap().unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .unwrap();
+        Arc::new(config)
+    }
+
+    // a stand-in for a public resolver with a certificate for dns.test and 127.0.0.1, echoing
+    // every query back as its reply. Speaks DoT, or DoH when http is set, and counts connections.
+    fn stand_in(http: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        let config = server_config();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut stream = StreamOwned::new(ServerConnection::new(config.clone()).unwrap(), socket.unwrap());
+                thread::spawn(move || loop {
+                    let query = if http { read_request(&mut stream) } else { read_frame(&mut stream).ok() };
+                    let Some(mut reply) = query else { break };
+                    reply[2] |= 0x80;
+                    if http {
+                        let head = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+                            reply.len()
+                        );
+                        stream.write_all(&[head.as_bytes(), &reply].concat()).unwrap();
+                    } else {
+                        write_frame(&mut stream, &reply).unwrap();
+                    }
+                });
+            }
+        });
+        (addr, accepted)
+    }
+
+    // the query out of a DoH request, from the dns parameter of a GET or the body of a POST
+    fn read_request<S: Read>(stream: &mut S) -> Option<Vec<u8>> {
+        let mut head = vec![];
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).ok()?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.contains("\r\nHost: dns.test:"));
+        assert!(head.contains("\r\nAccept: application/dns-message\r\n"));
+        if let Some(rest) = head.strip_prefix("GET /dns-query?dns=") {
+            let encoded = rest.split(' ').next().unwrap().replace('-', "+").replace('_', "/");
+            return from_base64(&encoded);
+        }
+        assert!(head.starts_with("POST /dns-query HTTP/1.1\r\n"));
+        assert!(head.contains("\r\nContent-Type: application/dns-message\r\n"));
+        let length = head.lines().find_map(|l| l.strip_prefix("Content-Length: "))?.parse().unwrap();
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).ok()?;
+        Some(body)
+    }
+
+    fn query() -> Vec<u8> {
+        let question = Question { qname: "example.com".to_string(), qtype: RecordType::A, qclass: 1 };
+        build_query(question, true, None).unwrap()
+    }
+
+    fn client(server: SocketAddr, name: &str, doh: Option<Endpoint>) -> Client {
+        let mut encryption = trusting(CA.as_bytes(), doh).unwrap();
+        encryption.server_names.insert(server, name.to_string());
+        let mut client = Client::new(RetryPolicy::new(Duration::from_secs(2), 0));
+        client.encryption = Some(encryption);
+        client
+    }
+
+    #[test]
+    fn test_dns_over_tls() {
+        let (server, accepted) = stand_in(false);
+        let mut client = client(server, "dns.test", None);
+        for _ in 0..2 {
+            let mut attempts = vec![];
+            let (response, _) = client.exchange(&query(), &[server], &mut attempts).unwrap();
+            assert!(response.header.is_response());
+            assert_eq!(attempts[0].protocol, Protocol::Tls);
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        // the certificate is checked against the address when no name is given
+        let mut client = Client::new(RetryPolicy::new(Duration::from_secs(2), 0));
+        client.encryption = Some(trusting(CA.as_bytes(), None).unwrap());
+        assert!(client.send(&query(), server).is_ok());
+    }
+
+    #[test]
+    fn test_certificate_is_checked() {
+        let (server, _) = stand_in(false);
+        let error = client(server, "dns.example", None).send(&query(), server).unwrap_err();
+        assert!(error.to_string().contains("not valid for name"), "{}", error);
+
+        // a CA bundle that doesn't hold the stand-in's CA
+        let mut encryption = trusting(SERVER_CERT, None).unwrap();
+        encryption.server_names.insert(server, "dns.test".to_string());
+        let mut client = Client::new(RetryPolicy::new(Duration::from_secs(2), 0));
+        client.encryption = Some(encryption);
+        assert!(client.send(&query(), server).is_err());
+        assert!(trusting(SERVER_KEY, None).is_err());
+    }
+
+    #[test]
+    fn test_dns_over_https() {
+        let (server, accepted) = stand_in(true);
+        for method in [Method::Get, Method::Post] {
+            let endpoint = Endpoint { path: "/dns-query".to_string(), method };
+            let mut client = client(server, "dns.test", Some(endpoint));
+            let sent = Message::from_bytes(&query()).unwrap();
+            let mut attempts = vec![];
+            let (response, _) = client.exchange(&query(), &[server], &mut attempts).unwrap();
+            assert_eq!(response.questions[0].qname, sent.questions[0].qname);
+            assert_eq!(attempts[0].protocol, Protocol::Https);
+            client.send(&query(), server).unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,748 @@
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{ Error, ErrorKind, Read, Write };
+use std::net::{ SocketAddr, TcpStream, UdpSocket };
+use std::time::{ Duration, Instant };
+use rand::Rng;
+
+use crate::doh;
+use crate::edns::{ Edns, EdnsOption };
+use crate::message::{ malformed, rcode_name, Header, Message, MessageBuilder, Question };
+use crate::tls::{ Encryption, TlsStream, DOH_PORT };
+
+// large enough for any advertised EDNS payload size
+const MAX_MESSAGE_SIZE: usize = 65535;
+
+// a single-question query with a fresh id, optionally carrying an OPT record
+pub fn build_query(
+    question: Question,
+    recursion_desired: bool,
+    edns: Option<&Edns>
+) -> Result<Vec<u8>, Error> {
+    let header = Header {
+        id: rand::random(),
+        flags: if recursion_desired { 0x0100 } else { 0x0000 }, // standard query
+        // counts are filled in by the builder
+        qdcount: 0,
+        ancount: 0,
+        nscount: 0,
+        arcount: 0,
+    };
+    let mut builder = MessageBuilder::new(&header);
+    builder.question(&question)?;
+    if let Some(edns) = edns {
+        let mut edns = edns.clone();
+        if edns.options.iter().any(|o| matches!(o, EdnsOption::Padding(_))) {
+            edns.options.retain(|o| !matches!(o, EdnsOption::Padding(_)));
+            let unpadded = builder.size() + edns.to_record().to_bytes()?.len();
+            edns.pad(unpadded);
+        }
+        builder.additional(&edns.to_record())?;
+    }
+    Ok(builder.finish())
+}
+
+// how long to wait for each try and how often to go around the server list
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub timeout: Duration, // wait for the first round
+    pub retries: u32, // extra rounds over the whole server list
+    pub backoff: u32, // timeout multiplier applied after every round
+    pub max_timeout: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Answered(u8), // rcode of the accepted response
+    TimedOut,
+    Failed(u8), // SERVFAIL or REFUSED, try the next server
+    Error(String),
+}
+
+// how a reply reached us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Udp,
+    Tcp, // either forced or after a truncated udp reply
+    Tls,
+    Https,
+}
+
+// one try against one server, kept so callers can report what happened
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    pub server: SocketAddr,
+    pub outcome: Outcome,
+    pub elapsed: Duration,
+    pub protocol: Protocol,
+}
+
+impl RetryPolicy {
+    pub fn new(timeout: Duration, retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            timeout,
+            retries,
+            backoff: 2,
+            max_timeout: Duration::from_secs(30),
+        }
+    }
+
+    // exponential backoff: timeout * backoff^round, capped
+    pub fn timeout_for(&self, round: u32) -> Duration {
+        let factor = self.backoff.max(1).saturating_pow(round);
+        self.timeout.saturating_mul(factor).min(self.max_timeout)
+    }
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = self.elapsed.as_millis();
+        let via = match self.protocol {
+            Protocol::Udp => "",
+            Protocol::Tcp => " over TCP",
+            Protocol::Tls => " over TLS",
+            Protocol::Https => " over HTTPS",
+        };
+        match &self.outcome {
+            Outcome::Answered(rcode) => {
+                write!(f, "{} answered {} in {} ms{}", self.server, rcode_name(*rcode as u16), ms, via)
+            }
+            Outcome::TimedOut => write!(f, "{} timed out after {} ms", self.server, ms),
+            Outcome::Failed(rcode) => {
+                write!(f, "{} returned {} in {} ms{}", self.server, rcode_name(*rcode as u16), ms, via)
+            }
+            Outcome::Error(e) => write!(f, "{} failed: {}", self.server, e),
+        }
+    }
+}
+
+// an open stream to a server, kept for later queries
+enum Connection {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream>),
+}
+
+impl Connection {
+    fn socket(&self) -> &TcpStream {
+        match self {
+            Connection::Tcp(socket) => socket,
+            Connection::Tls(stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Connection::Tcp(socket) => socket.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            Connection::Tcp(socket) => socket.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Connection::Tcp(socket) => socket.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+// sends queries over udp, falling back to tcp on truncation, or over TLS or HTTPS only when
+// encryption is set. Connections stay open so later queries to the same server can reuse
+// them (RFC 7766 6.2.1, RFC 7858 3.4).
+pub struct Client {
+    pub policy: RetryPolicy,
+    pub force_tcp: bool,
+    pub randomize_case: bool, // 0x20 encoding of the qname (draft-vixie-dnsext-dns0x20)
+    pub encryption: Option<Encryption>,
+    connections: HashMap<SocketAddr, Connection>,
+}
+
+impl Client {
+    pub fn new(policy: RetryPolicy) -> Client {
+        Client {
+            policy,
+            force_tcp: false,
+            randomize_case: false,
+            encryption: None,
+            connections: HashMap::new(),
+        }
+    }
+
+    // single server convenience wrapper around exchange
+    pub fn send(&mut self, message: &[u8], server: SocketAddr) -> Result<Message, Error> {
+        self.exchange(message, &[server], &mut vec![]).map(|(response, _)| response)
+    }
+
+    // rotates through the servers, backing off after each full round; every try is logged in
+    // attempts. A SERVFAIL/REFUSED answer is only returned when nobody gave a better one.
+    pub fn exchange(
+        &mut self,
+        message: &[u8],
+        servers: &[SocketAddr],
+        attempts: &mut Vec<Attempt>
+    ) -> Result<(Message, SocketAddr), Error> {
+        self.exchange_wire(message, servers, attempts).map(|(response, _, server)| (response, server))
+    }
+
+    // exchange, also handing back the reply exactly as it arrived so a TSIG over it can be checked
+    pub fn exchange_wire(
+        &mut self,
+        message: &[u8],
+        servers: &[SocketAddr],
+        attempts: &mut Vec<Attempt>
+    ) -> Result<(Message, Vec<u8>, SocketAddr), Error> {
+        let mut query = message.to_vec();
+        if self.randomize_case {
+            randomize_case(&mut query);
+        }
+        // what a genuine reply has to echo back
+        let expected = Message::from_bytes(&query)?;
+
+        let mut fallback = None;
+        let mut malformed_reply = None;
+
+        for round in 0..=self.policy.retries {
+            let timeout = self.policy.timeout_for(round);
+            for &server in servers {
+                let started = Instant::now();
+                let result = self.send_once(&query, &expected, server, timeout);
+                let elapsed = started.elapsed();
+
+                let protocol = match &result {
+                    Ok((_, _, protocol)) => *protocol,
+                    Err(_) => self.protocol(),
+                };
+                let outcome = match &result {
+                    Ok((response, _, _)) if matches!(response.header.rcode(), 2 | 5) => {
+                        Outcome::Failed(response.header.rcode())
+                    }
+                    Ok((response, _, _)) => Outcome::Answered(response.header.rcode()),
+                    Err(e) if is_timeout(e) => Outcome::TimedOut,
+                    Err(e) => {
+                        // decoding errors, including running off the end of a short reply
+                        if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) {
+                            malformed_reply = Some(e.to_string());
+                        }
+                        Outcome::Error(e.to_string())
+                    }
+                };
+                attempts.push(Attempt { server, outcome: outcome.clone(), elapsed, protocol });
+
+                match (outcome, result) {
+                    (Outcome::Answered(_), Ok((response, wire, _))) => {
+                        return Ok((response, wire, server));
+                    }
+                    (Outcome::Failed(_), Ok((response, wire, _))) => {
+                        fallback = Some((response, wire, server));
+                    }
+                    _ => {}
+                }
+            }
+            // a server that refuses us won't change its mind on retry
+            if fallback.is_some() {
+                break;
+            }
+        }
+
+        fallback.ok_or_else(|| {
+            let timed_out = attempts.iter().all(|a| a.outcome == Outcome::TimedOut);
+            if timed_out {
+                Error::new(ErrorKind::TimedOut, "no response from any server")
+            } else if let Some(reason) = malformed_reply {
+                malformed(&format!("no usable response from any server, last: {}", reason))
+            } else {
+                Error::other("no usable response from any server")
+            }
+        })
+    }
+
+    // what queries are sent over before any udp reply turns out to be truncated
+    fn protocol(&self) -> Protocol {
+        match &self.encryption {
+            Some(encryption) if encryption.doh.is_some() => Protocol::Https,
+            Some(_) => Protocol::Tls,
+            None if self.force_tcp => Protocol::Tcp,
+            None => Protocol::Udp,
+        }
+    }
+
+    // the response, its wire form and how it got here
+    fn send_once(
+        &mut self,
+        message: &[u8],
+        expected: &Message,
+        server: SocketAddr,
+        timeout: Duration
+    ) -> Result<(Message, Vec<u8>, Protocol), Error> {
+        let protocol = self.protocol();
+        if protocol == Protocol::Udp {
+            let (response, wire) = send_udp(message, expected, server, timeout, self.randomize_case)?;
+            if !response.header.is_truncated() {
+                return Ok((response, wire, Protocol::Udp));
+            }
+        }
+        let (response, wire) = self.send_stream(message, expected, server, timeout)?;
+        Ok((response, wire, if protocol == Protocol::Udp { Protocol::Tcp } else { protocol }))
+    }
+
+    fn send_stream(
+        &mut self,
+        message: &[u8],
+        expected: &Message,
+        server: SocketAddr,
+        timeout: Duration
+    ) -> Result<(Message, Vec<u8>), Error> {
+        let exact_case = self.randomize_case;
+        let encryption = self.encryption.as_ref();
+
+        // the server may have closed an idle connection, which only shows once we use it: then we
+        // reconnect. Timeouts and bad replies are the server's answer and the query isn't sent twice.
+        if let Some(connection) = self.connections.get_mut(&server) {
+            match round_trip(connection, encryption, server, message, expected, timeout, exact_case) {
+                Ok(response) => {
+                    return Ok(response);
+                }
+                Err(e) => {
+                    self.connections.remove(&server);
+                    if !is_closed(&e) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        let mut connection = match encryption {
+            Some(encryption) => Connection::Tls(Box::new(encryption.connect(server, timeout)?)),
+            None => Connection::Tcp(TcpStream::connect_timeout(&server, timeout)?),
+        };
+        let response = round_trip(&mut connection, encryption, server, message, expected, timeout, exact_case)?;
+        self.connections.insert(server, connection);
+        Ok(response)
+    }
+}
+
+// waits for a reply that passes validation; anything else arriving on the socket in the
+// meantime is dropped, so a spoofed datagram can't cut the query short
+fn send_udp(
+    message: &[u8],
+    expected: &Message,
+    server: SocketAddr,
+    timeout: Duration,
+    exact_case: bool
+) -> Result<(Message, Vec<u8>), Error> {
+    let socket = bind_random(server)?;
+    socket.send_to(message, server)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(ErrorKind::TimedOut, "no valid response before timeout"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (len, peer) = socket.recv_from(&mut buffer)?;
+        if peer != server {
+            continue;
+        }
+        let response = match Message::from_bytes(&buffer[..len]) {
+            Ok(response) => response,
+            // undecodable but carrying our id: the server itself sent garbage
+            Err(e) if len >= 2 && buffer[..2] == message[..2] => {
+                return Err(e);
+            }
+            Err(_) => {
+                continue;
+            }
+        };
+        if validate(&response, expected, exact_case).is_ok() {
+            return Ok((response, buffer[..len].to_vec()));
+        }
+    }
+}
+
+// a fresh socket on a random source port for every query, so an off-path attacker has to
+// guess the port as well as the id
+fn bind_random(server: SocketAddr) -> Result<UdpSocket, Error> {
+    let ip = if server.is_ipv4() { "0.0.0.0" } else { "::" };
+    let mut rng = rand::thread_rng();
+    for _ in 0..8 {
+        let port: u16 = rng.gen_range(1024..=65535);
+        if let Ok(socket) = UdpSocket::bind((ip, port)) {
+            return Ok(socket);
+        }
+    }
+    UdpSocket::bind((ip, 0))
+}
+
+fn round_trip(
+    connection: &mut Connection,
+    encryption: Option<&Encryption>,
+    server: SocketAddr,
+    message: &[u8],
+    expected: &Message,
+    timeout: Duration,
+    exact_case: bool
+) -> Result<(Message, Vec<u8>), Error> {
+    connection.socket().set_read_timeout(Some(timeout))?;
+    connection.socket().set_write_timeout(Some(timeout))?;
+
+    // HTTP pairs every reply with its request
+    if let Some((encryption, endpoint)) = encryption.and_then(|e| e.doh.as_ref().map(|doh| (e, doh))) {
+        let mut authority = encryption.server_name(server);
+        if authority.contains(':') {
+            authority = format!("[{}]", authority);
+        }
+        if server.port() != DOH_PORT {
+            authority = format!("{}:{}", authority, server.port());
+        }
+        let body = doh::exchange(connection, &authority, endpoint, message)?;
+        let response = Message::from_bytes(&body)?;
+        validate(&response, expected, exact_case).map_err(malformed)?;
+        return Ok((response, body));
+    }
+
+    write_frame(connection, message)?;
+    connection.flush()?;
+    // a reused connection can still hold the answer to an earlier query that timed out
+    loop {
+        let frame = read_frame(connection)?;
+        let response = Message::from_bytes(&frame)?;
+        if response.header.id != expected.header.id {
+            continue;
+        }
+        validate(&response, expected, exact_case).map_err(malformed)?;
+        return Ok((response, frame));
+    }
+}
+
+// checks a reply against the query: QR set, same id, same question. With 0x20 the qname
+// must come back in exactly the case we sent.
+pub fn validate(response: &Message, query: &Message, exact_case: bool) -> Result<(), &'static str> {
+    if !response.header.is_response() {
+        return Err("reply does not have the QR bit set");
+    }
+    if response.header.id != query.header.id {
+        return Err("reply id does not match query");
+    }
+    // FORMERR replies may legitimately leave out the question
+    if response.questions.is_empty() && response.header.rcode() == 1 {
+        return Ok(());
+    }
+    if response.questions.len() != query.questions.len() {
+        return Err("reply question count does not match query");
+    }
+    for (got, sent) in response.questions.iter().zip(query.questions.iter()) {
+        let same_name = if exact_case {
+            got.qname == sent.qname
+        } else {
+            got.qname.eq_ignore_ascii_case(&sent.qname)
+        };
+        if !same_name || got.qtype != sent.qtype || got.qclass != sent.qclass {
+            return Err("reply question does not match query");
+        }
+    }
+    Ok(())
+}
+
+// flips the case of each letter in the first question's name at random
+pub fn randomize_case(message: &mut [u8]) {
+    let mut rng = rand::thread_rng();
+    let mut pos = 12; // question name starts right after the header
+    while pos < message.len() && message[pos] != 0 && message[pos] & 0xc0 == 0 {
+        let end = (pos + 1 + (message[pos] as usize)).min(message.len());
+        for b in message[pos + 1..end].iter_mut() {
+            if b.is_ascii_alphabetic() && rng.gen::<bool>() {
+                *b ^= 0x20;
+            }
+        }
+        pos = end;
+    }
+}
+
+// two-byte length prefix framing for dns over tcp (RFC 1035 4.2.2)
+pub fn write_frame<W: Write>(stream: &mut W, message: &[u8]) -> Result<(), Error> {
+    if message.len() > u16::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "message too large for tcp framing"));
+    }
+    let mut frame = Vec::with_capacity(message.len() + 2);
+    frame.write_u16::<BigEndian>(message.len() as u16)?;
+    frame.extend_from_slice(message);
+    stream.write_all(&frame)
+}
+
+pub fn read_frame<R: Read>(stream: &mut R) -> Result<Vec<u8>, Error> {
+    let len = stream.read_u16::<BigEndian>()? as usize;
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+// what writing to or reading from a connection the other end has dropped gives
+fn is_closed(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::UnexpectedEof |
+            ErrorKind::ConnectionReset |
+            ErrorKind::ConnectionAborted |
+            ErrorKind::BrokenPipe |
+            ErrorKind::NotConnected
+    )
+}
+
+// read timeouts surface as WouldBlock on unix and TimedOut on windows
+fn is_timeout(e: &Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata::RecordType;
+    use std::net::TcpListener;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::sync::Arc;
+    use std::thread;
+
+    // answers every query with the given rcode
+    fn spawn(rcode: u16) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer) {
+                let mut reply = buffer[..len].to_vec();
+                reply[2] |= 0x80;
+                reply[3] = (reply[3] & 0xf0) | (rcode as u8);
+                socket.send_to(&reply, peer).unwrap();
+            }
+        });
+        addr
+    }
+
+    // bound but never answers
+    fn silent() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    fn query() -> Vec<u8> {
+        let question = Question { qname: "example.com".to_string(), qtype: RecordType::A, qclass: 1 };
+        build_query(question, true, None).unwrap()
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(Duration::from_millis(50), 1)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy { max_timeout: Duration::from_millis(300), ..policy() };
+        assert_eq!(policy.timeout_for(0), Duration::from_millis(50));
+        assert_eq!(policy.timeout_for(2), Duration::from_millis(200));
+        assert_eq!(policy.timeout_for(5), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_fails_over_past_timeouts_and_servfail() {
+        let (_keep, dead) = silent();
+        let broken = spawn(2);
+        let good = spawn(0);
+
+        let mut attempts = vec![];
+        let (response, server) = Client::new(policy()).exchange(&query(), &[dead, broken, good], &mut attempts).unwrap();
+        assert_eq!(server, good);
+        assert_eq!(response.header.rcode(), 0);
+        let outcomes: Vec<Outcome> = attempts.into_iter().map(|a| a.outcome).collect();
+        assert_eq!(outcomes, vec![Outcome::TimedOut, Outcome::Failed(2), Outcome::Answered(0)]);
+    }
+
+    #[test]
+    fn test_refused_everywhere_returns_last_answer() {
+        let mut attempts = vec![];
+        let (response, _) = Client::new(policy()).exchange(&query(), &[spawn(5)], &mut attempts).unwrap();
+        assert_eq!(response.header.rcode(), 5);
+        assert_eq!(attempts.len(), 1);
+    }
+
+    #[test]
+    fn test_all_silent_times_out() {
+        let (_keep, dead) = silent();
+        let mut attempts = vec![];
+        let error = Client::new(policy()).exchange(&query(), &[dead], &mut attempts).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(attempts.len(), 2); // one retry round
+    }
+
+    // udp side always truncates, tcp side answers and counts accepted connections
+    fn spawn_truncating() -> (SocketAddr, Arc<AtomicUsize>) {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let listener = TcpListener::bind(addr).unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, peer)) = udp.recv_from(&mut buffer) {
+                let mut reply = buffer[..len].to_vec();
+                reply[2] |= 0x82; // QR + TC
+                udp.send_to(&reply, peer).unwrap();
+            }
+        });
+        let counter = accepted.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    while let Ok(query) = read_frame(&mut stream) {
+                        let mut reply = query.clone();
+                        reply[2] |= 0x80;
+                        write_frame(&mut stream, &reply).unwrap();
+                    }
+                });
+            }
+        });
+        (addr, accepted)
+    }
+
+    #[test]
+    fn test_truncated_reply_retried_over_tcp() {
+        let (server, _) = spawn_truncating();
+        let mut attempts = vec![];
+        let (response, _) = Client::new(policy()).exchange(&query(), &[server], &mut attempts).unwrap();
+        assert!(!response.header.is_truncated());
+        assert_eq!(attempts[0].protocol, Protocol::Tcp);
+    }
+
+    #[test]
+    fn test_tcp_connection_is_reused() {
+        let (server, accepted) = spawn_truncating();
+        let mut client = Client::new(policy());
+        client.force_tcp = true;
+        for _ in 0..3 {
+            client.send(&query(), server).unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    // a tcp server that handles the n-th query it gets, on whatever connection, the n-th way:
+    // 'a' answers, 'c' hangs up without answering, 'q' answers without the QR bit
+    fn spawn_scripted(script: &'static str) -> (SocketAddr, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let queries = Arc::new(AtomicUsize::new(0));
+        let (connections, counter) = (accepted.clone(), queries.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                connections.fetch_add(1, Ordering::SeqCst);
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    while let Ok(query) = read_frame(&mut stream) {
+                        let n = counter.fetch_add(1, Ordering::SeqCst);
+                        let mut reply = query.clone();
+                        match script.as_bytes().get(n) {
+                            Some(b'a') => reply[2] |= 0x80,
+                            Some(b'q') => {}
+                            _ => return,
+                        }
+                        write_frame(&mut stream, &reply).unwrap();
+                    }
+                });
+            }
+        });
+        (addr, accepted, queries)
+    }
+
+    #[test]
+    fn test_reconnects_only_when_closed() {
+        let client = || {
+            let mut client = Client::new(RetryPolicy::new(Duration::from_secs(1), 0));
+            client.force_tcp = true;
+            client
+        };
+
+        // the reused connection was dropped: the query goes out again on a new one
+        let (server, accepted, queries) = spawn_scripted("aca");
+        let mut closing = client();
+        closing.send(&query(), server).unwrap();
+        closing.send(&query(), server).unwrap();
+        assert_eq!((accepted.load(Ordering::SeqCst), queries.load(Ordering::SeqCst)), (2, 3));
+
+        // a bad reply on the reused connection is an answer; the query isn't repeated
+        let (server, accepted, queries) = spawn_scripted("aq");
+        let mut bad = client();
+        bad.send(&query(), server).unwrap();
+        assert!(bad.send(&query(), server).is_err());
+        assert_eq!((accepted.load(Ordering::SeqCst), queries.load(Ordering::SeqCst)), (1, 2));
+    }
+
+    #[test]
+    fn test_spoofed_replies_are_ignored() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let attacker = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let mut buffer = [0u8; 512];
+            let (len, peer) = socket.recv_from(&mut buffer).unwrap();
+            let mut reply = buffer[..len].to_vec();
+            reply[2] |= 0x80;
+
+            // right id from the wrong address, then wrong id from the right one
+            attacker.send_to(&reply, peer).unwrap();
+            let mut wrong_id = reply.clone();
+            wrong_id[0] ^= 0xff;
+            socket.send_to(&wrong_id, peer).unwrap();
+
+            reply[3] = 0x03; // the genuine answer is NXDOMAIN
+            socket.send_to(&reply, peer).unwrap();
+        });
+
+        let response = Client::new(policy()).send(&query(), server).unwrap();
+        assert_eq!(response.header.rcode(), 3);
+    }
+
+    #[test]
+    fn test_validate_question_and_case() {
+        let mut sent = query();
+        randomize_case(&mut sent);
+        let query = Message::from_bytes(&sent).unwrap();
+        assert!(query.questions[0].qname.eq_ignore_ascii_case("example.com"));
+
+        let mut response = query.clone();
+        response.header.flags |= 0x8000;
+        assert!(validate(&response, &query, true).is_ok());
+
+        // case only matters once we randomized it
+        response.questions[0].qname = "EXAMPLE.COM".to_string();
+        assert!(validate(&response, &query, false).is_ok());
+        let upper_sent = query.questions[0].qname == "EXAMPLE.COM";
+        assert_eq!(validate(&response, &query, true).is_ok(), upper_sent);
+
+        response.questions[0].qname = "example.org".to_string();
+        assert!(validate(&response, &query, false).is_err());
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut wire = vec![];
+        write_frame(&mut wire, &[1, 2, 3]).unwrap();
+        assert_eq!(wire, vec![0, 3, 1, 2, 3]);
+        assert_eq!(read_frame(&mut wire.as_slice()).unwrap(), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,509 @@
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use ring::hmac;
+use std::fmt;
+use std::fs;
+use std::io::{ Cursor, Error, ErrorKind };
+use std::path::Path;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::encoding::from_base64;
+use crate::message::{ Header, Question, ResourceRecord, CLASS_ANY };
+use crate::name::Name;
+use crate::rdata::{ tsig_error_name, RData, RecordType };
+
+pub const HMAC_SHA256: &str = "hmac-sha256";
+
+// what BIND and Knot use by default (RFC 8945 10)
+pub const DEFAULT_FUDGE: u16 = 300;
+
+const BADTIME: u16 = 18;
+
+// a shared secret, as tsig-keygen writes it
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    pub name: String,
+    pub algorithm: String,
+    pub secret: Vec<u8>,
+}
+
+// keeps the secret out of logs and panics
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key {{ name: {:?}, algorithm: {:?} }}", self.name, self.algorithm)
+    }
+}
+
+impl Key {
+    pub fn load(path: &Path) -> Result<Key, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Key::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // the first `key "name" { algorithm hmac-sha256; secret "base64"; };` clause in named.conf syntax
+    pub fn parse(text: &str) -> Result<Key, String> {
+        let tokens = tokenize(text)?;
+        let start = tokens.iter().position(|t| t == "key").ok_or("no key clause")?;
+        let mut tokens = tokens[start + 1..].iter().map(|t| t.as_str());
+
+        let name = tokens.next().ok_or("key clause without a name")?;
+        let name = Name::parse(name)?.to_string().to_ascii_lowercase();
+        if tokens.next() != Some("{") {
+            return Err(format!("expected {{ after key {}", name));
+        }
+        let (mut algorithm, mut secret) = (None, None);
+        loop {
+            match tokens.next() {
+                Some("}") => break,
+                Some("algorithm") => algorithm = tokens.next().map(|a| a.to_ascii_lowercase()),
+                Some("secret") => {
+                    let value = tokens.next().ok_or("secret without a value")?;
+                    secret = Some(from_base64(value).ok_or("secret is not valid base64")?);
+                }
+                Some(";") => continue,
+                Some(other) => return Err(format!("unexpected '{}' in key {}", other, name)),
+                None => return Err(format!("key {} is not closed", name)),
+            }
+            if tokens.next() != Some(";") {
+                return Err(format!("missing ; in key {}", name));
+            }
+        }
+
+        let algorithm = algorithm.ok_or_else(|| format!("key {} has no algorithm", name))?;
+        if algorithm.trim_end_matches('.') != HMAC_SHA256 {
+            return Err(format!("unsupported TSIG algorithm: {}", algorithm));
+        }
+        let secret = secret.ok_or_else(|| format!("key {} has no secret", name))?;
+        Ok(Key { name, algorithm: HMAC_SHA256.to_string(), secret })
+    }
+}
+
+// words, quoted strings and the punctuation of named.conf, with #, // and /* */ comments dropped
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => last = c,
+                        None => return Err("unterminated comment".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => token.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(token);
+            }
+            '{' | '}' | ';' => tokens.push(c.to_string()),
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{};\"#".contains(*c)) {
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// appends a TSIG record to a finished message (RFC 8945 5.1); request_mac is the MAC of the
+// request being answered, empty when signing a request. Returns the signed message and its MAC,
+// which the reply will be signed over.
+pub fn sign(message: &[u8], key: &Key, time_signed: u64, request_mac: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    if message.len() < 12 {
+        return Err(failure("message too short to sign"));
+    }
+    let mut rdata = RData::TSIG {
+        algorithm: key.algorithm.clone(),
+        time_signed,
+        fudge: DEFAULT_FUDGE,
+        mac: vec![],
+        original_id: u16::from_be_bytes([message[0], message[1]]),
+        error: 0,
+        other: vec![],
+    };
+    let mac = digest(key, request_mac, message, &rdata, false)?;
+    if let RData::TSIG { mac: slot, .. } = &mut rdata {
+        slot.clone_from(&mac);
+    }
+    let record = ResourceRecord { name: key.name.clone(), rtype: RecordType::TSIG, class: CLASS_ANY, ttl: 0, rdata };
+
+    let mut signed = message.to_vec();
+    let arcount = u16::from_be_bytes([message[10], message[11]]);
+    let arcount = arcount.checked_add(1).ok_or_else(|| failure("no room for a TSIG record"))?;
+    signed[10..12].copy_from_slice(&arcount.to_be_bytes());
+    signed.extend(record.to_bytes()?);
+    Ok((signed, mac))
+}
+
+// checks the TSIG record a signed message has to end with, as it came off the wire; request_mac
+// is the MAC our query carried when this is the reply to it. now is the local clock in seconds.
+pub fn verify(message: &[u8], key: &Key, request_mac: &[u8], now: u64) -> Result<(), Error> {
+    check(message, key, request_mac, &[], false, now).map(|_| ())
+}
+
+// checks a reply that comes as a stream of messages, like a zone transfer (RFC 8945 5.3.1). The
+// first and last messages are signed, those in between may not be; each MAC covers the one
+// before it and every message since, followed by just the time signed and fudge.
+pub struct StreamVerifier<'a> {
+    key: &'a Key,
+    prior_mac: Vec<u8>, // the request MAC until the first message is checked
+    unsigned: Vec<u8>, // messages since the last signed one
+    pending: usize,
+    first: bool,
+}
+
+impl<'a> StreamVerifier<'a> {
+    pub fn new(key: &'a Key, request_mac: &[u8]) -> StreamVerifier<'a> {
+        StreamVerifier { key, prior_mac: request_mac.to_vec(), unsigned: vec![], pending: 0, first: true }
+    }
+
+    pub fn next(&mut self, message: &[u8], now: u64) -> Result<(), Error> {
+        if !self.first && find(message)?.is_none() {
+            // the RFC lets a server leave out at most 99 in a row
+            if self.pending == 99 {
+                return Err(failure("more than 99 unsigned messages in a row"));
+            }
+            self.unsigned.extend(message);
+            self.pending += 1;
+            return Ok(());
+        }
+        self.prior_mac = check(message, self.key, &self.prior_mac, &self.unsigned, !self.first, now)?;
+        self.unsigned.clear();
+        self.pending = 0;
+        self.first = false;
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.first || self.pending > 0 {
+            return Err(failure("the last message is not signed"));
+        }
+        Ok(())
+    }
+}
+
+// verify, with the messages that came since prior_mac was checked and the MAC of this one handed
+// back for the next message to be checked against
+fn check(
+    message: &[u8],
+    key: &Key,
+    prior_mac: &[u8],
+    preceding: &[u8],
+    timers_only: bool,
+    now: u64
+) -> Result<Vec<u8>, Error> {
+    let (start, record) = find(message)?.ok_or_else(|| failure("reply is not signed"))?;
+    let (algorithm, time_signed, fudge, mac, original_id, error, other) = match &record.rdata {
+        RData::TSIG { algorithm, time_signed, fudge, mac, original_id, error, other } => {
+            (algorithm, *time_signed, *fudge, mac, *original_id, *error, other)
+        }
+        _ => return Err(failure("malformed TSIG record")),
+    };
+    if !record.name.eq_ignore_ascii_case(&key.name) || !algorithm.eq_ignore_ascii_case(&key.algorithm) {
+        return Err(failure(&format!("signed with key {} ({}), not {}", record.name, algorithm, key.name)));
+    }
+    // the server couldn't check our signature, so it sent none of its own (RFC 8945 5.3.2)
+    if error != 0 && error != BADTIME {
+        return Err(failure(&format!("server reported {}", tsig_error_name(error))));
+    }
+    // a MAC cut short is allowed by RFC 8945 5.2.2.1, but nobody sends one and we won't accept it
+    if mac.len() != 32 {
+        return Err(failure("truncated MAC"));
+    }
+
+    // the MAC covers the message as it was before the TSIG record went on
+    let mut unsigned = preceding.to_vec();
+    let at = unsigned.len();
+    unsigned.extend(&message[..start]);
+    let arcount = u16::from_be_bytes([message[10], message[11]]) - 1;
+    unsigned[at..at + 2].copy_from_slice(&original_id.to_be_bytes());
+    unsigned[at + 10..at + 12].copy_from_slice(&arcount.to_be_bytes());
+    // ring compares in constant time, so timing doesn't give away how much of a MAC was right
+    let data = signed_data(key, prior_mac, &unsigned, &record.rdata, timers_only)?;
+    if hmac::verify(&hmac_key(key), &data, mac).is_err() {
+        return Err(failure("BADSIG: the MAC does not match"));
+    }
+    let expected = mac.to_vec();
+    if error == BADTIME {
+        let clock = server_time(other).map(|t| format!(", its clock says {}", t)).unwrap_or_default();
+        return Err(failure(&format!("server reported BADTIME{}", clock)));
+    }
+    // only a correctly signed time is worth complaining about (RFC 8945 5.2.3)
+    if now.abs_diff(time_signed) > fudge as u64 {
+        return Err(failure(&format!("BADTIME: signed at {}, our clock says {}", time_signed, now)));
+    }
+    Ok(expected)
+}
+
+// where the TSIG record starts and the record itself, if the last additional record is one
+fn find(message: &[u8]) -> Result<Option<(usize, ResourceRecord)>, Error> {
+    let mut cursor = Cursor::new(message);
+    let header = Header::from_bytes(&mut cursor)?;
+    for _ in 0..header.qdcount {
+        Question::from_bytes(&mut cursor)?;
+    }
+    let records = header.ancount as usize + header.nscount as usize + header.arcount as usize;
+    let mut last = None;
+    for _ in 0..records {
+        let start = cursor.position() as usize;
+        last = Some((start, ResourceRecord::from_bytes(&mut cursor)?));
+    }
+    match last {
+        Some((start, record)) if header.arcount > 0 && record.rtype == RecordType::TSIG => Ok(Some((start, record))),
+        _ => Ok(None),
+    }
+}
+
+// HMAC-SHA256 over what signed_data lays out
+fn digest(
+    key: &Key,
+    request_mac: &[u8],
+    message: &[u8],
+    rdata: &RData,
+    timers_only: bool
+) -> Result<Vec<u8>, Error> {
+    let data = signed_data(key, request_mac, message, rdata, timers_only)?;
+    Ok(hmac::sign(&hmac_key(key), &data).as_ref().to_vec())
+}
+
+fn hmac_key(key: &Key) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, &key.secret)
+}
+
+// what the HMAC covers: the request MAC, the unsigned message and the TSIG variables (RFC 8945
+// 4.3), or only the timers for the later messages of a stream
+fn signed_data(
+    key: &Key,
+    request_mac: &[u8],
+    message: &[u8],
+    rdata: &RData,
+    timers_only: bool
+) -> Result<Vec<u8>, Error> {
+    let (algorithm, time_signed, fudge, error, other) = match rdata {
+        RData::TSIG { algorithm, time_signed, fudge, error, other, .. } => (algorithm, *time_signed, *fudge, *error, other),
+        _ => return Err(failure("not a TSIG record")),
+    };
+    let mut data = vec![];
+    if !request_mac.is_empty() {
+        data.write_u16::<BigEndian>(request_mac.len() as u16)?;
+        data.extend(request_mac);
+    }
+    data.extend(message);
+    if !timers_only {
+        // names in canonical form: uncompressed and lowercase
+        Name::parse(&key.name.to_ascii_lowercase()).map_err(|e| failure(&e))?.write(&mut data);
+        data.write_u16::<BigEndian>(CLASS_ANY)?;
+        data.write_u32::<BigEndian>(0)?; // ttl
+        Name::parse(&algorithm.to_ascii_lowercase()).map_err(|e| failure(&e))?.write(&mut data);
+    }
+    data.write_u16::<BigEndian>((time_signed >> 32) as u16)?;
+    data.write_u32::<BigEndian>(time_signed as u32)?;
+    data.write_u16::<BigEndian>(fudge)?;
+    if !timers_only {
+        data.write_u16::<BigEndian>(error)?;
+        data.write_u16::<BigEndian>(other.len() as u16)?;
+        data.extend(other);
+    }
+    Ok(data)
+}
+
+// the time a BADTIME reply says the server has, for telling the user how far off their clock is
+fn server_time(other: &[u8]) -> Option<u64> {
+    let mut cursor = Cursor::new(other);
+    let high = cursor.read_u16::<BigEndian>().ok()? as u64;
+    let low = cursor.read_u32::<BigEndian>().ok()? as u64;
+    Some((high << 32) | low)
+}
+
+fn failure(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("TSIG: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::build_query;
+
+    const KEY_FILE: &str = r#"
+# generated by tsig-keygen
+key "ops-key." {
+    algorithm hmac-sha256; // the only one we speak
+    secret "c2VjcmV0IHNoYXJlZCB3aXRoIHRoZSBwcmltYXJ5IHNlcnZlcg==";
+};
+"#;
+
+    fn key() -> Key {
+        Key::parse(KEY_FILE).unwrap()
+    }
+
+    fn query() -> Vec<u8> {
+        let question = Question { qname: "example.com".to_string(), qtype: RecordType::SOA, qclass: 1 };
+        build_query(question, false, None).unwrap()
+    }
+
+    #[test]
+    fn test_key_file() {
+        let key = key();
+        assert_eq!(key.name, "ops-key");
+        assert_eq!(key.algorithm, HMAC_SHA256);
+        assert_eq!(key.secret, b"secret shared with the primary server");
+        assert!(!format!("{:?}", key).contains("secret"));
+        assert!(Key::parse("key k { algorithm hmac-md5; secret \"AAAA\"; };").is_err());
+        assert!(Key::parse("key k { algorithm hmac-sha256; };").is_err());
+        assert!(Key::parse("key k { algorithm hmac-sha256; secret \"AAAA\" };").is_err());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let query = query();
+        let (signed, mac) = sign(&query, &key(), 1_700_000_000, &[]).unwrap();
+        assert_eq!(signed[10..12], [0, 1]);
+        assert_eq!(signed[..query.len()][12..], query[12..]);
+        verify(&signed, &key(), &[], 1_700_000_100).unwrap();
+
+        // the reply is signed over our MAC, so it only verifies against the query it answers
+        let mut reply = query.clone();
+        reply[2] |= 0x80;
+        let (reply, _) = sign(&reply, &key(), 1_700_000_001, &mac).unwrap();
+        verify(&reply, &key(), &mac, 1_700_000_001).unwrap();
+        assert!(verify(&reply, &key(), &[], 1_700_000_001).is_err());
+
+        let mut tampered = reply.clone();
+        tampered[3] ^= 1;
+        assert!(verify(&tampered, &key(), &mac, 1_700_000_001).unwrap_err().to_string().contains("BADSIG"));
+        let other = Key { secret: b"another secret".to_vec(), ..key() };
+        assert!(verify(&reply, &other, &mac, 1_700_000_001).is_err());
+        assert!(verify(&reply, &key(), &mac, 1_700_000_400).unwrap_err().to_string().contains("BADTIME"));
+        assert!(verify(&query, &key(), &[], 1_700_000_000).is_err());
+    }
+
+    #[test]
+    fn test_server_errors() {
+        let mut reply = query();
+        reply[2] |= 0x80;
+        reply[3] |= 9; // NOTAUTH
+        reply[11] = 1;
+        let mut rdata = RData::TSIG {
+            algorithm: HMAC_SHA256.to_string(),
+            time_signed: 1_700_000_000,
+            fudge: DEFAULT_FUDGE,
+            mac: vec![],
+            original_id: u16::from_be_bytes([reply[0], reply[1]]),
+            error: 17,
+            other: vec![],
+        };
+        let record = |rdata: &RData| ResourceRecord {
+            name: "ops-key".to_string(),
+            rtype: RecordType::TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            rdata: rdata.clone(),
+        };
+        let mut unsigned = reply.clone();
+        unsigned.extend(record(&rdata).to_bytes().unwrap());
+        assert!(verify(&unsigned, &key(), &[0; 32], 1_700_000_000).unwrap_err().to_string().contains("BADKEY"));
+
+        // BADTIME replies are signed and say what time the server thinks it is
+        let request_mac = [7; 32];
+        reply[11] = 0;
+        if let RData::TSIG { error, other, .. } = &mut rdata {
+            *error = BADTIME;
+            *other = vec![0, 0, 0x65, 0x53, 0xf1, 0x00];
+        }
+        let mac = digest(&key(), &request_mac, &reply, &rdata, false).unwrap();
+        if let RData::TSIG { mac: slot, .. } = &mut rdata {
+            *slot = mac;
+        }
+        reply[11] = 1;
+        reply.extend(record(&rdata).to_bytes().unwrap());
+        let error = verify(&reply, &key(), &request_mac, 1_600_000_000).unwrap_err();
+        assert!(error.to_string().contains("BADTIME, its clock says 1700000000"), "{}", error);
+    }
+
+    // a server signing every other message of a stream, the way it would sign later messages
+    // of a zone transfer
+    fn sign_stream(messages: &[Vec<u8>], request_mac: &[u8]) -> Vec<Vec<u8>> {
+        let (first, mut prior) = sign(&messages[0], &key(), 1_700_000_000, request_mac).unwrap();
+        let mut signed = vec![first];
+        let mut unsigned = vec![];
+        for (i, message) in messages.iter().enumerate().skip(1) {
+            if i % 2 == 1 && i != messages.len() - 1 {
+                unsigned.extend(message);
+                signed.push(message.clone());
+                continue;
+            }
+            unsigned.extend(message);
+            let mut rdata = RData::TSIG {
+                algorithm: HMAC_SHA256.to_string(),
+                time_signed: 1_700_000_000,
+                fudge: DEFAULT_FUDGE,
+                mac: vec![],
+                original_id: u16::from_be_bytes([message[0], message[1]]),
+                error: 0,
+                other: vec![],
+            };
+            let mac = digest(&key(), &prior, &unsigned, &rdata, true).unwrap();
+            if let RData::TSIG { mac: slot, .. } = &mut rdata {
+                slot.clone_from(&mac);
+            }
+            let record = ResourceRecord { name: key().name, rtype: RecordType::TSIG, class: CLASS_ANY, ttl: 0, rdata };
+            let mut message = message.clone();
+            message[11] += 1;
+            message.extend(record.to_bytes().unwrap());
+            signed.push(message);
+            unsigned.clear();
+            prior = mac;
+        }
+        signed
+    }
+
+    #[test]
+    fn test_stream() {
+        let key = key();
+        let (_, request_mac) = sign(&query(), &key, 1_700_000_000, &[]).unwrap();
+        let mut reply = query();
+        reply[2] |= 0x80;
+        let stream = sign_stream(&[reply.clone(), reply.clone(), reply.clone(), reply.clone()], &request_mac);
+        assert_eq!(stream.iter().map(|m| m[11]).collect::<Vec<u8>>(), vec![1, 0, 1, 1]);
+
+        let mut verifier = StreamVerifier::new(&key, &request_mac);
+        for message in stream.iter() {
+            verifier.next(message, 1_700_000_000).unwrap();
+        }
+        verifier.finish().unwrap();
+
+        // the unsigned message is covered by the MAC of the one after it
+        let mut verifier = StreamVerifier::new(&key, &request_mac);
+        let mut tampered = stream.clone();
+        tampered[1][3] ^= 1;
+        verifier.next(&tampered[0], 1_700_000_000).unwrap();
+        verifier.next(&tampered[1], 1_700_000_000).unwrap();
+        assert!(verifier.next(&tampered[2], 1_700_000_000).unwrap_err().to_string().contains("BADSIG"));
+
+        let mut verifier = StreamVerifier::new(&key, &request_mac);
+        verifier.next(&stream[0], 1_700_000_000).unwrap();
+        verifier.next(&stream[1], 1_700_000_000).unwrap();
+        assert!(verifier.finish().is_err());
+        assert!(StreamVerifier::new(&key, &request_mac).next(&stream[1], 1_700_000_000).is_err());
+    }
+}
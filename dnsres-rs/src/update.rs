@@ -0,0 +1,262 @@
+use std::io::Error;
+
+use crate::iterative::is_subdomain;
+use crate::message::{ Header, MessageBuilder, Question, ResourceRecord, CLASS_ANY, CLASS_NONE };
+use crate::rdata::{ RData, RecordType };
+use crate::zone::{ absolute, parse_records };
+
+// opcode 5 in the header flags
+const OPCODE_UPDATE: u16 = 5 << 11;
+
+// an RFC 2136 UPDATE: conditions the zone has to meet, then the changes to make to it
+#[derive(Debug, Clone, Default)]
+pub struct Update {
+    pub zone: String,
+    pub prerequisites: Vec<ResourceRecord>,
+    pub updates: Vec<ResourceRecord>,
+}
+
+impl Update {
+    // reads nsupdate-style commands, one per line:
+    //   zone <zone>
+    //   ttl <seconds>
+    //   prereq nxdomain|yxdomain <name>
+    //   prereq nxrrset <name> <type>
+    //   prereq yxrrset <name> <type> [<rdata>]
+    //   [update] add <name> [<ttl>] [IN] <type> <rdata>
+    //   [update] delete <name> [<type> [<rdata>]]
+    //   send
+    // Names are relative to the zone, which comes from the script or else from `zone`.
+    pub fn parse(script: &str, zone: Option<&str>) -> Result<Update, String> {
+        let lines: Vec<(usize, &str)> = script
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with([';', '#']))
+            .collect();
+
+        // the zone has to be known before any relative name can be made absolute
+        let declared = lines.iter().find_map(|(_, line)| match next_word(line) {
+            Some(("zone", rest)) => Some(rest.trim()),
+            _ => None,
+        });
+        let zone = declared.or(zone).ok_or("no zone given")?;
+        let zone = absolute(zone, "")?.to_ascii_lowercase();
+        let mut update = Update { zone, ..Update::default() };
+
+        let mut ttl = None;
+        let mut sent = false;
+        for (number, line) in lines {
+            let at = |e: String| format!("line {}: {}", number, e);
+            if sent {
+                return Err(at("only one update can be sent per run".to_string()));
+            }
+            let (command, rest) = next_word(line).unwrap_or_default();
+            let (command, rest) = match (command, next_word(rest)) {
+                ("update", Some((command, rest))) => (command, rest),
+                _ => (command, rest),
+            };
+            match command {
+                "zone" => {}
+                "send" => sent = true,
+                "ttl" => ttl = Some(rest.trim().parse::<u32>().map_err(|_| at(format!("invalid ttl: {}", rest)))?),
+                "prereq" => {
+                    let record = update.prerequisite(rest).map_err(at)?;
+                    update.prerequisites.push(record);
+                }
+                "add" => {
+                    let record = update.addition(rest, ttl).map_err(at)?;
+                    update.updates.push(record);
+                }
+                "delete" | "del" => {
+                    let record = update.deletion(rest).map_err(at)?;
+                    update.updates.push(record);
+                }
+                _ => return Err(at(format!("unknown command: {}", command))),
+            }
+        }
+        if update.updates.is_empty() {
+            return Err("nothing to update".to_string());
+        }
+        Ok(update)
+    }
+
+    // the zone, prerequisite and update sections travel where a query has its question,
+    // answer and authority sections (RFC 2136 2)
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let header = Header { id: rand::random(), flags: OPCODE_UPDATE, qdcount: 0, ancount: 0, nscount: 0, arcount: 0 };
+        let mut builder = MessageBuilder::new(&header);
+        builder.question(&Question { qname: self.zone.clone(), qtype: RecordType::SOA, qclass: 1 })?;
+        for record in self.prerequisites.iter() {
+            builder.answer(record)?;
+        }
+        for record in self.updates.iter() {
+            builder.authority(record)?;
+        }
+        Ok(builder.finish())
+    }
+
+    // RFC 2136 2.4
+    fn prerequisite(&self, text: &str) -> Result<ResourceRecord, String> {
+        let (kind, rest) = next_word(text).ok_or("prereq needs a condition")?;
+        let (name, rest) = next_word(rest).ok_or("prereq needs a name")?;
+        let name = self.name(name)?;
+        let rrset = |class| -> Result<ResourceRecord, String> {
+            let (rtype, rest) = next_word(rest).ok_or_else(|| format!("prereq {} needs a type", kind))?;
+            if !rest.is_empty() {
+                return Err(format!("unexpected data after prereq {} {}", kind, rtype));
+            }
+            Ok(empty(&name, rtype.parse()?, class))
+        };
+        match kind {
+            "nxdomain" => Ok(empty(&name, RecordType::ANY, CLASS_NONE)),
+            "yxdomain" => Ok(empty(&name, RecordType::ANY, CLASS_ANY)),
+            "nxrrset" => rrset(CLASS_NONE),
+            // with rdata the whole RRset has to match, without it just has to exist
+            "yxrrset" if has_rdata(rest) => {
+                let record = self.record(&name, 0, rest)?;
+                Ok(ResourceRecord { ttl: 0, ..record })
+            }
+            "yxrrset" => rrset(CLASS_ANY),
+            _ => Err(format!("unknown prerequisite: {}", kind)),
+        }
+    }
+
+    fn addition(&self, text: &str, ttl: Option<u32>) -> Result<ResourceRecord, String> {
+        let (name, rest) = next_word(text).ok_or("add needs a name")?;
+        let name = self.name(name)?;
+        let explicit = next_word(rest).is_some_and(|(word, _)| word.starts_with(|c: char| c.is_ascii_digit()));
+        match ttl {
+            _ if explicit => self.record(&name, 0, rest),
+            Some(ttl) => self.record(&name, ttl, rest),
+            None => Err(format!("add {} needs a ttl", name)),
+        }
+    }
+
+    // RFC 2136 2.5.2 to 2.5.4: a whole name, one RRset or a single record
+    fn deletion(&self, text: &str) -> Result<ResourceRecord, String> {
+        let (name, rest) = next_word(text).ok_or("delete needs a name")?;
+        let name = self.name(name)?;
+        // nsupdate allows a ttl and class here, both of which are ignored
+        let mut rest = rest;
+        while let Some((word, tail)) = next_word(rest) {
+            if word.parse::<u32>().is_err() && !["IN", "ANY", "NONE"].contains(&word.to_ascii_uppercase().as_str()) {
+                break;
+            }
+            rest = tail;
+        }
+        match next_word(rest) {
+            None => Ok(empty(&name, RecordType::ANY, CLASS_ANY)),
+            Some((rtype, "")) => Ok(empty(&name, rtype.parse()?, CLASS_ANY)),
+            Some(_) => {
+                let record = self.record(&name, 0, rest)?;
+                Ok(ResourceRecord { class: CLASS_NONE, ttl: 0, ..record })
+            }
+        }
+    }
+
+    // `[ttl] [IN] type rdata` read the way a master file would be, with ttl as the default
+    fn record(&self, name: &str, ttl: u32, text: &str) -> Result<ResourceRecord, String> {
+        let line = format!("$TTL {}\n{}. {}\n", ttl, name, text);
+        let mut records = parse_records(&line, &self.zone)?;
+        if records.len() != 1 {
+            return Err(format!("expected one record, got {}", records.len()));
+        }
+        let record = records.remove(0);
+        if record.class != 1 {
+            return Err("only class IN can be updated".to_string());
+        }
+        Ok(record)
+    }
+
+    // made absolute against the zone, which it has to be in (RFC 2136 3.4.1.3)
+    fn name(&self, text: &str) -> Result<String, String> {
+        let name = absolute(text, &self.zone)?;
+        if !is_subdomain(&name, &self.zone) {
+            return Err(format!("{} is outside zone {}", name, self.zone));
+        }
+        Ok(name)
+    }
+}
+
+// prerequisites and deletions match on name, type and class alone
+fn empty(name: &str, rtype: RecordType, class: u16) -> ResourceRecord {
+    ResourceRecord { name: name.to_string(), rtype, class, ttl: 0, rdata: RData::Unknown(vec![]) }
+}
+
+// whether `type rdata...` goes on past the type
+fn has_rdata(text: &str) -> bool {
+    next_word(text).is_some_and(|(_, rest)| !rest.is_empty())
+}
+
+// the first whitespace-separated word and what follows it
+fn next_word(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return None;
+    }
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    Some((&text[..end], text[end..].trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+
+    const SCRIPT: &str = "\
+; move www to the new web server
+zone example.com
+prereq yxrrset www A 192.0.2.80
+prereq nxdomain staging
+update delete www A 192.0.2.80
+update add www 300 IN A 192.0.2.81
+ttl 600
+add www TXT \"moved to web2\"
+delete old.example.com.
+delete mail MX
+send
+";
+
+    #[test]
+    fn test_parse_script() {
+        let update = Update::parse(SCRIPT, None).unwrap();
+        assert_eq!(update.zone, "example.com");
+        let summary = |records: &[ResourceRecord]| -> Vec<(String, RecordType, u16, u32)> {
+            records.iter().map(|r| (r.name.clone(), r.rtype, r.class, r.ttl)).collect()
+        };
+        assert_eq!(summary(&update.prerequisites), vec![
+            ("www.example.com".to_string(), RecordType::A, 1, 0),
+            ("staging.example.com".to_string(), RecordType::ANY, CLASS_NONE, 0),
+        ]);
+        assert_eq!(summary(&update.updates), vec![
+            ("www.example.com".to_string(), RecordType::A, CLASS_NONE, 0),
+            ("www.example.com".to_string(), RecordType::A, 1, 300),
+            ("www.example.com".to_string(), RecordType::TXT, 1, 600),
+            ("old.example.com".to_string(), RecordType::ANY, CLASS_ANY, 0),
+            ("mail.example.com".to_string(), RecordType::MX, CLASS_ANY, 0),
+        ]);
+        assert_eq!(update.updates[0].rdata, RData::A([192, 0, 2, 80].into()));
+
+        // the message decodes back into the same sections
+        let message = Message::from_bytes(&update.to_bytes().unwrap()).unwrap();
+        assert_eq!(message.header.opcode(), 5);
+        assert_eq!(message.questions[0].qtype, RecordType::SOA);
+        assert_eq!(summary(&message.answers), summary(&update.prerequisites));
+        assert_eq!(summary(&message.authorities), summary(&update.updates));
+        assert_eq!(message.authorities[3].rdata, RData::Unknown(vec![]));
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(Update::parse("add www 300 A 192.0.2.1", None).is_err()); // no zone
+        assert_eq!(Update::parse("add www 300 A 192.0.2.1", Some("example.com.")).unwrap().zone, "example.com");
+        assert!(Update::parse("zone example.com\nadd www A 192.0.2.1", None).unwrap_err().contains("needs a ttl"));
+        assert!(Update::parse("zone example.com\nadd www.example.org. 300 A 192.0.2.1", None).unwrap_err().contains("outside"));
+        assert!(Update::parse("zone example.com\nadd www 300 CH A 192.0.2.1", None).is_err());
+        assert!(Update::parse("zone example.com\nprereq nxrrset www", None).is_err());
+        assert!(Update::parse("zone example.com\nprereq yxdomain www", None).unwrap_err().contains("nothing"));
+        assert!(Update::parse("zone example.com\ndelete www\nsend\ndelete ftp", None).unwrap_err().starts_with("line 4"));
+        assert!(Update::parse("zone example.com\nfrobnicate www", None).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use std::io::{ Error, ErrorKind };
+use std::net::{ SocketAddr, TcpStream };
+use std::time::Duration;
+
+use crate::message::{ malformed, rcode_name, Header, Message, MessageBuilder, Question, ResourceRecord };
+use crate::rdata::{ RData, RecordType };
+use crate::transport::{ read_frame, write_frame };
+use crate::tsig::{ self, Key, StreamVerifier };
+
+// what came back from a zone transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transfer {
+    Full(Vec<ResourceRecord>), // the whole zone, SOA first
+    Incremental(Vec<Diff>), // oldest change first
+    UpToDate(ResourceRecord), // our serial is current, here is the server's SOA
+}
+
+// one step of an IXFR (RFC 1995 4): the old SOA and the records that went away, then the new
+// SOA and the records that arrived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    pub from: u32,
+    pub to: u32,
+    pub deleted: Vec<ResourceRecord>,
+    pub added: Vec<ResourceRecord>,
+}
+
+impl Diff {
+    // brings a copy of the zone at serial `from` up to `to`, SOA included. A deleted record we
+    // don't have means the copy isn't the zone the diff was made against; the records are left
+    // as they were.
+    pub fn apply(&self, records: &mut Vec<ResourceRecord>) -> Result<(), String> {
+        let mut kept = records.clone();
+        for gone in self.deleted.iter() {
+            match kept.iter().position(|r| same_record(r, gone)) {
+                Some(index) => {
+                    kept.remove(index);
+                }
+                None => {
+                    return Err(format!("diff from serial {} deletes {} {}, which the zone does not have", self.from, gone.name, gone.rtype));
+                }
+            }
+        }
+        *records = kept;
+        for record in self.added.iter() {
+            if !records.iter().any(|r| same_record(r, record)) {
+                records.push(record.clone());
+            }
+        }
+        // keep the SOA where a zone file starts
+        if let Some(index) = records.iter().position(|r| r.rtype == RecordType::SOA) {
+            let soa = records.remove(index);
+            records.insert(0, soa);
+        }
+        Ok(())
+    }
+}
+
+// AXFR over tcp (RFC 5936), or IXFR from `serial` when one is given (RFC 1995). The reply runs
+// over as many messages as the server likes, so it is read until the closing SOA; with a key
+// every message is checked against the TSIG stream.
+pub fn transfer(
+    zone: &str,
+    serial: Option<u32>,
+    server: SocketAddr,
+    key: Option<&Key>,
+    timeout: Duration
+) -> Result<Transfer, Error> {
+    let query = build(zone, serial)?;
+    let (query, request_mac) = match key {
+        Some(key) => tsig::sign(&query, key, tsig::now(), &[])?,
+        None => (query, vec![]),
+    };
+    let id = u16::from_be_bytes([query[0], query[1]]);
+
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write_frame(&mut stream, &query)?;
+
+    let mut verifier = key.map(|key| StreamVerifier::new(key, &request_mac));
+    let mut reader = Reader::new(zone, serial);
+    let mut first = true;
+    loop {
+        let frame = read_frame(&mut stream).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => Error::new(e.kind(), "connection closed before the closing SOA"),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => Error::new(ErrorKind::TimedOut, "timed out waiting for the server"),
+            _ => e,
+        })?;
+        let message = Message::from_bytes(&frame)?;
+        if message.header.id != id || !message.header.is_response() {
+            return Err(malformed("reply does not answer the transfer request"));
+        }
+        // only the first message has to repeat the question (RFC 5936 2.2.1)
+        if first && message.questions.iter().any(|q| !q.qname.eq_ignore_ascii_case(zone)) {
+            return Err(malformed("reply question does not match the zone"));
+        }
+        if message.header.rcode() != 0 {
+            let rcode = rcode_name(message.header.rcode() as u16);
+            return Err(Error::other(format!("server answered {}", rcode)));
+        }
+        if let Some(verifier) = verifier.as_mut() {
+            verifier.next(&frame, tsig::now())?;
+        }
+        for record in message.answers {
+            reader.push(record).map_err(|e| malformed(&e))?;
+        }
+        first = false;
+        if let Some(transfer) = reader.finish() {
+            if let Some(verifier) = verifier.as_ref() {
+                verifier.finish()?;
+            }
+            return Ok(transfer);
+        }
+    }
+}
+
+// the question, and for IXFR the SOA we have in the authority section (RFC 1995 3)
+fn build(zone: &str, serial: Option<u32>) -> Result<Vec<u8>, Error> {
+    let header = Header { id: rand::random(), flags: 0, qdcount: 0, ancount: 0, nscount: 0, arcount: 0 };
+    let qtype = if serial.is_some() { RecordType::IXFR } else { RecordType::AXFR };
+    let mut builder = MessageBuilder::new(&header);
+    builder.question(&Question { qname: zone.to_string(), qtype, qclass: 1 })?;
+    if let Some(serial) = serial {
+        // only the serial is looked at
+        let rdata = RData::SOA {
+            mname: String::new(),
+            rname: String::new(),
+            serial,
+            refresh: 0,
+            retry: 0,
+            expire: 0,
+            minimum: 0,
+        };
+        builder.authority(&ResourceRecord { name: zone.to_string(), rtype: RecordType::SOA, class: 1, ttl: 0, rdata })?;
+    }
+    Ok(builder.finish())
+}
+
+enum State {
+    Start,
+    First(ResourceRecord), // just the SOA so far: a full zone, a diff or our own serial
+    Full(Vec<ResourceRecord>),
+    Deleting(Vec<Diff>, Diff),
+    Adding(Vec<Diff>, Diff),
+    Done(Transfer),
+}
+
+// works out which kind of reply the answer records make up as they arrive
+struct Reader {
+    zone: String,
+    serial: Option<u32>,
+    latest: u32, // the serial of the first SOA, which closes the transfer
+    state: State,
+}
+
+impl Reader {
+    fn new(zone: &str, serial: Option<u32>) -> Reader {
+        Reader { zone: zone.to_string(), serial, latest: 0, state: State::Start }
+    }
+
+    fn push(&mut self, record: ResourceRecord) -> Result<(), String> {
+        let soa = match &record.rdata {
+            RData::SOA { serial, .. } if record.name.eq_ignore_ascii_case(&self.zone) => Some(*serial),
+            _ => None,
+        };
+        self.state = match (std::mem::replace(&mut self.state, State::Start), soa) {
+            (State::Start, Some(serial)) => {
+                self.latest = serial;
+                State::First(record)
+            }
+            (State::Start, None) => return Err("transfer does not start with the zone's SOA".to_string()),
+            // a second SOA with an older serial starts the first diff, which has to start from
+            // the serial we asked from; an AXFR of an empty zone is the SOA twice
+            (State::First(_), Some(serial)) if self.serial.is_some() && serial != self.latest => {
+                if Some(serial) != self.serial {
+                    return Err(format!("first diff starts at serial {}, not {}", serial, self.serial.unwrap_or_default()));
+                }
+                let diff = Diff { from: serial, to: serial, deleted: vec![record], added: vec![] };
+                State::Deleting(vec![], diff)
+            }
+            (State::First(soa), Some(_)) => State::Done(Transfer::Full(vec![soa])),
+            (State::First(soa), None) => State::Full(vec![soa, record]),
+            (State::Full(records), Some(_)) => State::Done(Transfer::Full(records)),
+            (State::Full(mut records), None) => {
+                records.push(record);
+                State::Full(records)
+            }
+            (State::Deleting(diffs, mut diff), Some(serial)) => {
+                diff.to = serial;
+                diff.added.push(record);
+                State::Adding(diffs, diff)
+            }
+            (State::Deleting(diffs, mut diff), None) => {
+                diff.deleted.push(record);
+                State::Deleting(diffs, diff)
+            }
+            (State::Adding(mut diffs, diff), Some(serial)) => {
+                if serial != diff.to {
+                    return Err(format!("diff from serial {} does not follow on from {}", serial, diff.to));
+                }
+                diffs.push(diff);
+                if serial == self.latest {
+                    State::Done(Transfer::Incremental(diffs))
+                } else {
+                    State::Deleting(diffs, Diff { from: serial, to: serial, deleted: vec![record], added: vec![] })
+                }
+            }
+            (State::Adding(diffs, mut diff), None) => {
+                diff.added.push(record);
+                State::Adding(diffs, diff)
+            }
+            (State::Done(_), _) => return Err("records after the closing SOA".to_string()),
+        };
+        Ok(())
+    }
+
+    // the transfer once it is complete, checked at the end of every message. A lone SOA no newer
+    // than ours is all a server sends when we're up to date.
+    fn finish(&mut self) -> Option<Transfer> {
+        match std::mem::replace(&mut self.state, State::Start) {
+            State::Done(transfer) => Some(transfer),
+            State::First(soa) if self.serial.is_some_and(|ours| !serial_newer(self.latest, ours)) => {
+                Some(Transfer::UpToDate(soa))
+            }
+            state => {
+                self.state = state;
+                None
+            }
+        }
+    }
+}
+
+// serial number arithmetic (RFC 1982): whether a comes after b
+pub fn serial_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000_0000
+}
+
+// the ttl may change without the record being a different one
+fn same_record(a: &ResourceRecord, b: &ResourceRecord) -> bool {
+    a.rtype == b.rtype && a.class == b.class && a.name.eq_ignore_ascii_case(&b.name) && a.rdata == b.rdata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::parse_records;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn soa(serial: u32) -> ResourceRecord {
+        let text = format!("@ 3600 IN SOA ns1 hostmaster {} 7200 900 1209600 300", serial);
+        parse_records(&text, "example.com").unwrap().remove(0)
+    }
+
+    fn records(text: &str) -> Vec<ResourceRecord> {
+        parse_records(text, "example.com").unwrap()
+    }
+
+    // a primary that answers one transfer request with the given messages' answer sections
+    fn primary(messages: Vec<Vec<ResourceRecord>>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = Message::from_bytes(&read_frame(&mut stream).unwrap()).unwrap();
+            for (i, answers) in messages.into_iter().enumerate() {
+                let reply = Message {
+                    header: Header { id: query.header.id, flags: 0x8400, qdcount: 0, ancount: 0, nscount: 0, arcount: 0 },
+                    questions: if i == 0 { query.questions.clone() } else { vec![] },
+                    answers,
+                    authorities: vec![],
+                    additionals: vec![],
+                };
+                write_frame(&mut stream, &reply.to_bytes().unwrap()).unwrap();
+            }
+        });
+        addr
+    }
+
+    fn timeout() -> Duration {
+        Duration::from_secs(2)
+    }
+
+    #[test]
+    fn test_axfr_across_messages() {
+        let zone = records("@ 3600 NS ns1\nns1 3600 A 192.0.2.1\nwww 300 A 192.0.2.80");
+        let server = primary(vec![vec![soa(5), zone[0].clone()], vec![zone[1].clone(), zone[2].clone()], vec![soa(5)]]);
+        let mut expected = vec![soa(5)];
+        expected.extend(zone);
+        assert_eq!(transfer("example.com", None, server, None, timeout()).unwrap(), Transfer::Full(expected));
+
+        // a server that hangs up early didn't send the whole zone
+        let server = primary(vec![vec![soa(5), records("www 300 A 192.0.2.80").remove(0)]]);
+        assert!(transfer("example.com", None, server, None, timeout()).is_err());
+    }
+
+    fn new_ns() -> ResourceRecord {
+        records("@ 3600 NS ns1").remove(0)
+    }
+
+    #[test]
+    fn test_ixfr() {
+        let old = records("www 300 A 192.0.2.80\nmail 300 A 192.0.2.25");
+        let new = records("www 300 A 192.0.2.81\nftp 300 A 192.0.2.21");
+        let server = primary(vec![
+            vec![soa(7), soa(5), old[0].clone(), soa(6), new[0].clone()],
+            vec![soa(6), old[1].clone(), soa(7), new[1].clone(), soa(7)],
+        ]);
+        let diffs = match transfer("example.com", Some(5), server, None, timeout()).unwrap() {
+            Transfer::Incremental(diffs) => diffs,
+            other => panic!("expected an incremental transfer, got {:?}", other),
+        };
+        assert_eq!(diffs.iter().map(|d| (d.from, d.to)).collect::<Vec<(u32, u32)>>(), vec![(5, 6), (6, 7)]);
+
+        let mut zone = vec![soa(5)];
+        zone.extend(old.iter().cloned());
+        for diff in diffs.iter() {
+            diff.apply(&mut zone).unwrap();
+        }
+        let mut expected = vec![soa(7)];
+        expected.extend(new.iter().cloned());
+        assert_eq!(zone, expected);
+
+        // the diff doesn't fit a zone that never had the record it deletes
+        let mut other = vec![soa(5), old[1].clone()];
+        assert!(diffs[0].apply(&mut other).is_err());
+        assert_eq!(other, vec![soa(5), old[1].clone()]);
+
+        // diffs that start somewhere other than our serial can't be applied
+        let server = primary(vec![vec![soa(7), soa(6), old[1].clone(), soa(7), new[1].clone(), soa(7)]]);
+        assert!(transfer("example.com", Some(5), server, None, timeout()).is_err());
+
+        let server = primary(vec![vec![soa(7)]]);
+        assert_eq!(transfer("example.com", Some(7), server, None, timeout()).unwrap(), Transfer::UpToDate(soa(7)));
+
+        // servers without the history fall back to sending the whole zone
+        let server = primary(vec![vec![soa(7), new_ns()], vec![soa(7)]]);
+        assert_eq!(transfer("example.com", Some(1), server, None, timeout()).unwrap(), Transfer::Full(vec![soa(7), new_ns()]));
+    }
+
+    #[test]
+    fn test_serial_arithmetic() {
+        assert!(serial_newer(2, 1));
+        assert!(serial_newer(0, u32::MAX));
+        assert!(!serial_newer(1, 1));
+        assert!(!serial_newer(1, 2));
+    }
+}
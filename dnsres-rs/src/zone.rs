@@ -0,0 +1,905 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::net::{ Ipv4Addr, Ipv6Addr };
+use std::path::Path;
+
+use crate::dnssec::{ nsec3_match, nsec_covers };
+use crate::edns::{ self, Edns };
+use crate::encoding::{ from_base32hex, from_base64, from_hex };
+use crate::iterative::is_subdomain;
+use crate::message::{ class_name, parse_class, Header, Message, Question, ResourceRecord };
+use crate::name::{ is_absolute, Name };
+use crate::rdata::{ parse_time, RData, RecordType };
+use crate::server::{ error_reply, Handler };
+
+const MAX_CNAME_CHAIN: usize = 8;
+
+// one word of a zone file; quoted words keep their spaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+// an entry, with parentheses already joined into one logical line
+struct Line {
+    number: usize, // where it started, for error messages
+    owner_blank: bool, // started with whitespace: same owner as before
+    tokens: Vec<Token>,
+}
+
+// an in-memory zone loaded from an RFC 1035 master file
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub origin: String, // apex, the owner of the SOA
+    pub records: Vec<ResourceRecord>,
+}
+
+impl Zone {
+    pub fn load(path: &Path, origin: &str) -> Result<Zone, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Zone::parse(&text, origin).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str, origin: &str) -> Result<Zone, String> {
+        let records = parse_records(text, origin)?;
+        let soa = records
+            .iter()
+            .find(|r| r.rtype == RecordType::SOA)
+            .ok_or("zone has no SOA record")?;
+        let origin = soa.name.clone();
+        if records.iter().any(|r| !is_subdomain(&r.name, &origin)) {
+            return Err(format!("zone '{}' contains records outside of it", origin));
+        }
+        Ok(Zone { origin, records })
+    }
+
+    pub fn soa(&self) -> Option<&ResourceRecord> {
+        self.records.iter().find(|r| r.rtype == RecordType::SOA && same_name(&r.name, &self.origin))
+    }
+
+    // a master file that parse reads back the same, with every name absolute
+    pub fn to_text(&self) -> String {
+        let mut text = format!("$ORIGIN {}.\n", self.origin);
+        for record in self.records.iter() {
+            text.push_str(&format!(
+                "{}.\t{}\t{}\t{}\t{}\n",
+                record.name,
+                record.ttl,
+                class_name(record.class),
+                record.rtype,
+                record.rdata
+            ));
+        }
+        text
+    }
+
+    fn records_at(&self, name: &str) -> Vec<&ResourceRecord> {
+        self.records.iter().filter(|r| same_name(&r.name, name)).collect()
+    }
+
+    // a name exists if it owns records or anything below it does (empty non-terminals);
+    // NSEC3 owners are hashes rather than part of the namespace
+    fn name_exists(&self, name: &str) -> bool {
+        self.records.iter().filter(|r| !is_nsec3(r)).any(|r| is_subdomain(&r.name, name))
+    }
+
+    // the topmost zone cut between the apex and the name, if any;
+    // a DS query for the cut itself is answered from this side of it
+    fn delegation(&self, name: &str, qtype: RecordType) -> Option<String> {
+        let labels: Vec<&str> = name.split('.').collect();
+        let apex_labels = if self.origin.is_empty() { 0 } else { self.origin.split('.').count() };
+        for keep in (apex_labels + 1)..=labels.len() {
+            let candidate = labels[labels.len() - keep..].join(".");
+            if self.records_at(&candidate).iter().any(|r| r.rtype == RecordType::NS) {
+                if keep == labels.len() && qtype == RecordType::DS {
+                    return None;
+                }
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    // wildcard records that would answer a name that doesn't exist (RFC 4592)
+    fn wildcard_for(&self, name: &str) -> Option<String> {
+        let mut encloser = name;
+        while let Some((_, parent)) = encloser.split_once('.') {
+            encloser = parent;
+            if self.name_exists(encloser) {
+                let wildcard = format!("*.{}", encloser);
+                return if self.records_at(&wildcard).is_empty() { None } else { Some(wildcard) };
+            }
+            if same_name(encloser, &self.origin) {
+                break;
+            }
+        }
+        None
+    }
+
+    // an authoritative answer, a referral, NODATA or NXDOMAIN;
+    // with dnssec set, signatures and NSEC/NSEC3 proofs are added too
+    pub fn answer(&self, question: &Question, dnssec: bool) -> Message {
+        let mut response = Message {
+            header: Header { id: 0, flags: 0x8400, qdcount: 1, ancount: 0, nscount: 0, arcount: 0 }, // qr aa
+            questions: vec![question.clone()],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        };
+
+        let mut denial = None; // the name that is missing, and whether it's NXDOMAIN
+        let mut expanded = vec![]; // names answered from a wildcard
+        let mut name = question.qname.clone();
+        for _ in 0..MAX_CNAME_CHAIN {
+            if !is_subdomain(&name, &self.origin) {
+                break; // CNAME left the zone, the client takes it from here
+            }
+
+            if let Some(cut) = self.delegation(&name, question.qtype) {
+                if response.answers.is_empty() {
+                    response.header.flags &= !0x0400; // referrals aren't authoritative
+                }
+                let ns: Vec<ResourceRecord> = self
+                    .records_at(&cut)
+                    .into_iter()
+                    .filter(|r| r.rtype == RecordType::NS)
+                    .cloned()
+                    .collect();
+                response.additionals = self.glue(&ns);
+                response.authorities = ns;
+                break;
+            }
+
+            // wildcard matches take the query name as their owner
+            let owner = if !self.name_exists(&name) {
+                match self.wildcard_for(&name) {
+                    Some(wildcard) => {
+                        expanded.push(name.clone());
+                        wildcard
+                    }
+                    None => {
+                        response.header.flags |= 3; // NXDOMAIN
+                        response.authorities = self.negative_soa();
+                        denial = Some((name.clone(), true));
+                        break;
+                    }
+                }
+            } else {
+                name.clone()
+            };
+            let synthesized = |r: &ResourceRecord| ResourceRecord { name: name.clone(), ..r.clone() };
+
+            // ANY takes every RRset at the name; RRSIGs only go with what they cover, to DO clients
+            let wanted = |rtype: RecordType| match question.qtype {
+                RecordType::ANY => rtype != RecordType::RRSIG,
+                qtype => rtype == qtype,
+            };
+            let at_name = self.records_at(&owner);
+            let matching: Vec<ResourceRecord> = at_name
+                .iter()
+                .filter(|r| wanted(r.rtype) && r.class == question.qclass)
+                .map(|r| synthesized(r))
+                .collect();
+            if !matching.is_empty() {
+                response.answers.extend(matching);
+                break;
+            }
+
+            let cname = at_name.iter().find(|r| r.rtype == RecordType::CNAME);
+            match cname.map(|r| (synthesized(r), &r.rdata)) {
+                Some((record, RData::CNAME(target))) if question.qtype != RecordType::CNAME => {
+                    name = target.clone();
+                    response.answers.push(record);
+                }
+                _ => {
+                    response.authorities = self.negative_soa(); // NODATA
+                    denial = Some((name.clone(), false));
+                    break;
+                }
+            }
+        }
+
+        if dnssec {
+            self.add_dnssec(&mut response, denial, &expanded);
+        }
+
+        response.header.ancount = response.answers.len() as u16;
+        response.header.nscount = response.authorities.len() as u16;
+        response.header.arcount = response.additionals.len() as u16;
+        response
+    }
+
+    // SOA for negative answers, its TTL capped at the minimum field (RFC 2308 3)
+    fn negative_soa(&self) -> Vec<ResourceRecord> {
+        self.soa()
+            .map(|soa| {
+                let ttl = match soa.rdata {
+                    RData::SOA { minimum, .. } => soa.ttl.min(minimum),
+                    _ => soa.ttl,
+                };
+                vec![ResourceRecord { ttl, ..soa.clone() }]
+            })
+            .unwrap_or_default()
+    }
+
+    // RRSIGs for everything handed out, plus NSEC or NSEC3 records proving what isn't there
+    fn add_dnssec(&self, response: &mut Message, denial: Option<(String, bool)>, expanded: &[String]) {
+        let mut proofs = vec![];
+        if let Some((name, nxdomain)) = denial {
+            proofs.extend(self.deny(&name, nxdomain));
+        }
+        for name in expanded.iter() {
+            proofs.extend(self.deny_expanded(name));
+        }
+        for proof in proofs {
+            if !response.authorities.iter().any(|r| r.name == proof.name && r.rdata == proof.rdata) {
+                response.authorities.push(proof);
+            }
+        }
+        response.answers = self.with_signatures(&response.answers);
+        response.authorities = self.with_signatures(&response.authorities);
+    }
+
+    fn with_signatures(&self, records: &[ResourceRecord]) -> Vec<ResourceRecord> {
+        let mut signed = records.to_vec();
+        let mut seen: Vec<(String, RecordType)> = vec![];
+        for record in records.iter() {
+            let key = (record.name.to_ascii_lowercase(), record.rtype);
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+            // answers synthesised from a wildcard carry the wildcard's signatures
+            let owner = match self.records_at(&record.name).is_empty() {
+                true => self.wildcard_for(&record.name).unwrap_or_else(|| record.name.clone()),
+                false => record.name.clone(),
+            };
+            signed.extend(
+                self.records_at(&owner)
+                    .into_iter()
+                    .filter(|r| matches!(r.rdata, RData::RRSIG { type_covered, .. } if type_covered == record.rtype))
+                    .map(|r| ResourceRecord { name: record.name.clone(), ..r.clone() })
+            );
+        }
+        signed
+    }
+
+    fn deny(&self, name: &str, nxdomain: bool) -> Vec<ResourceRecord> {
+        let nsec3: Vec<ResourceRecord> = self.records.iter().filter(|r| r.rtype == RecordType::NSEC3).cloned().collect();
+        if !nsec3.is_empty() {
+            if !nxdomain {
+                return nsec3_match(&nsec3, name, false).cloned().into_iter().collect();
+            }
+            // closest encloser proof (RFC 5155 7.2.1)
+            let encloser = self.closest_encloser(name);
+            let proofs = [
+                nsec3_match(&nsec3, &encloser, false),
+                nsec3_match(&nsec3, &next_closer(name, &encloser), true),
+                nsec3_match(&nsec3, &format!("*.{}", encloser), true),
+            ];
+            return proofs.into_iter().flatten().cloned().collect();
+        }
+
+        if !nxdomain {
+            // the name's own NSEC, or for an empty non-terminal the one skipping over it
+            let own = self.records_at(name).into_iter().find(|r| r.rtype == RecordType::NSEC).cloned();
+            return own.or_else(|| self.nsec_covering(name)).into_iter().collect();
+        }
+        let wildcard = format!("*.{}", self.closest_encloser(name));
+        [self.nsec_covering(name), self.nsec_covering(&wildcard)].into_iter().flatten().collect()
+    }
+
+    // a wildcard answer also has to show the name itself doesn't exist
+    fn deny_expanded(&self, name: &str) -> Vec<ResourceRecord> {
+        let nsec3: Vec<ResourceRecord> = self.records.iter().filter(|r| r.rtype == RecordType::NSEC3).cloned().collect();
+        if nsec3.is_empty() {
+            return self.nsec_covering(name).into_iter().collect();
+        }
+        let encloser = self.closest_encloser(name);
+        nsec3_match(&nsec3, &next_closer(name, &encloser), true).cloned().into_iter().collect()
+    }
+
+    fn nsec_covering(&self, name: &str) -> Option<ResourceRecord> {
+        self.records
+            .iter()
+            .find(|r| matches!(&r.rdata, RData::NSEC { next, .. } if nsec_covers(&r.name, next, name)))
+            .cloned()
+    }
+
+    // the nearest ancestor of a missing name that does exist
+    fn closest_encloser(&self, name: &str) -> String {
+        let mut encloser = name;
+        while let Some((_, parent)) = encloser.split_once('.') {
+            encloser = parent;
+            if self.name_exists(encloser) {
+                return encloser.to_string();
+            }
+        }
+        self.origin.clone()
+    }
+
+    fn glue(&self, ns: &[ResourceRecord]) -> Vec<ResourceRecord> {
+        ns.iter()
+            .filter_map(|r| match &r.rdata {
+                RData::NS(host) => Some(host),
+                _ => None,
+            })
+            .flat_map(|host| {
+                self.records_at(host)
+                    .into_iter()
+                    .filter(|r| matches!(r.rtype, RecordType::A | RecordType::AAAA))
+                    .cloned()
+            })
+            .collect()
+    }
+}
+
+// answers from whichever loaded zone is closest to the name, refusing the rest
+pub struct Authority {
+    pub zones: Vec<Zone>,
+}
+
+impl Authority {
+    // DS records live on the parent side of a cut, so those go to the parent if we have it
+    pub fn zone_for(&self, name: &str, qtype: RecordType) -> Option<&Zone> {
+        let mut zones: Vec<&Zone> = self.zones.iter().filter(|z| is_subdomain(name, &z.origin)).collect();
+        zones.sort_by_key(|z| Reverse(z.origin.len()));
+        if qtype == RecordType::DS && zones.len() > 1 && same_name(&zones[0].origin, name) {
+            return Some(zones[1]);
+        }
+        zones.first().copied()
+    }
+}
+
+impl Handler for Authority {
+    fn handle(&self, query: &Message) -> Message {
+        let refuse = |rcode| {
+            let mut reply = error_reply(query.header.id, query.header.flags, rcode);
+            reply.header.flags &= !0x0080; // no recursion offered
+            reply.questions = query.questions.clone();
+            reply
+        };
+        if query.header.opcode() != 0 {
+            return refuse(4); // NOTIMP
+        }
+        if query.questions.len() != 1 {
+            return refuse(1); // FORMERR
+        }
+        let question = &query.questions[0];
+        let zone = match self.zone_for(&question.qname, question.qtype) {
+            Some(zone) => zone,
+            None => return refuse(5), // REFUSED
+        };
+
+        let dnssec = query.edns().map(|e| e.dnssec_ok).unwrap_or(false);
+        let mut response = zone.answer(question, dnssec);
+        response.header.id = query.header.id;
+        response.header.flags |= query.header.flags & 0x0100; // echo RD
+        response.questions = query.questions.clone();
+        if query.edns().is_some() {
+            let mut edns = Edns::new(edns::DEFAULT_UDP_SIZE);
+            edns.dnssec_ok = dnssec;
+            response.additionals.push(edns.to_record());
+            response.header.arcount += 1;
+        }
+        response
+    }
+}
+
+// master file entries in order; origin is used until the file sets its own with $ORIGIN
+pub fn parse_records(text: &str, origin: &str) -> Result<Vec<ResourceRecord>, String> {
+    let mut origin = origin.trim_end_matches('.').to_ascii_lowercase();
+    let mut default_ttl: Option<u32> = None;
+    let mut last_owner: Option<String> = None;
+    let mut last_ttl: Option<u32> = None;
+    let mut records = vec![];
+
+    for line in logical_lines(text)? {
+        let at = |e: String| format!("line {}: {}", line.number, e);
+        let mut tokens = line.tokens.iter().peekable();
+
+        if let Some(first) = tokens.peek() {
+            if !line.owner_blank && !first.quoted && first.text.starts_with('$') {
+                let directive = first.text.to_ascii_uppercase();
+                let value = line.tokens.get(1).ok_or_else(|| at(format!("{} needs a value", directive)))?;
+                match directive.as_str() {
+                    "$ORIGIN" => origin = absolute(&value.text, &origin).map_err(at)?,
+                    "$TTL" => default_ttl = Some(parse_ttl(&value.text).map_err(at)?),
+                    _ => return Err(at(format!("unsupported directive {}", directive))),
+                }
+                continue;
+            }
+        }
+
+        let owner = if line.owner_blank {
+            last_owner.clone().ok_or_else(|| at("no previous owner name".to_string()))?
+        } else {
+            let token = tokens.next().ok_or_else(|| at("missing owner".to_string()))?;
+            absolute(&token.text, &origin).map_err(at)?
+        };
+
+        // ttl and class are both optional and may come in either order
+        let mut ttl = None;
+        let mut class = 1;
+        let rtype = loop {
+            let token = tokens.next().ok_or_else(|| at("missing record type".to_string()))?;
+            if token.text.starts_with(|c: char| c.is_ascii_digit()) && ttl.is_none() {
+                ttl = Some(parse_ttl(&token.text).map_err(at)?);
+            } else if let Ok(parsed) = parse_class(&token.text) {
+                class = parsed;
+            } else {
+                break token.text.parse::<RecordType>().map_err(at)?;
+            }
+        };
+
+        let rest: Vec<&str> = tokens.map(|t| t.text.as_str()).collect();
+        let rdata = parse_rdata(rtype, &rest, &origin).map_err(at)?;
+        let ttl = match (ttl, default_ttl, last_ttl, &rdata) {
+            (Some(ttl), _, _, _) => ttl,
+            (None, Some(ttl), _, _) => ttl,
+            (None, None, Some(ttl), _) => ttl,
+            // RFC 1035 falls back to the SOA minimum
+            (None, None, None, RData::SOA { minimum, .. }) => *minimum,
+            _ => return Err(at("no TTL and no $TTL".to_string())),
+        };
+
+        last_owner = Some(owner.clone());
+        last_ttl = Some(ttl);
+        records.push(ResourceRecord { name: owner, rtype, class, ttl, rdata });
+    }
+    Ok(records)
+}
+
+// parses presentation-format rdata for the given type
+pub fn parse_rdata(rtype: RecordType, words: &[&str], origin: &str) -> Result<RData, String> {
+    let need = |count: usize| {
+        if words.len() < count {
+            Err(format!("{} needs {} fields, got {}", rtype, count, words.len()))
+        } else {
+            Ok(())
+        }
+    };
+    let number = |value: &str| value.parse::<u16>().map_err(|_| format!("invalid number: {}", value));
+    let byte = |value: &str| value.parse::<u8>().map_err(|_| format!("invalid number: {}", value));
+
+    // RFC 3597 generic rdata works for every type
+    if words.first() == Some(&"\\#") {
+        need(2)?;
+        let data = from_hex(&words[2..].concat()).ok_or("invalid hex in generic rdata")?;
+        if data.len() != words[1].parse::<usize>().map_err(|_| "invalid generic rdata length")? {
+            return Err("generic rdata length does not match data".to_string());
+        }
+        return Ok(RData::Unknown(data));
+    }
+
+    let rdata = match rtype {
+        RecordType::A => {
+            need(1)?;
+            RData::A(words[0].parse::<Ipv4Addr>().map_err(|_| format!("invalid IPv4 address: {}", words[0]))?)
+        }
+        RecordType::AAAA => {
+            need(1)?;
+            RData::AAAA(words[0].parse::<Ipv6Addr>().map_err(|_| format!("invalid IPv6 address: {}", words[0]))?)
+        }
+        RecordType::NS => {
+            need(1)?;
+            RData::NS(absolute(words[0], origin)?)
+        }
+        RecordType::CNAME => {
+            need(1)?;
+            RData::CNAME(absolute(words[0], origin)?)
+        }
+        RecordType::PTR => {
+            need(1)?;
+            RData::PTR(absolute(words[0], origin)?)
+        }
+        RecordType::MX => {
+            need(2)?;
+            RData::MX { preference: number(words[0])?, exchange: absolute(words[1], origin)? }
+        }
+        RecordType::TXT => {
+            need(1)?;
+            let strings = words.iter().map(|w| unescape(w)).collect::<Result<Vec<_>, _>>()?;
+            if strings.iter().any(|s| s.len() > 255) {
+                return Err("TXT character-string longer than 255 bytes".to_string());
+            }
+            RData::TXT(strings)
+        }
+        RecordType::SOA => {
+            need(7)?;
+            RData::SOA {
+                mname: absolute(words[0], origin)?,
+                rname: absolute(words[1], origin)?,
+                serial: words[2].parse().map_err(|_| format!("invalid serial: {}", words[2]))?,
+                refresh: parse_ttl(words[3])?,
+                retry: parse_ttl(words[4])?,
+                expire: parse_ttl(words[5])?,
+                minimum: parse_ttl(words[6])?,
+            }
+        }
+        RecordType::SRV => {
+            need(4)?;
+            RData::SRV {
+                priority: number(words[0])?,
+                weight: number(words[1])?,
+                port: number(words[2])?,
+                target: absolute(words[3], origin)?,
+            }
+        }
+        RecordType::CAA => {
+            need(3)?;
+            RData::CAA {
+                flags: byte(words[0])?,
+                tag: words[1].to_string(),
+                value: unescape(words[2])?,
+            }
+        }
+        RecordType::DS => {
+            need(4)?;
+            RData::DS {
+                key_tag: number(words[0])?,
+                algorithm: byte(words[1])?,
+                digest_type: byte(words[2])?,
+                digest: from_hex(&words[3..].concat()).ok_or("invalid hex in DS digest")?,
+            }
+        }
+        RecordType::DNSKEY => {
+            need(4)?;
+            RData::DNSKEY {
+                flags: number(words[0])?,
+                protocol: byte(words[1])?,
+                algorithm: byte(words[2])?,
+                public_key: from_base64(&words[3..].concat()).ok_or("invalid base64 in DNSKEY")?,
+            }
+        }
+        RecordType::RRSIG => {
+            need(9)?;
+            let time = |value: &str| parse_time(value).ok_or_else(|| format!("invalid signature time: {}", value));
+            RData::RRSIG {
+                type_covered: words[0].parse()?,
+                algorithm: byte(words[1])?,
+                labels: byte(words[2])?,
+                original_ttl: parse_ttl(words[3])?,
+                expiration: time(words[4])?,
+                inception: time(words[5])?,
+                key_tag: number(words[6])?,
+                signer: absolute(words[7], origin)?,
+                signature: from_base64(&words[8..].concat()).ok_or("invalid base64 in RRSIG")?,
+            }
+        }
+        RecordType::NSEC => {
+            need(1)?;
+            RData::NSEC { next: absolute(words[0], origin)?, types: parse_types(&words[1..])? }
+        }
+        RecordType::NSEC3 => {
+            need(5)?;
+            let salt = match words[3] {
+                "-" => vec![],
+                hex => from_hex(hex).ok_or("invalid hex in NSEC3 salt")?,
+            };
+            RData::NSEC3 {
+                hash_algorithm: byte(words[0])?,
+                flags: byte(words[1])?,
+                iterations: number(words[2])?,
+                salt,
+                next_hashed: from_base32hex(words[4]).ok_or("invalid base32hex in NSEC3")?,
+                types: parse_types(&words[5..])?,
+            }
+        }
+        other => {
+            return Err(format!("{} records need the generic \\# form", other));
+        }
+    };
+    Ok(rdata)
+}
+
+fn parse_types(words: &[&str]) -> Result<Vec<RecordType>, String> {
+    words.iter().map(|w| w.parse::<RecordType>()).collect()
+}
+
+// turns a name from the file into our dotless absolute form
+pub fn absolute(name: &str, origin: &str) -> Result<String, String> {
+    if name.is_empty() {
+        return Err("empty name".to_string());
+    }
+    if name == "@" {
+        return Ok(origin.to_string());
+    }
+    let full = if is_absolute(name) || name == "." || origin.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, origin)
+    };
+    Name::parse(&full).map(|n| n.to_string())
+}
+
+// seconds, or BIND style units: 1w2d3h4m5s
+pub fn parse_ttl(value: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid TTL: {}", value);
+    if let Ok(seconds) = value.parse::<u32>() {
+        return Ok(seconds);
+    }
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit: u64 = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(invalid()),
+        };
+        let amount: u64 = digits.parse().map_err(|_| invalid())?;
+        total += amount * unit;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
+    u32::try_from(total).map_err(|_| invalid())
+}
+
+// resolves \X and \DDD escapes in a character-string
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let digits = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(|b| b.is_ascii_digit()));
+        match digits {
+            Some(digits) => {
+                let value: u16 = std::str::from_utf8(digits).unwrap().parse().unwrap();
+                out.push(u8::try_from(value).map_err(|_| format!("escape out of range in {}", text))?);
+                i += 4;
+            }
+            None => {
+                let escaped = bytes.get(i + 1).ok_or_else(|| format!("dangling escape in {}", text))?;
+                out.push(*escaped);
+                i += 2;
+            }
+        }
+    }
+    Ok(out)
+}
+
+// splits the file into entries, dropping comments and joining parenthesised lines
+fn logical_lines(text: &str) -> Result<Vec<Line>, String> {
+    let mut lines = vec![];
+    let mut current: Option<Line> = None;
+    let mut depth = 0;
+
+    for (index, raw) in text.lines().enumerate() {
+        let number = index + 1;
+        let mut chars = raw.chars().peekable();
+        let mut word = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        let mut words: Vec<Token> = vec![];
+
+        let flush = |word: &mut String, quoted: &mut bool, words: &mut Vec<Token>| {
+            if !word.is_empty() || *quoted {
+                words.push(Token { text: std::mem::take(word), quoted: *quoted });
+            }
+            *quoted = false;
+        };
+
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                match c {
+                    '"' => {
+                        in_quotes = false;
+                        flush(&mut word, &mut quoted, &mut words);
+                    }
+                    '\\' => {
+                        word.push(c);
+                        if let Some(next) = chars.next() {
+                            word.push(next);
+                        }
+                    }
+                    _ => word.push(c),
+                }
+                continue;
+            }
+            match c {
+                ';' => break,
+                '"' => {
+                    flush(&mut word, &mut quoted, &mut words);
+                    in_quotes = true;
+                    quoted = true;
+                }
+                '(' => {
+                    flush(&mut word, &mut quoted, &mut words);
+                    depth += 1;
+                }
+                ')' => {
+                    flush(&mut word, &mut quoted, &mut words);
+                    if depth == 0 {
+                        return Err(format!("line {}: unbalanced ')'", number));
+                    }
+                    depth -= 1;
+                }
+                '\\' => {
+                    word.push(c);
+                    if let Some(next) = chars.next() {
+                        word.push(next);
+                    }
+                }
+                c if c.is_whitespace() => flush(&mut word, &mut quoted, &mut words),
+                _ => word.push(c),
+            }
+        }
+        if in_quotes {
+            return Err(format!("line {}: unterminated quoted string", number));
+        }
+        flush(&mut word, &mut quoted, &mut words);
+
+        match current.as_mut() {
+            Some(line) => line.tokens.extend(words),
+            None => {
+                let owner_blank = raw.starts_with([' ', '\t']);
+                current = Some(Line { number, owner_blank, tokens: words });
+            }
+        }
+        if depth == 0 {
+            if let Some(line) = current.take() {
+                if !line.tokens.is_empty() {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    if depth != 0 {
+        return Err("unbalanced '(' at end of file".to_string());
+    }
+    Ok(lines)
+}
+
+fn is_nsec3(record: &ResourceRecord) -> bool {
+    record.rtype == RecordType::NSEC3 ||
+        matches!(record.rdata, RData::RRSIG { type_covered: RecordType::NSEC3, .. })
+}
+
+// the ancestor of name one label below the encloser
+fn next_closer(name: &str, encloser: &str) -> String {
+    let labels: Vec<&str> = name.split('.').collect();
+    let keep = if encloser.is_empty() { 1 } else { encloser.split('.').count() + 1 };
+    labels[labels.len().saturating_sub(keep)..].join(".")
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::transport::{ self, Client, RetryPolicy };
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    const EXAMPLE: &str = include_str!("../tests/zones/example.com.zone");
+
+    fn zone() -> Zone {
+        Zone::parse(EXAMPLE, "").unwrap()
+    }
+
+    fn ask(name: &str, qtype: RecordType) -> Message {
+        zone().answer(&Question { qname: name.to_string(), qtype, qclass: 1 }, false)
+    }
+
+    #[test]
+    fn test_parse_fixture() {
+        let zone = zone();
+        assert_eq!(zone.origin, "example.com");
+        assert_eq!(zone.records.len(), 16);
+        match &zone.soa().unwrap().rdata {
+            RData::SOA { mname, serial, refresh, retry, expire, minimum, .. } => {
+                assert_eq!(mname, "ns1.example.com");
+                assert_eq!(*serial, 2024010101);
+                assert_eq!((*refresh, *retry, *expire, *minimum), (7200, 1800, 1209600, 300));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let mail = zone.records.iter().find(|r| r.name == "mail.example.com").unwrap();
+        assert_eq!(mail.ttl, 600);
+        let txt = zone.records.iter().find(|r| r.rtype == RecordType::TXT).unwrap();
+        assert_eq!(txt.rdata, RData::TXT(vec![b"v=spf1 -all".to_vec(), b"second string".to_vec(), b"bare".to_vec()]));
+
+        // written back out, it reads in the same
+        assert_eq!(Zone::parse(&zone.to_text(), "").unwrap().records, zone.records);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Zone::parse("www 300 A 192.0.2.1", "example.com").is_err()); // no SOA
+        assert!(Zone::parse("@ 300 SOA a b ( 1 2 3 4 5", "example.com").is_err());
+        assert!(Zone::parse("$TTL 300\n@ SOA a b 1 2 3 4 5\nwww A 999.0.0.1", "example.com").is_err());
+        assert_eq!(parse_ttl("1h30m").unwrap(), 5400);
+        assert_eq!(unescape("a\\\"b\\065").unwrap(), b"a\"bA".to_vec());
+    }
+
+    #[test]
+    fn test_authoritative_answer() {
+        let response = ask("WWW.example.com", RecordType::AAAA);
+        assert!(response.header.flag_names().contains(&"aa"));
+        assert_eq!(response.answers[0].rdata, RData::AAAA("2001:db8::80".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_nxdomain_and_nodata() {
+        let response = ask("missing.example.com", RecordType::A);
+        assert_eq!(response.header.rcode(), 3);
+        assert_eq!(response.authorities[0].rtype, RecordType::SOA);
+        assert_eq!(response.authorities[0].ttl, 300);
+
+        let response = ask("www.example.com", RecordType::MX);
+        assert_eq!(response.header.rcode(), 0);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].rtype, RecordType::SOA);
+
+        // empty non-terminal: NODATA rather than NXDOMAIN
+        let response = ask("empty.example.com", RecordType::A);
+        assert_eq!(response.header.rcode(), 0);
+    }
+
+    #[test]
+    fn test_any() {
+        let response = ask("www.example.com", RecordType::ANY);
+        assert_eq!(response.header.rcode(), 0);
+        let types: Vec<RecordType> = response.answers.iter().map(|r| r.rtype).collect();
+        assert_eq!(types, vec![RecordType::A, RecordType::AAAA]);
+        assert!(response.authorities.is_empty());
+        assert_eq!(ask("missing.example.com", RecordType::ANY).header.rcode(), 3);
+    }
+
+    #[test]
+    fn test_cname_and_wildcard() {
+        let response = ask("ftp.example.com", RecordType::A);
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[1].rdata, RData::A([192, 0, 2, 80].into()));
+
+        let response = ask("anything.wild.example.com", RecordType::A);
+        assert_eq!(response.answers[0].name, "anything.wild.example.com");
+        assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 99].into()));
+        assert_eq!(ask("anything.wild.example.com", RecordType::MX).header.rcode(), 0);
+    }
+
+    #[test]
+    fn test_referral_with_glue() {
+        let response = ask("host.sub.example.com", RecordType::A);
+        assert!(!response.header.flag_names().contains(&"aa"));
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].rdata, RData::NS("ns.sub.example.com".to_string()));
+        assert_eq!(response.additionals[0].rdata, RData::A([192, 0, 2, 200].into()));
+    }
+
+    #[test]
+    fn test_served_over_the_network() {
+        let authority = Arc::new(Authority { zones: vec![zone()] });
+        let server = Server::bind("127.0.0.1:0".parse().unwrap(), authority).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = Client::new(RetryPolicy::new(Duration::from_secs(1), 0));
+        let ask = |client: &mut Client, name: &str| {
+            let question = Question { qname: name.to_string(), qtype: RecordType::MX, qclass: 1 };
+            client.send(&transport::build_query(question, false, None).unwrap(), addr).unwrap()
+        };
+        let response = ask(&mut client, "example.com");
+        assert_eq!(response.answers[0].rdata, RData::MX { preference: 10, exchange: "mail.example.com".to_string() });
+        assert_eq!(ask(&mut client, "example.org").header.rcode(), 5);
+    }
+}